@@ -5,6 +5,7 @@ clear
 APP_DIR="WindUSB.AppDir"
 BIN_DIR="$(pwd)/$APP_DIR/bin-local"
 LIB_DIR="$(pwd)/$APP_DIR/lib-local"
+DATA_DIR="$(pwd)/$APP_DIR/share/windusb"
 BUILD_ROOT="$(pwd)/build_temp"
 APPIMAGE_TOOL="./appimagetool-x86_64.appimage"
 
@@ -17,6 +18,12 @@ URL_UTIL_LINUX="https://mirrors.edge.kernel.org/pub/linux/utils/util-linux/v2.41
 URL_POPT="https://ftp.osuosl.org/pub/blfs/conglomeration/popt/popt-1.19.tar.gz"
 URL_GPTFDISK="https://downloads.sourceforge.net/project/gptfdisk/gptfdisk/1.0.10/gptfdisk-1.0.10.tar.gz"
 URL_PARTED="https://ftp.gnu.org/gnu/parted/parted-3.6.tar.xz"
+URL_MSSYS="https://downloads.sourceforge.net/project/ms-sys/ms-sys%20stable/2.8.0/ms-sys-2.8.0.tar.gz"
+URL_NTFS3G="https://tuxera.com/opensource/ntfs-3g_ntfsprogs-2022.10.3.tgz"
+UEFI_NTFS_TAG="v4.6"
+URL_UEFI_NTFS="https://github.com/pbatard/rufus/raw/${UEFI_NTFS_TAG}/res/uefi/uefi-ntfs.img"
+# SHA-256 of uefi-ntfs.img at $UEFI_NTFS_TAG; update it together with the tag.
+SHA256_UEFI_NTFS=""
 
 export CC="gcc"
 export CXX="g++"
@@ -44,6 +51,10 @@ done
 
 if [ "$CLEAN_START" = true ]; then
     echo "🧹 Performing Deep Build (Full Clean)..."
+    if [ -z "$SHA256_UEFI_NTFS" ]; then
+        echo "❌ SHA256_UEFI_NTFS is not set; refusing to bundle an unverified UEFI:NTFS image." >&2
+        exit 1
+    fi
     mkdir -p "$BIN_DIR" "$LIB_DIR" "$BUILD_ROOT"
     find "$BIN_DIR" -mindepth 1 ! -name ".gitkeep" -delete 2>/dev/null || true
     find "$LIB_DIR" -mindepth 1 ! -name ".gitkeep" -delete 2>/dev/null || true
@@ -83,7 +94,7 @@ if [ "$CLEAN_START" = true ]; then
     echo "📦 Building util-linux..."
     wget -qN "$URL_UTIL_LINUX"
     tar -xf util-linux-2.41.3.tar.gz && cd util-linux-2.41.3
-    ./configure --disable-all-programs --enable-wipefs --enable-lsblk --enable-blockdev \
+    ./configure --disable-all-programs --enable-wipefs --enable-lsblk --enable-blockdev --enable-sfdisk --enable-libfdisk \
                 --enable-libuuid --enable-libblkid --enable-libsmartcols --enable-libmount \
                 --disable-bash-completion --disable-nls --without-python --without-systemd --without-udev \
                 LDFLAGS="-static"
@@ -92,6 +103,7 @@ if [ "$CLEAN_START" = true ]; then
     find . -type f -name wipefs -not -path "*/scripts/*" -exec file {} + | grep "ELF" | cut -d: -f1 | head -n 1 | xargs -I {} cp {} "$BIN_DIR/wipefs"
     find . -type f -name lsblk -not -path "*/scripts/*" -exec file {} + | grep "ELF" | cut -d: -f1 | head -n 1 | xargs -I {} cp {} "$BIN_DIR/lsblk"
     find . -type f -name blockdev -not -path "*/scripts/*" -exec file {} + | grep "ELF" | cut -d: -f1 | head -n 1 | xargs -I {} cp {} "$BIN_DIR/blockdev"
+    find . -type f -name sfdisk -not -path "*/scripts/*" -exec file {} + | grep "ELF" | cut -d: -f1 | head -n 1 | xargs -I {} cp {} "$BIN_DIR/sfdisk"
     
     LOCAL_UUID_DIR=$(pwd)
     cd ..
@@ -111,7 +123,7 @@ if [ "$CLEAN_START" = true ]; then
         "$POPT_LIB" "$LOCAL_UUID_DIR/.libs/libuuid.a" -static -static-libgcc -static-libstdc++ -lpthread -no-pie
     cd ..
 
-    echo "📦 Building partprobe..."
+    echo "📦 Building parted and partprobe..."
     wget -qN "$URL_PARTED"
     tar -xf parted-3.6.tar.xz && cd parted-3.6
     sed -i 's/do_version ()/do_version (PedDevice** dev, PedDisk** diskp)/g' parted/parted.c
@@ -125,8 +137,29 @@ if [ "$CLEAN_START" = true ]; then
     
     make -j$(nproc)
     find . -type f -name partprobe -not -path "*/scripts/*" -exec file {} + | grep "ELF" | cut -d: -f1 | head -n 1 | xargs -I {} cp {} "$BIN_DIR/partprobe"
+    find . -type f -name parted -not -path "*/scripts/*" -exec file {} + | grep "ELF" | cut -d: -f1 | head -n 1 | xargs -I {} cp {} "$BIN_DIR/parted"
     cd ..
 
+    echo "📦 Building ms-sys..."
+    wget -qN "$URL_MSSYS"
+    tar -xf ms-sys-2.8.0.tar.gz && cd ms-sys-2.8.0
+    make -j$(nproc) LDFLAGS="-static"
+    cp bin/ms-sys "$BIN_DIR/ms-sys" && cd ..
+
+    echo "📦 Building ntfs-3g and mkfs.ntfs..."
+    wget -qN "$URL_NTFS3G"
+    tar -xf ntfs-3g_ntfsprogs-2022.10.3.tgz && cd ntfs-3g_ntfsprogs-2022.10.3
+    ./configure --with-fuse=internal --disable-shared --enable-really-static
+    make -j$(nproc)
+    cp src/ntfs-3g "$BIN_DIR/ntfs-3g"
+    cp ntfsprogs/mkntfs "$BIN_DIR/mkfs.ntfs" && cd ..
+
+    echo "📥 Downloading UEFI:NTFS..."
+    mkdir -p "$DATA_DIR"
+    curl -fLo uefi-ntfs.img "$URL_UEFI_NTFS"
+    echo "$SHA256_UEFI_NTFS  uefi-ntfs.img" | sha256sum -c -
+    cp uefi-ntfs.img "$DATA_DIR/uefi-ntfs.img"
+
     cd "$ROOT_DIR"
     chmod 755 "$BIN_DIR"/* || true
     for f in "$BIN_DIR"/*; do
@@ -0,0 +1,352 @@
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::os::unix::io::FromRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use windusb::drives::format_size;
+use windusb::FlashEvent;
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_FLASH_FAILED: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_INVALID_INPUT: i32 = 3;
+pub const EXIT_ABORTED: i32 = 4;
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+const USAGE: &str = "\
+Usage: windusb-gui --cli --drive <DEVICE> --iso <FILE> [--edition <INDEX>...] [--verify-checksum] [--verify] [--yes] [--json | --json-fd <FD>]
+       windusb-gui --cli --list-drives
+       windusb-gui --cli --list-editions --iso <FILE>
+
+Options:
+  --drive <DEVICE>   USB drive to overwrite, e.g. /dev/sdb
+  --iso <FILE>       Windows ISO image to write
+  --edition <INDEX>  Only write this edition (see --list-editions); repeat or comma-separate for several
+  --verify-checksum  Hash the ISO and refuse to flash if it is a corrupted official image
+  --verify           Read the drive back after writing and compare it with the ISO
+  --scheme <SCHEME>  Partition table: gpt (UEFI, default), mbr or hybrid (both also boot from BIOS)
+  --layout <LAYOUT>  File system: fat32 (split install image, default) or ntfs (image kept whole, UEFI:NTFS boot)
+  -y, --yes          Do not ask for confirmation before wiping the drive
+  --json             Print progress as newline-delimited JSON on stdout
+  --json-fd <FD>     Write newline-delimited JSON progress to an inherited file descriptor (3 or above)
+  --list-drives      Print the detected USB drives and exit
+  --list-editions    Print the Windows editions in the ISO's install image and exit
+  -h, --help         Show this help
+
+Exit codes:
+  0 success, 1 flashing failed, 2 usage error, 3 invalid drive or ISO,
+  4 aborted at a prompt or drive still in use, 130 interrupted";
+
+#[derive(Default)]
+struct CliOptions {
+    drive: Option<String>,
+    iso: Option<PathBuf>,
+    editions: Vec<u32>,
+    verify_checksum: bool,
+    verify: bool,
+    scheme: windusb::PartitionScheme,
+    layout: windusb::DriveLayout,
+    assume_yes: bool,
+    list_drives: bool,
+    list_editions: bool,
+    help: bool,
+    json: Option<JsonTarget>,
+}
+
+enum JsonTarget {
+    Stdout,
+    Fd(i32),
+}
+
+pub fn is_cli(args: &[String]) -> bool {
+    args.iter().skip(1).any(|a| a == "--cli")
+}
+
+fn parse_args(args: &[String]) -> Result<CliOptions, String> {
+    let mut opts = CliOptions::default();
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--cli" => {}
+            "--drive" => opts.drive = Some(iter.next().ok_or("--drive needs a device path")?.clone()),
+            "--iso" => opts.iso = Some(PathBuf::from(iter.next().ok_or("--iso needs a file path")?)),
+            "--edition" => {
+                let list = iter.next().ok_or("--edition needs an image index")?;
+                for index in list.split(',') {
+                    opts.editions.push(index.trim().parse().map_err(|_| format!("Invalid edition index: {}", index))?);
+                }
+            }
+            "--verify-checksum" => opts.verify_checksum = true,
+            "--verify" => opts.verify = true,
+            "--scheme" => {
+                let name = iter.next().ok_or("--scheme needs gpt, mbr or hybrid")?;
+                opts.scheme = windusb::PartitionScheme::from_name(name).ok_or_else(|| format!("Unknown partition scheme: {}", name))?;
+            }
+            "--layout" => {
+                let name = iter.next().ok_or("--layout needs fat32 or ntfs")?;
+                opts.layout = windusb::DriveLayout::from_name(name).ok_or_else(|| format!("Unknown drive layout: {}", name))?;
+            }
+            "-y" | "--yes" => opts.assume_yes = true,
+            "--json" => opts.json = Some(JsonTarget::Stdout),
+            "--json-fd" => {
+                let fd = iter.next().ok_or("--json-fd needs a file descriptor")?;
+                let fd = fd.parse::<i32>().ok().filter(|fd| *fd >= 0).ok_or_else(|| format!("Invalid file descriptor: {}", fd))?;
+                // The descriptor is owned and closed once the run ends, which must not happen to stdin, stdout or stderr.
+                if fd <= 2 {
+                    return Err(format!("--json-fd {} is a standard stream; use --json to write JSON to stdout", fd));
+                }
+                // File::from_raw_fd must only ever be handed an open descriptor.
+                if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+                    return Err(format!("File descriptor {} is not open", fd));
+                }
+                opts.json = Some(JsonTarget::Fd(fd));
+            }
+            "--list-drives" => opts.list_drives = true,
+            "--list-editions" => opts.list_editions = true,
+            "-h" | "--help" => opts.help = true,
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+    Ok(opts)
+}
+
+/// Cancel flag of the running flash, for the signal handler.
+static FLASH: Mutex<Option<Arc<AtomicBool>>> = Mutex::new(None);
+
+/// Signal handler for CLI mode. The first signal cancels the flash so its
+/// mounts are released and the drive erased; a second one, or one outside a
+/// flash, detaches our mounts and exits straight away.
+pub fn interrupt() {
+    let flash = FLASH.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+    if let Some(cancel) = flash {
+        eprintln!("\nInterrupted, cleaning up...");
+        cancel.store(true, Ordering::Relaxed);
+        return;
+    }
+    windusb::mounts::release_all();
+    eprintln!("\nInterrupted.");
+    std::process::exit(EXIT_INTERRUPTED);
+}
+
+fn confirm(drive: &str) -> bool {
+    eprint!("WARNING: ALL DATA on {} will be DELETED. Type 'yes' to proceed: ", drive);
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    answer.trim().eq_ignore_ascii_case("yes")
+}
+
+/// Asks whether to retry once the programs holding the drive are closed.
+fn retry() -> bool {
+    eprint!("Close them and press Enter to retry, or type 'abort': ");
+    let _ = io::stderr().flush();
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(0) | Err(_) => false,
+        Ok(_) => answer.trim() != "abort",
+    }
+}
+
+fn describe(hazards: &[windusb::Hazard]) -> String {
+    hazards.iter().map(|h| h.to_string()).collect::<Vec<_>>().join("; ")
+}
+
+/// Hashes the ISO and compares it with the catalogue; returns `false` on a mismatch.
+fn verify_checksum(iso: &Path) -> bool {
+    let cancel = AtomicBool::new(false);
+    let mut last = None;
+    let result = windusb::checksum::hash_file(iso, &cancel, |hashed, total| {
+        let percent = (hashed * 100).checked_div(total).unwrap_or(100);
+        if last != Some(percent) {
+            eprint!("\rVerifying checksum: {:>3}%", percent);
+            last = Some(percent);
+        }
+    });
+    eprintln!();
+    let hashes = match result {
+        Ok(hashes) => hashes,
+        Err(err) => {
+            eprintln!("Warning: cannot hash {}: {}.", iso.display(), err);
+            return true;
+        }
+    };
+    let name = iso.file_name().unwrap_or_default().to_string_lossy();
+    match windusb::Catalogue::load().check(&name, &hashes) {
+        verdict @ windusb::Verdict::Verified(_) => eprintln!("{}.", capitalize(&verdict.to_string())),
+        verdict @ windusb::Verdict::Unknown => eprintln!("Warning: {} (SHA-256 {}).", verdict, hashes.sha256),
+        verdict @ windusb::Verdict::Mismatch(_) => {
+            eprintln!("Error: {} (SHA-256 {}).", verdict, hashes.sha256);
+            return false;
+        }
+    }
+    true
+}
+
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+pub fn run(args: &[String]) -> i32 {
+    let opts = match parse_args(args) {
+        Ok(opts) => opts,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            return EXIT_USAGE;
+        }
+    };
+    if opts.help {
+        println!("{}", USAGE);
+        return EXIT_OK;
+    }
+
+    if opts.list_editions {
+        let Some(iso) = opts.iso else {
+            eprintln!("--list-editions needs --iso.\n\n{}", USAGE);
+            return EXIT_USAGE;
+        };
+        let Some(editions) = windusb::list_editions(&iso) else {
+            eprintln!("Error: cannot read the Windows editions in {}.", iso.display());
+            return EXIT_INVALID_INPUT;
+        };
+        for edition in &editions {
+            println!("{}\t{}\t{}", edition.index, edition.name, edition.summary());
+        }
+        return EXIT_OK;
+    }
+
+    let drives = windusb::list_usb_drives();
+    if opts.list_drives {
+        for drive in &drives {
+            let hazards = windusb::drive_hazards(&drive.path);
+            if hazards.is_empty() {
+                println!("{}\t{}", drive.path, drive.description);
+            } else {
+                println!("{}\t{}\t[locked: {}]", drive.path, drive.description, describe(&hazards));
+            }
+        }
+        return EXIT_OK;
+    }
+
+    let (drive, iso) = match (opts.drive, opts.iso) {
+        (Some(drive), Some(iso)) => (drive, iso),
+        _ => {
+            eprintln!("Both --drive and --iso are required.\n\n{}", USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    if unsafe { libc::getuid() } != 0 {
+        eprintln!("Error: flashing requires root privileges.");
+        return EXIT_INVALID_INPUT;
+    }
+    if !drives.iter().any(|d| d.path == drive) {
+        eprintln!("Error: {} is not a detected USB drive (see --list-drives).", drive);
+        return EXIT_INVALID_INPUT;
+    }
+    let hazards = windusb::drive_hazards(&drive);
+    if !hazards.is_empty() {
+        eprintln!("Error: refusing to erase {}: {}.", drive, describe(&hazards));
+        return EXIT_INVALID_INPUT;
+    }
+    if !windusb::is_valid_windows_iso(&iso) {
+        eprintln!("Error: {} is not a Windows ISO (missing install.wim/esd).", iso.display());
+        return EXIT_INVALID_INPUT;
+    }
+    if let Some(platform) = windusb::detect_platform(&iso) {
+        let summary = platform.summary();
+        if !summary.is_empty() {
+            eprintln!("Detected {}.", summary);
+        }
+        for warning in platform.warnings(opts.scheme) {
+            eprintln!("Warning: {}.", warning);
+        }
+    }
+    if let Some(editions) = windusb::list_editions(&iso).filter(|_| !opts.editions.is_empty()) {
+        if let Some(index) = opts.editions.iter().find(|index| !editions.iter().any(|e| e.index == **index)) {
+            eprintln!("Error: {} has no edition with index {} (see --list-editions).", iso.display(), index);
+            return EXIT_INVALID_INPUT;
+        }
+    }
+    if opts.verify_checksum && !verify_checksum(&iso) {
+        return EXIT_INVALID_INPUT;
+    }
+    if let Some(space) = windusb::check_iso_fits(&iso, &drive, &opts.editions, opts.layout) {
+        let (required, available) = (format_size(space.required), format_size(space.available));
+        if !space.fits() {
+            eprintln!("Error: {} is too small: the ISO needs {}, the drive holds {}.", drive, required, available);
+            return EXIT_INVALID_INPUT;
+        }
+        if space.is_tight() {
+            eprintln!("Warning: the ISO needs {} of the {} on {}; little space will be left.", required, available, drive);
+        }
+    }
+    if !opts.assume_yes && !confirm(&drive) {
+        eprintln!("Aborted.");
+        return EXIT_ABORTED;
+    }
+    while let Err(busy) = windusb::release_drive(&drive) {
+        eprintln!("{} is still in use:", drive);
+        for mount in &busy {
+            eprintln!("  {}", mount);
+        }
+        if opts.assume_yes || !retry() {
+            eprintln!("Aborted.");
+            return EXIT_ABORTED;
+        }
+    }
+
+    let mut json_out: Option<Box<dyn Write>> = match opts.json {
+        Some(JsonTarget::Stdout) => Some(Box::new(io::stdout())),
+        Some(JsonTarget::Fd(fd)) => Some(Box::new(unsafe { File::from_raw_fd(fd) })),
+        None => None,
+    };
+    let human_stdout = !matches!(opts.json, Some(JsonTarget::Stdout));
+
+    windusb::mounts::sweep_stale(&windusb::SystemRunner);
+    let cancel = Arc::new(AtomicBool::new(false));
+    *FLASH.lock().unwrap() = Some(cancel.clone());
+    let (tx, rx) = mpsc::channel::<FlashEvent>();
+    let (editions, verify, scheme, layout) = (opts.editions, opts.verify, opts.scheme, opts.layout);
+    let worker = thread::spawn(move || windusb::Flasher::new(drive, iso).editions(editions).verify(verify).scheme(scheme).layout(layout).cancel(cancel).run(tx));
+
+    let mut last_line = None;
+    for event in rx {
+        if let Some(out) = json_out.as_mut() {
+            if let Ok(line) = serde_json::to_string(&event) {
+                let _ = writeln!(out, "{}", line);
+                let _ = out.flush();
+            }
+        }
+        if !human_stdout {
+            if let FlashEvent::Failed { message, .. } = &event {
+                eprintln!("Error: {}", message);
+            }
+            continue;
+        }
+        match event {
+            FlashEvent::Progress(progress) => {
+                let percent = (progress.fraction * 100.0).floor() as u32;
+                if last_line != Some((progress.phase, percent)) {
+                    println!("[{:>3}%] {}", percent, progress.message);
+                    last_line = Some((progress.phase, percent));
+                }
+            }
+            FlashEvent::Finished => {
+                println!("[100%] Installation finished. You can now safely unplug the drive.");
+            }
+            FlashEvent::Failed { message, .. } => eprintln!("Error: {}", message),
+        }
+    }
+
+    FLASH.lock().unwrap().take();
+    match worker.join() {
+        Ok(Ok(())) => EXIT_OK,
+        Ok(Err(windusb::FlashError::Cancelled)) => EXIT_INTERRUPTED,
+        _ => EXIT_FLASH_FAILED,
+    }
+}
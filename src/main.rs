@@ -3,290 +3,80 @@ use gtk4::glib;
 use std::env;
 use std::os::unix::process::CommandExt;
 use std::process::Command;
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::{Arc, Mutex};
-use std::path::{PathBuf, Path};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::PathBuf;
 use std::thread;
 use std::sync::mpsc;
+use windusb::FlashEvent;
+
+mod cli;
 
 struct AppState {
     drive: Option<String>,
     iso: Option<PathBuf>,
+    /// Indexes of the editions to write; `None` writes the whole install image.
+    editions: Option<Vec<u32>>,
+    /// Cancels the checksum still running for the previously selected ISO.
+    checksum: Option<Arc<AtomicBool>>,
+    /// Official image the ISO is named after but does not match; flashing it needs confirming.
+    mismatch: Option<String>,
+    /// Read the drive back after writing and compare it with the ISO.
+    verify: bool,
+    scheme: windusb::PartitionScheme,
+    layout: windusb::DriveLayout,
+    /// Cancels the flash while one is running.
+    flash: Option<Arc<AtomicBool>>,
+    /// Bytes the running flash is expected to write, measured before it started.
+    expected: Option<u64>,
+    /// Quit once the cancelled flash has cleaned up.
+    closing: bool,
 }
 
-enum ProgressMsg {
-    Update(String, f64),
-    Finished,
-    Error(String),
-}
-
-fn get_local_bin(bin_name: &str) -> String {
-    if let Ok(appdir) = std::env::var("APPDIR") {
-        let local_path = format!("{}/bin-local/{}", appdir, bin_name);
-        if std::path::Path::new(&local_path).exists() {
-            return local_path;
+/// Asks the running flash to stop its tools, release its mounts and erase
+/// the half-written drive; returns `false` if no flash is running.
+fn cancel_flash(state: &Arc<Mutex<AppState>>) -> bool {
+    match &state.lock().unwrap().flash {
+        Some(cancel) => {
+            cancel.store(true, Ordering::Relaxed);
+            true
         }
+        None => false,
     }
-    bin_name.to_string()
-}
-
-fn cleanup_processes() {
-    let pgid = unsafe { libc::getpgrp() };
-    thread::spawn(move || {
-        let _ = Command::new("pkill").args(["-9", "wimlib-imagex"]).status();
-        let _ = Command::new("pkill").args(["-9", "7z"]).status();
-        let _ = Command::new("sh")
-        .args(["-c", "umount -l /tmp/windusb_* 2>/dev/null"])
-        .status();
-        unsafe {
-            libc::kill(-pgid, libc::SIGKILL);
-        }
-    });
-    std::process::exit(0);
 }
 
 fn main() {
-    unsafe {
-        libc::setpgid(0, 0);
+    let args: Vec<String> = env::args().collect();
+    if cli::is_cli(&args) {
+        ctrlc::set_handler(cli::interrupt).expect("Error setting Ctrl-C handler");
+        std::process::exit(cli::run(&args));
     }
     env::set_var("GSETTINGS_BACKEND", "memory");
     env::set_var("GTK_USE_PORTAL", "1");
     env::set_var("GIO_USE_VFS", "local");
-    ctrlc::set_handler(move || {
-        cleanup_processes();
-    }).expect("Error setting Ctrl-C handler");
     let app = libadwaita::Application::builder()
     .application_id("io.github.windusb")
     .build();
-    app.connect_activate(build_ui);
-    app.run();
-}
-
-fn is_valid_windows_iso(path: &Path) -> bool {
-    let z_bin = get_local_bin("7z");
-    let output = Command::new(z_bin)
-    .args(["l", &path.to_string_lossy()])
-    .output();
-    if let Ok(out) = output {
-        let stdout = String::from_utf8_lossy(&out.stdout).to_lowercase();
-        stdout.contains("sources/install.wim") || stdout.contains("sources/install.esd")
-    } else {
-        false
-    }
-}
-
-fn get_system_dirty_bytes() -> f64 {
-    let mut total_kb = 0.0;
-    if let Ok(content) = std::fs::read_to_string("/proc/meminfo") {
-        for line in content.lines() {
-            if line.starts_with("Dirty:") || line.starts_with("Writeback:") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    if let Ok(kb) = parts[1].parse::<f64>() {
-                        total_kb += kb;
-                    }
-                }
-            }
-        }
-    }
-    total_kb * 1024.0
-}
-
-fn device_exists(drive: &str) -> bool {
-    Path::new(drive).exists()
-}
-
-fn run_flasher(drive: String, iso: PathBuf, tx: mpsc::Sender<ProgressMsg>) {
-    let usb_mt = format!("/tmp/windusb_usb_{}", unsafe { libc::rand() });
-    let iso_mt = format!("/tmp/windusb_iso_{}", unsafe { libc::rand() });
-    let _ = Command::new("mkdir").args(["-p", &usb_mt, &iso_mt]).status();
-
-    let z_bin = get_local_bin("7z");
-
-    let list_output = Command::new(&z_bin).args(["l", &iso.to_string_lossy()]).output().ok();
-    let mut install_file = String::new();
-    let mut extension = String::new();
-    if let Some(out) = list_output {
-        let stdout = String::from_utf8_lossy(&out.stdout);
-        if stdout.contains("sources/install.wim") {
-            install_file = "sources/install.wim".to_string();
-            extension = "swm".to_string();
-        } else if stdout.contains("sources/install.esd") {
-            install_file = "sources/install.esd".to_string();
-            extension = "esd".to_string();
-        }
-    }
-
-    if install_file.is_empty() {
-        let _ = tx.send(ProgressMsg::Error("Invalid ISO: install.wim/esd not found".into()));
-        return;
-    }
-
-    let _ = tx.send(ProgressMsg::Update(format!("Formatting drive {}...", drive), 0.02));
-    let _ = Command::new("sh").args(["-c", &format!("umount -l {}* 2>/dev/null", drive)]).status();
-
-    if !device_exists(&drive) {
-        let _ = tx.send(ProgressMsg::Error("Drive disconnected before formatting".into()));
-        return;
-    }
-
-    let _ = Command::new(get_local_bin("blockdev")).args(["--flushbufs", &drive]).status();
-    let _ = Command::new(get_local_bin("wipefs")).args(["-af", &drive]).status();
-    let _ = Command::new(get_local_bin("sgdisk")).args(["-Z", &drive]).status();
-    let _ = Command::new(get_local_bin("sgdisk")).args(["-n=1:0:0", "-t=1:0700", &drive]).status();
-    let _ = Command::new(get_local_bin("partprobe")).arg(&drive).status();
-    thread::sleep(std::time::Duration::from_secs(2));
-
-    let part = if drive.contains("nvme") { format!("{}p1", drive) } else { format!("{}1", drive) };
-    if Command::new(get_local_bin("mkfs.fat")).args(["-F32", "-I", &part]).status().is_err() {
-        let _ = tx.send(ProgressMsg::Error("Formatting failed. Drive may have been removed.".into()));
-        return;
-    }
-
-    if Command::new("mount").args([&part, &usb_mt]).status().is_err() {
-        let _ = tx.send(ProgressMsg::Error("Failed to mount USB drive.".into()));
-        return;
-    }
-    let _ = Command::new("mount").args(["-o", "loop,ro", &iso.to_string_lossy(), &iso_mt]).status();
-
-    let install_full_path = format!("{}/{}", iso_mt, install_file);
-    let wim_size = match std::fs::metadata(&install_full_path) {
-        Ok(m) => m.len() as f64,
-        Err(_) => 4_000_000_000.0,
-    };
-
-    let is_active = Arc::new(Mutex::new(true));
-    let phase = Arc::new(Mutex::new(1));
-
-    let is_active_t = is_active.clone();
-    let phase_t = phase.clone();
-    let tx_t = tx.clone();
-    let usb_mt_t = usb_mt.clone();
-    let drive_t = drive.clone();
-
-    thread::spawn(move || {
-        let total_wim_mb = wim_size / 1024.0 / 1024.0;
-        let mut baseline_size = 0.0;
-
-        while *is_active_t.lock().unwrap() {
-            if !device_exists(&drive_t) { break; }
-
-            let current_phase = *phase_t.lock().unwrap();
-            let output = Command::new("du").args(["-sb", &usb_mt_t]).output();
-
-            if let Ok(out) = output {
-                let stdout = String::from_utf8_lossy(&out.stdout);
-                if let Some(size_str) = stdout.split_whitespace().next() {
-                    if let Ok(current_bytes) = size_str.parse::<f64>() {
-                        if current_phase == 1 {
-                            let dirty = get_system_dirty_bytes();
-                            let actual = (current_bytes - dirty).max(0.0);
-                            let progress = 0.05 + ((actual / 500_000_000.0).min(1.0) * 0.20);
-                            let _ = tx_t.send(ProgressMsg::Update(
-                                "Extracting boot files...".to_string(),
-                                                                  progress
-                            ));
-                            baseline_size = current_bytes;
-                        } else {
-                            let wim_progress_bytes = (current_bytes - baseline_size).max(0.0);
-                            let mb = wim_progress_bytes / 1024.0 / 1024.0;
-                            let progress = 0.25 + ((wim_progress_bytes / wim_size).min(1.0) * 0.55);
-                            let _ = tx_t.send(ProgressMsg::Update(
-                                format!("Splitting install.wim: {:.0} / {:.0} MB", mb, total_wim_mb),
-                                    progress
-                            ));
-                        }
-                    }
-                }
-            }
-            thread::sleep(std::time::Duration::from_millis(500));
-        }
-    });
-
-    let status_7z = Command::new(&z_bin)
-    .args(["x", &iso.to_string_lossy(), &format!("-o{}", usb_mt), &format!("-xr!{}", install_file.split('/').last().unwrap()), "-y"])
-    .status();
-
-    if status_7z.is_err() || !status_7z.unwrap().success() || !device_exists(&drive) {
-        *is_active.lock().unwrap() = false;
-        let _ = tx.send(ProgressMsg::Error("Drive removed or 7z error during extraction.".into()));
-        return;
-    }
-
-    {
-        let mut p = phase.lock().unwrap();
-        *p = 2;
-    }
-
-    let dst_path = format!("{}/sources/install.{}", usb_mt, extension);
-    let status_wim = Command::new(get_local_bin("wimlib-imagex"))
-    .args(["split", &install_full_path, &dst_path, "3400"])
-    .status();
-
-    if status_wim.is_err() || !status_wim.unwrap().success() || !device_exists(&drive) {
-        *is_active.lock().unwrap() = false;
-        let _ = tx.send(ProgressMsg::Error("Drive removed or wimlib error during split.".into()));
-        return;
-    }
-
-    *is_active.lock().unwrap() = false;
-
-    let initial_dirty = get_system_dirty_bytes().max(1.0);
-    let usb_mt_c = usb_mt.clone();
-    let iso_mt_c = iso_mt.clone();
-    let unmount_done = Arc::new(Mutex::new(false));
-    let unmount_error = Arc::new(Mutex::new(None));
-    let unmount_done_t = unmount_done.clone();
-    let unmount_err_t = unmount_error.clone();
-    let drive_check = drive.clone();
-
-    thread::spawn(move || {
-        let s1 = Command::new("sync").status();
-        let s2 = Command::new("umount").arg("-l").arg(&usb_mt_c).status();
-        let _ = Command::new("umount").arg("-l").arg(&iso_mt_c).status();
-
-        if s1.is_err() || s2.is_err() || !Path::new(&drive_check).exists() {
-            let mut err = unmount_err_t.lock().unwrap();
-            *err = Some("Sync failed. Drive was likely unplugged.".to_string());
-        }
-        let mut done = unmount_done_t.lock().unwrap();
-        *done = true;
-    });
-
-    let mut spin_idx = 0;
-    let spinners = vec!["-", "\\", "|", "/"];
-    loop {
-        if *unmount_done.lock().unwrap() { break; }
-
-        if let Some(err_msg) = unmount_error.lock().unwrap().clone() {
-            let _ = tx.send(ProgressMsg::Error(err_msg));
-            return;
-        }
-
-        if !device_exists(&drive) {
-            let _ = tx.send(ProgressMsg::Error("Drive disconnected during final sync.".into()));
-            return;
-        }
-
-        let current_dirty = get_system_dirty_bytes();
-        let sync_progress = 0.80 + ((1.0 - (current_dirty / initial_dirty)) * 0.19);
-
-        if current_dirty <= 10.0 * 1024.0 * 1024.0 {
-            spin_idx = (spin_idx + 1) % 4;
-            let _ = tx.send(ProgressMsg::Update(format!("Finishing writes... {}", spinners[spin_idx]), 0.99));
-        } else {
-            let mb_left = current_dirty / 1024.0 / 1024.0;
-            let _ = tx.send(ProgressMsg::Update(format!("Flushing cache: {:.1} MB left", mb_left), sync_progress.min(0.99)));
+    let state = Arc::new(Mutex::new(AppState { drive: None, iso: None, editions: None, checksum: None, mismatch: None, verify: false, scheme: windusb::PartitionScheme::Gpt, layout: windusb::DriveLayout::Fat32, flash: None, expected: None, closing: false }));
+    let s_int = state.clone();
+    ctrlc::set_handler(move || {
+        let already_closing = std::mem::replace(&mut s_int.lock().unwrap().closing, true);
+        if already_closing || !cancel_flash(&s_int) {
+            windusb::mounts::release_all();
+            std::process::exit(0);
         }
-        thread::sleep(std::time::Duration::from_millis(200));
-    }
-
-    let _ = tx.send(ProgressMsg::Finished);
+    }).expect("Error setting Ctrl-C handler");
+    app.connect_activate(move |app| build_ui(app, state.clone()));
+    app.run();
 }
 
-fn build_ui(app: &libadwaita::Application) {
+fn build_ui(app: &libadwaita::Application, state: Arc<Mutex<AppState>>) {
     if unsafe { libc::getuid() } != 0 {
         escalate_privileges();
     }
+    windusb::mounts::sweep_stale(&windusb::SystemRunner);
     let provider = gtk4::CssProvider::new();
     provider.load_from_data("
     button { border-radius: 99px; padding-left: 24px; padding-right: 24px; min-height: 38px; }
@@ -314,7 +104,6 @@ background-color: rgba(145, 65, 172, 0.1);
     );
     let style_manager = libadwaita::StyleManager::default();
     style_manager.set_color_scheme(libadwaita::ColorScheme::PreferDark);
-    let state = Arc::new(Mutex::new(AppState { drive: None, iso: None }));
     let window = libadwaita::ApplicationWindow::builder()
     .application(app)
     .title("WindUSB Creator")
@@ -322,8 +111,12 @@ background-color: rgba(145, 65, 172, 0.1);
     .default_height(380)
     .resizable(false)
     .build();
-    window.connect_close_request(|_| {
-        cleanup_processes();
+    let s_close = state.clone();
+    window.connect_close_request(move |_| {
+        if cancel_flash(&s_close) {
+            s_close.lock().unwrap().closing = true;
+            return glib::Propagation::Stop;
+        }
         glib::Propagation::Proceed
     });
     let root_box = gtk4::Box::new(gtk4::Orientation::Vertical, 0);
@@ -348,36 +141,80 @@ background-color: rgba(145, 65, 172, 0.1);
     .valign(gtk4::Align::Center)
     .build();
     percent_label.add_css_class("caption");
+    let details_label = gtk4::Label::new(None);
+    details_label.add_css_class("caption");
+    details_label.add_css_class("dim-label");
+    let phases_label = gtk4::Label::builder()
+    .justify(gtk4::Justification::Center)
+    .build();
+    phases_label.add_css_class("caption");
+    phases_label.add_css_class("dim-label");
     let finish_btn = gtk4::Button::with_label("Finish & Exit");
     finish_btn.add_css_class("suggested-action");
     finish_btn.set_visible(false);
-    finish_btn.connect_clicked(|_| { cleanup_processes(); });
+    finish_btn.connect_clicked(|_| { std::process::exit(0); });
     let cancel_btn = gtk4::Button::with_label("Cancel");
     cancel_btn.add_css_class("destructive-action");
-    cancel_btn.connect_clicked(|_| { cleanup_processes(); });
-    let (sender, receiver) = mpsc::channel::<ProgressMsg>();
+    let s_cancel = state.clone();
+    let st_cancel = status_label.clone();
+    cancel_btn.connect_clicked(move |btn| {
+        if cancel_flash(&s_cancel) {
+            btn.set_sensitive(false);
+            st_cancel.set_text("Cancelling...");
+        }
+    });
+    let (sender, receiver) = mpsc::channel::<FlashEvent>();
     let st_c = status_label.clone();
     let pb_c = progress_bar.clone();
     let fb_c = finish_btn.clone();
     let cb_c = cancel_btn.clone();
     let pl_c = percent_label.clone();
+    let dl_c = details_label.clone();
+    let ph_c = phases_label.clone();
+    let s_stats = state.clone();
+    let st_stack = stack.clone();
+    let mut stats: Option<windusb::FlashStats> = None;
     glib::timeout_add_local(std::time::Duration::from_millis(50), move || {
         while let Ok(msg) = receiver.try_recv() {
+            if !matches!(msg, FlashEvent::Progress(_)) {
+                let mut s = s_stats.lock().unwrap();
+                s.flash = None;
+                if s.closing {
+                    std::process::exit(0);
+                }
+            }
             match msg {
-                ProgressMsg::Update(text, fraction) => {
-                    st_c.set_text(&text);
-                    pb_c.set_fraction(fraction);
-                    let p = (fraction * 100.0).floor() as u32;
+                FlashEvent::Progress(progress) => {
+                    let stats = stats.get_or_insert_with(|| {
+                        let s = s_stats.lock().unwrap();
+                        windusb::FlashStats::new(s.drive.as_deref().unwrap_or_default(), s.expected)
+                    });
+                    stats.observe(&progress);
+                    if cb_c.is_sensitive() { st_c.set_text(&progress.message); }
+                    pb_c.set_fraction(progress.fraction);
+                    let p = (progress.fraction * 100.0).floor() as u32;
                     pl_c.set_text(&format!("{}%", p));
                 }
-                ProgressMsg::Finished => {
+                FlashEvent::Finished => {
+                    if let Some(stats) = stats.as_mut() { stats.finish(); }
                     st_c.set_text("Installation Finished! You can now safely unplug the drive.");
                     pb_c.set_visible(false);
                     pl_c.set_visible(false);
                     cb_c.set_visible(false);
                     fb_c.set_visible(true);
                 }
-                ProgressMsg::Error(err) => {
+                FlashEvent::Failed { code: windusb::FlashError::CANCELLED, .. } => {
+                    stats = None;
+                    st_c.set_text("Waiting...");
+                    pb_c.set_fraction(0.0);
+                    pl_c.set_text("0%");
+                    dl_c.set_text("");
+                    ph_c.set_text("");
+                    cb_c.set_sensitive(true);
+                    st_stack.set_visible_child_name("drive");
+                }
+                FlashEvent::Failed { message: err, .. } => {
+                    if let Some(stats) = stats.as_mut() { stats.finish(); }
                     st_c.set_text(&format!("Error: {}", err));
                     pb_c.add_css_class("error");
                     pl_c.set_visible(false);
@@ -387,11 +224,15 @@ background-color: rgba(145, 65, 172, 0.1);
                 }
             }
         }
+        if let Some(stats) = stats.as_mut() {
+            stats.sample();
+            show_stats(&dl_c, &ph_c, stats);
+        }
         glib::ControlFlow::Continue
     });
     let drive_page = build_drive_page(&stack, state.clone());
     let iso_page = build_iso_page(&stack, state.clone(), sender);
-    let prog_page = build_progress_page(status_label, progress_bar, percent_label, finish_btn, cancel_btn);
+    let prog_page = build_progress_page(status_label, progress_bar, percent_label, details_label, phases_label, finish_btn, cancel_btn);
     stack.add_named(&drive_page, Some("drive"));
     stack.add_named(&iso_page, Some("iso"));
     stack.add_named(&prog_page, Some("progress"));
@@ -430,6 +271,27 @@ fn build_drive_page(stack: &gtk4::Stack, state: Arc<Mutex<AppState>>) -> gtk4::B
         refresh_drives(&lb_ref);
         nb_ref.set_sensitive(false);
     });
+    let (hotplug_tx, hotplug_rx) = mpsc::channel::<()>();
+    if windusb::hotplug::watch_drives(hotplug_tx).is_ok() {
+        let lb_hp = list_box.clone();
+        let nb_hp = next_btn.clone();
+        let s_hp = state.clone();
+        glib::timeout_add_local(std::time::Duration::from_millis(250), move || {
+            let mut changed = false;
+            while hotplug_rx.try_recv().is_ok() { changed = true; }
+            if changed {
+                let selected = s_hp.lock().unwrap().drive.clone();
+                refresh_drives(&lb_hp);
+                if let Some(drive) = selected {
+                    if !select_drive_row(&lb_hp, &drive) {
+                        s_hp.lock().unwrap().drive = None;
+                        nb_hp.set_sensitive(false);
+                    }
+                }
+            }
+            glib::ControlFlow::Continue
+        });
+    }
     let nb_c = next_btn.clone();
     let s_c = state.clone();
     list_box.connect_row_selected(move |_, row| {
@@ -444,7 +306,7 @@ fn build_drive_page(stack: &gtk4::Stack, state: Arc<Mutex<AppState>>) -> gtk4::B
     box_
 }
 
-fn build_iso_page(stack: &gtk4::Stack, state: Arc<Mutex<AppState>>, sender: mpsc::Sender<ProgressMsg>) -> gtk4::Box {
+fn build_iso_page(stack: &gtk4::Stack, state: Arc<Mutex<AppState>>, sender: mpsc::Sender<FlashEvent>) -> gtk4::Box {
     let box_ = gtk4::Box::new(gtk4::Orientation::Vertical, 16);
     let label = gtk4::Label::new(Some("Select Windows ISO"));
     label.add_css_class("title-4");
@@ -459,6 +321,73 @@ fn build_iso_page(stack: &gtk4::Stack, state: Arc<Mutex<AppState>>, sender: mpsc
     let folder_icon = gtk4::Image::from_icon_name("folder-open-symbolic");
     iso_row.add_prefix(&folder_icon);
     list_box.append(&iso_row);
+    let platform_icon = gtk4::Image::from_icon_name("computer-symbolic");
+    let platform_row = libadwaita::ActionRow::builder()
+    .subtitle_lines(3)
+    .visible(false)
+    .build();
+    platform_row.add_prefix(&platform_icon);
+    list_box.append(&platform_row);
+    let checksum_icon = gtk4::Image::from_icon_name("content-loading-symbolic");
+    let checksum_row = libadwaita::ActionRow::builder()
+    .subtitle_lines(2)
+    .visible(false)
+    .build();
+    checksum_row.add_prefix(&checksum_icon);
+    list_box.append(&checksum_row);
+    let editions_list = gtk4::ListBox::new();
+    editions_list.add_css_class("boxed-list");
+    editions_list.set_selection_mode(gtk4::SelectionMode::None);
+    let editions_scroll = gtk4::ScrolledWindow::builder()
+    .child(&editions_list)
+    .hscrollbar_policy(gtk4::PolicyType::Never)
+    .max_content_height(160)
+    .propagate_natural_height(true)
+    .visible(false)
+    .build();
+    let verify_switch = gtk4::Switch::builder()
+    .valign(gtk4::Align::Center)
+    .build();
+    let verify_row = libadwaita::ActionRow::builder()
+    .title("Verify after writing")
+    .subtitle("Read the drive back and compare every file with the ISO")
+    .activatable_widget(&verify_switch)
+    .build();
+    verify_row.add_suffix(&verify_switch);
+    list_box.append(&verify_row);
+    let s_v = state.clone();
+    verify_switch.connect_active_notify(move |switch| { s_v.lock().unwrap().verify = switch.is_active(); });
+    let schemes: Vec<String> = windusb::PartitionScheme::ALL.iter().map(|scheme| scheme.to_string()).collect();
+    let schemes: Vec<&str> = schemes.iter().map(String::as_str).collect();
+    let scheme_row = libadwaita::ComboRow::builder()
+    .title("Partition scheme")
+    .subtitle("MBR and hybrid also boot older BIOS/CSM machines")
+    .model(&gtk4::StringList::new(&schemes))
+    .build();
+    list_box.append(&scheme_row);
+    let s_sch = state.clone();
+    let (pr_sch, pi_sch) = (platform_row.clone(), platform_icon.clone());
+    scheme_row.connect_selected_notify(move |row| {
+        let Some(scheme) = windusb::PartitionScheme::ALL.get(row.selected() as usize).copied() else { return; };
+        let iso = { let mut s = s_sch.lock().unwrap(); s.scheme = scheme; s.iso.clone() };
+        if let Some(iso) = iso { show_platform(&pr_sch, &pi_sch, &iso, scheme); }
+    });
+    let layouts: Vec<String> = windusb::DriveLayout::ALL.iter().map(|layout| layout.to_string()).collect();
+    let layouts: Vec<&str> = layouts.iter().map(String::as_str).collect();
+    let layout_row = libadwaita::ComboRow::builder()
+    .title("File system")
+    .subtitle("NTFS keeps install.wim in one piece for DISM and custom images")
+    .model(&gtk4::StringList::new(&layouts))
+    .build();
+    list_box.append(&layout_row);
+    // Set once an ISO is picked, so a new layout re-runs the space check.
+    let recheck_space: Rc<RefCell<Option<Rc<dyn Fn()>>>> = Rc::default();
+    let s_lay = state.clone();
+    let rs_lay = recheck_space.clone();
+    layout_row.connect_selected_notify(move |row| {
+        if let Some(layout) = windusb::DriveLayout::ALL.get(row.selected() as usize) { s_lay.lock().unwrap().layout = *layout; }
+        if let Some(recheck) = rs_lay.borrow().clone() { recheck(); }
+    });
     let btn_box = gtk4::Box::new(gtk4::Orientation::Horizontal, 16);
     btn_box.set_halign(gtk4::Align::Center);
     btn_box.set_margin_top(12);
@@ -471,6 +400,13 @@ fn build_iso_page(stack: &gtk4::Stack, state: Arc<Mutex<AppState>>, sender: mpsc
     let s_c = state.clone();
     let b_c = start_btn.clone();
     let r_c = iso_row.clone();
+    let el_c = editions_list.clone();
+    let es_c = editions_scroll.clone();
+    let pr_c = platform_row.clone();
+    let pi_c = platform_icon.clone();
+    let cr_c = checksum_row.clone();
+    let ci_c = checksum_icon.clone();
+    let rs_c = recheck_space.clone();
     iso_row.connect_activated(move |_| {
         let dialog = gtk4::FileChooserDialog::new(
             Some("Select Windows ISO"),
@@ -493,21 +429,51 @@ fn build_iso_page(stack: &gtk4::Stack, state: Arc<Mutex<AppState>>, sender: mpsc
         let s_i = s_c.clone();
         let b_i = b_c.clone();
         let r_i = r_c.clone();
+        let el_i = el_c.clone();
+        let es_i = es_c.clone();
+        let pr_i = pr_c.clone();
+        let pi_i = pi_c.clone();
+        let cr_i = cr_c.clone();
+        let ci_i = ci_c.clone();
+        let rs_i = rs_c.clone();
         dialog.connect_response(move |d, res| {
             if res == gtk4::ResponseType::Ok {
                 if let Some(file) = d.file() {
                     let path = file.path().unwrap();
-                    if is_valid_windows_iso(&path) {
+                    let recheck: Rc<dyn Fn()> = {
+                        let (s_r, r_r, b_r, path) = (s_i.clone(), r_i.clone(), b_i.clone(), path.clone());
+                        Rc::new(move || {
+                            let (drive, editions, layout) = { let s = s_r.lock().unwrap(); (s.drive.clone(), s.editions.clone().unwrap_or_default(), s.layout) };
+                            let Some(drive) = drive else { return; };
+                            r_r.remove_css_class("invalid-iso");
+                            r_r.set_title("Selected (Valid)");
+                            r_r.set_subtitle(&path.file_name().unwrap().to_string_lossy());
+                            let fits = show_space_check(&r_r, &path, &drive, &editions, layout);
+                            b_r.set_sensitive(fits);
+                        })
+                    };
+                    show_editions(&el_i, &es_i, &path, &s_i, recheck.clone());
+                    if windusb::is_valid_windows_iso(&path) {
+                        let scheme = s_i.lock().unwrap().scheme;
+                        show_platform(&pr_i, &pi_i, &path, scheme);
                         r_i.remove_css_class("invalid-iso");
                         r_i.set_title("Selected (Valid)");
                         r_i.set_subtitle(&path.file_name().unwrap().to_string_lossy());
+                        let (drive, layout) = { let s = s_i.lock().unwrap(); (s.drive.clone(), s.layout) };
+                        let fits = drive.is_none_or(|drive| show_space_check(&r_i, &path, &drive, &[], layout));
+                        start_checksum(&cr_i, &ci_i, &path, &s_i);
                         s_i.lock().unwrap().iso = Some(path);
-                        b_i.set_sensitive(true);
+                        b_i.set_sensitive(fits);
+                        *rs_i.borrow_mut() = Some(recheck);
                     } else {
                         r_i.add_css_class("invalid-iso");
                         r_i.set_title("Invalid ISO");
                         r_i.set_subtitle("Missing install.wim/esd");
+                        pr_i.set_visible(false);
+                        cr_i.set_visible(false);
+                        if let Some(check) = s_i.lock().unwrap().checksum.take() { check.store(true, Ordering::Relaxed); }
                         b_i.set_sensitive(false);
+                        *rs_i.borrow_mut() = None;
                     }
                 }
             }
@@ -516,40 +482,129 @@ fn build_iso_page(stack: &gtk4::Stack, state: Arc<Mutex<AppState>>, sender: mpsc
         dialog.show();
     });
     let st_flash = stack.clone();
+    let r_flash = iso_row.clone();
     start_btn.connect_clicked(move |btn| {
-        let drive_name = state.lock().unwrap().drive.clone().unwrap_or_default();
-        let confirm = gtk4::MessageDialog::new(
-            Some(&btn.root().and_downcast::<gtk4::Window>().unwrap()),
-                                               gtk4::DialogFlags::MODAL,
-                                               gtk4::MessageType::Warning,
-                                               gtk4::ButtonsType::YesNo,
-                                               &format!("WARNING: ALL DATA on {} will be DELETED. Proceed?", drive_name)
-        );
-        let st_conf = st_flash.clone();
-        let s_conf = state.clone();
-        let tx_conf = sender.clone();
-        confirm.connect_response(move |d, res| {
-            if res == gtk4::ResponseType::Yes {
-                st_conf.set_visible_child_name("progress");
-                let s = s_conf.lock().unwrap();
-                let drv = s.drive.clone().unwrap();
-                let iso = s.iso.clone().unwrap();
-                let tx = tx_conf.clone();
-                thread::spawn(move || { run_flasher(drv, iso, tx); });
+        let drive_name = match state.lock().unwrap().drive.clone() {
+            Some(drive) => drive,
+            None => {
+                st_flash.set_visible_child_name("drive");
+                return;
             }
-            d.destroy();
+        };
+        let (iso, editions, layout) = { let s = state.lock().unwrap(); (s.iso.clone(), s.editions.clone().unwrap_or_default(), s.layout) };
+        let space = iso.as_deref().and_then(|iso| windusb::check_iso_fits(iso, &drive_name, &editions, layout));
+        if let Some(iso) = iso {
+            if !show_space(&r_flash, &iso, &drive_name, space.as_ref()) {
+                btn.set_sensitive(false);
+                return;
+            }
+        }
+        state.lock().unwrap().expected = space.map(|space| space.required);
+        let parent = btn.root().and_downcast::<gtk4::Window>().unwrap();
+        let (parent_e, st_conf, s_conf, tx_conf) = (parent.clone(), st_flash.clone(), state.clone(), sender.clone());
+        let erase: Rc<dyn Fn()> = Rc::new(move || {
+            let confirm = gtk4::MessageDialog::new(
+                Some(&parent_e),
+                                                   gtk4::DialogFlags::MODAL,
+                                                   gtk4::MessageType::Warning,
+                                                   gtk4::ButtonsType::YesNo,
+                                                   &format!("WARNING: ALL DATA on {} will be DELETED. Proceed?", drive_name)
+            );
+            let (st_conf, s_conf, tx_conf) = (st_conf.clone(), s_conf.clone(), tx_conf.clone());
+            confirm.connect_response(move |d, res| {
+                if res == gtk4::ResponseType::Yes {
+                    let drive = s_conf.lock().unwrap().drive.clone();
+                    match (drive, d.transient_for()) {
+                        (Some(drive), Some(parent)) => {
+                            let (st_start, s_start, tx_start) = (st_conf.clone(), s_conf.clone(), tx_conf.clone());
+                            release_drive(&parent, drive, Rc::new(move || {
+                                let mut s = s_start.lock().unwrap();
+                                if let Some(check) = s.checksum.take() { check.store(true, Ordering::Relaxed); }
+                                if let (Some(drv), Some(iso)) = (s.drive.clone(), s.iso.clone()) {
+                                    st_start.set_visible_child_name("progress");
+                                    let tx = tx_start.clone();
+                                    let editions = s.editions.clone().unwrap_or_default();
+                                    let (verify, scheme, layout) = (s.verify, s.scheme, s.layout);
+                                    let cancel = Arc::new(AtomicBool::new(false));
+                                    s.flash = Some(cancel.clone());
+                                    thread::spawn(move || { let _ = windusb::Flasher::new(drv, iso).editions(editions).verify(verify).scheme(scheme).layout(layout).cancel(cancel).run(tx); });
+                                } else {
+                                    st_start.set_visible_child_name("drive");
+                                }
+                            }));
+                        }
+                        _ => st_conf.set_visible_child_name("drive"),
+                    }
+                }
+                d.destroy();
+            });
+            confirm.show();
         });
-        confirm.show();
+        let mismatch = state.lock().unwrap().mismatch.clone();
+        match mismatch {
+            Some(file) => confirm_mismatch(&parent, &file, erase),
+            None => erase(),
+        }
     });
     btn_box.append(&back_btn);
     btn_box.append(&start_btn);
     box_.append(&label);
     box_.append(&list_box);
+    box_.append(&editions_scroll);
     box_.append(&btn_box);
     box_
 }
 
-fn build_progress_page(status: gtk4::Label, bar: gtk4::ProgressBar, percent: gtk4::Label, finish: gtk4::Button, cancel: gtk4::Button) -> gtk4::Box {
+/// Warns that the ISO does not match the official `file` and calls `proceed` only if the user chooses to flash it anyway.
+fn confirm_mismatch(parent: &gtk4::Window, file: &str, proceed: Rc<dyn Fn()>) {
+    let dialog = gtk4::MessageDialog::new(
+        Some(parent),
+                                          gtk4::DialogFlags::MODAL,
+                                          gtk4::MessageType::Warning,
+                                          gtk4::ButtonsType::None,
+                                          "The ISO does not match its official checksum"
+    );
+    dialog.set_secondary_text(Some(&format!("It is named like {} but its SHA-256 differs. The download may be corrupt or modified, or Microsoft may have reissued the image under the same name.", file)));
+    dialog.add_button("Cancel", gtk4::ResponseType::Cancel);
+    dialog.add_button("Flash anyway", gtk4::ResponseType::Accept);
+    dialog.connect_response(move |d, res| {
+        d.destroy();
+        if res == gtk4::ResponseType::Accept {
+            proceed();
+        }
+    });
+    dialog.show();
+}
+
+/// Unmounts the drive's filesystems and calls `start`. Whatever keeps one
+/// busy is listed with the choice to close it and retry, or abort.
+fn release_drive(parent: &gtk4::Window, drive: String, start: Rc<dyn Fn()>) {
+    let busy = match windusb::release_drive(&drive) {
+        Ok(()) => return start(),
+        Err(busy) => busy,
+    };
+    let lines: Vec<String> = busy.iter().map(|mount| format!("• {}", mount)).collect();
+    let dialog = gtk4::MessageDialog::new(
+        Some(parent),
+                                          gtk4::DialogFlags::MODAL,
+                                          gtk4::MessageType::Warning,
+                                          gtk4::ButtonsType::None,
+                                          &format!("{} is still in use", drive)
+    );
+    dialog.set_secondary_text(Some(&format!("{}\n\nClose these programs or files, then retry.", lines.join("\n"))));
+    dialog.add_button("Abort", gtk4::ResponseType::Cancel);
+    dialog.add_button("Retry", gtk4::ResponseType::Accept);
+    let parent = parent.clone();
+    dialog.connect_response(move |d, res| {
+        d.destroy();
+        if res == gtk4::ResponseType::Accept {
+            release_drive(&parent, drive.clone(), start.clone());
+        }
+    });
+    dialog.show();
+}
+
+fn build_progress_page(status: gtk4::Label, bar: gtk4::ProgressBar, percent: gtk4::Label, details: gtk4::Label, phases: gtk4::Label, finish: gtk4::Button, cancel: gtk4::Button) -> gtk4::Box {
     let box_ = gtk4::Box::new(gtk4::Orientation::Vertical, 20);
     box_.set_valign(gtk4::Align::Center);
     box_.set_margin_top(20);
@@ -563,6 +618,8 @@ fn build_progress_page(status: gtk4::Label, bar: gtk4::ProgressBar, percent: gtk
     status.set_margin_bottom(8);
     box_.append(&status);
     box_.append(&row);
+    box_.append(&details);
+    box_.append(&phases);
     cancel.set_halign(gtk4::Align::Center);
     cancel.set_width_request(160);
     box_.append(&cancel);
@@ -573,24 +630,215 @@ fn build_progress_page(status: gtk4::Label, bar: gtk4::ProgressBar, percent: gtk
     box_
 }
 
+/// Shows write speed, elapsed time and time left, and how long each phase took.
+fn show_stats(details: &gtk4::Label, phases: &gtk4::Label, stats: &windusb::FlashStats) {
+    let mut parts = Vec::new();
+    if let Some(rate) = stats.bytes_per_sec() {
+        parts.push(format!("{:.1} MB/s", rate / 1_048_576.0));
+    }
+    parts.push(format!("{} elapsed", windusb::stats::format_duration(stats.elapsed())));
+    if let Some(eta) = stats.eta() {
+        parts.push(format!("about {} left", windusb::stats::format_duration(eta)));
+    }
+    details.set_text(&parts.join(" · "));
+    let lines: Vec<String> = stats.phases().iter().map(|p| {
+        let mut line = format!("{} {} {}", if p.finished { "✓" } else { "›" }, p.phase.label(), windusb::stats::format_duration(p.elapsed));
+        if let Some(bytes) = p.bytes_written.filter(|b| *b > 0) {
+            line.push_str(&format!(" · {} written", windusb::drives::format_size(bytes)));
+        }
+        line
+    }).collect();
+    phases.set_text(&lines.join("\n"));
+}
+
+/// Shows on the ISO row whether the ISO, cut down to the selected editions, fits on the drive written with `layout`; returns `false` if it does not.
+fn show_space_check(row: &libadwaita::ActionRow, iso: &std::path::Path, drive: &str, editions: &[u32], layout: windusb::DriveLayout) -> bool {
+    show_space(row, iso, drive, windusb::check_iso_fits(iso, drive, editions, layout).as_ref())
+}
+
+/// Shows an already measured `space` check on the ISO row; returns `false` if the ISO does not fit.
+fn show_space(row: &libadwaita::ActionRow, iso: &std::path::Path, drive: &str, space: Option<&windusb::SpaceCheck>) -> bool {
+    let name = iso.file_name().unwrap_or_default().to_string_lossy().to_string();
+    match space {
+        Some(space) if !space.fits() => {
+            row.add_css_class("invalid-iso");
+            row.set_title("Drive Too Small");
+            row.set_subtitle(&format!("{} needs {}, {} holds {}", name, windusb::drives::format_size(space.required), drive, windusb::drives::format_size(space.available)));
+            false
+        }
+        Some(space) if space.is_tight() => {
+            row.set_title("Selected (Low Space)");
+            row.set_subtitle(&format!("{} — only {} will be left free", name, windusb::drives::format_size(space.available - space.required)));
+            true
+        }
+        _ => true,
+    }
+}
+
+/// Shows the Windows release and architecture the ISO targets, and why the stick might not boot with `scheme`.
+fn show_platform(row: &libadwaita::ActionRow, icon: &gtk4::Image, iso: &std::path::Path, scheme: windusb::PartitionScheme) {
+    let Some(info) = windusb::detect_platform(iso) else {
+        row.set_visible(false);
+        return;
+    };
+    let summary = info.summary();
+    row.set_title(if summary.is_empty() { "Unknown Windows release" } else { summary.as_str() });
+    let warnings: Vec<String> = info.warnings(scheme).iter().map(|w| {
+        let text = w.to_string();
+        let mut chars = text.chars();
+        chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+    }).collect();
+    if warnings.is_empty() {
+        row.remove_css_class("warning");
+        icon.set_icon_name(Some("computer-symbolic"));
+        let loaders: Vec<String> = info.uefi_loaders.iter().map(|a| a.to_string()).collect();
+        if loaders.is_empty() {
+            row.set_subtitle("Boots on BIOS/CSM firmware only");
+        } else {
+            row.set_subtitle(&format!("Boots on {} UEFI firmware", loaders.join("/")));
+        }
+    } else {
+        row.add_css_class("warning");
+        icon.set_icon_name(Some("dialog-warning-symbolic"));
+        row.set_subtitle(&warnings.join("\n"));
+    }
+    row.set_visible(true);
+}
+
+/// Hashes the ISO in the background and shows whether it is an official Microsoft image.
+/// A checksum mismatch is only a warning, since Microsoft reissues images under the same name; flashing then asks for confirmation.
+fn start_checksum(row: &libadwaita::ActionRow, icon: &gtk4::Image, iso: &std::path::Path, state: &Arc<Mutex<AppState>>) {
+    let (tx, rx) = mpsc::channel::<windusb::ChecksumEvent>();
+    let cancel = windusb::verify_iso(iso.to_path_buf(), windusb::Catalogue::load(), tx);
+    {
+        let mut s = state.lock().unwrap();
+        s.mismatch = None;
+        if let Some(previous) = s.checksum.replace(cancel.clone()) { previous.store(true, Ordering::Relaxed); }
+    }
+    for class in ["success", "warning", "error"] { row.remove_css_class(class); }
+    icon.set_icon_name(Some("content-loading-symbolic"));
+    row.set_title("Verifying checksum…");
+    row.set_subtitle("Comparing the ISO with official Microsoft images");
+    row.set_visible(true);
+    let (row, icon, state) = (row.clone(), icon.clone(), state.clone());
+    glib::timeout_add_local(std::time::Duration::from_millis(100), move || {
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                return glib::ControlFlow::Break;
+            }
+            match rx.try_recv() {
+                Ok(windusb::ChecksumEvent::Hashing { hashed, total }) => {
+                    let percent = (hashed * 100).checked_div(total).unwrap_or(100);
+                    row.set_title(&format!("Verifying checksum… {}%", percent));
+                }
+                Ok(windusb::ChecksumEvent::Done { verdict, .. }) => {
+                    match verdict {
+                        windusb::Verdict::Verified(image) => {
+                            row.add_css_class("success");
+                            icon.set_icon_name(Some("emblem-ok-symbolic"));
+                            row.set_title("Verified official image");
+                            row.set_subtitle(&image.summary());
+                        }
+                        windusb::Verdict::Unknown => {
+                            icon.set_icon_name(Some("dialog-question-symbolic"));
+                            row.set_title("Unknown image");
+                            row.set_subtitle("Not in the checksum catalogue; it may be modified or newer than the catalogue");
+                        }
+                        windusb::Verdict::Mismatch(image) => {
+                            let file = image.file.clone().unwrap_or_else(|| "image".to_string());
+                            row.add_css_class("warning");
+                            icon.set_icon_name(Some("dialog-warning-symbolic"));
+                            row.set_title("Checksum mismatch");
+                            row.set_subtitle(&format!("Not the official {}; the download may be corrupt, or a newer reissue", file));
+                            state.lock().unwrap().mismatch = Some(file);
+                        }
+                    }
+                    return glib::ControlFlow::Break;
+                }
+                Ok(windusb::ChecksumEvent::Failed(err)) => {
+                    row.add_css_class("warning");
+                    icon.set_icon_name(Some("dialog-warning-symbolic"));
+                    row.set_title("Checksum unavailable");
+                    row.set_subtitle(&err);
+                    return glib::ControlFlow::Break;
+                }
+                Err(mpsc::TryRecvError::Empty) => return glib::ControlFlow::Continue,
+                Err(mpsc::TryRecvError::Disconnected) => return glib::ControlFlow::Break,
+            }
+        }
+    });
+}
+
+/// Lists the Windows editions found in the ISO's install image, hiding the list if there are none.
+/// Unticking editions limits the flash to the remaining ones; at least one always stays ticked.
+/// `changed` runs after every change to the selection.
+fn show_editions(list: &gtk4::ListBox, scroll: &gtk4::ScrolledWindow, iso: &std::path::Path, state: &Arc<Mutex<AppState>>, changed: Rc<dyn Fn()>) {
+    while let Some(child) = list.first_child() { list.remove(&child); }
+    state.lock().unwrap().editions = None;
+    let editions = windusb::list_editions(iso).unwrap_or_default();
+    let checks: Vec<(u32, gtk4::CheckButton)> = editions.iter().map(|edition| {
+        let row = libadwaita::ActionRow::builder()
+        .title(edition.name.as_str())
+        .subtitle(format!("Index {} · {}", edition.index, edition.summary()).as_str())
+        .build();
+        let check = gtk4::CheckButton::new();
+        check.set_active(true);
+        check.set_valign(gtk4::Align::Center);
+        row.add_prefix(&check);
+        row.set_activatable_widget(Some(&check));
+        list.append(&row);
+        (edition.index, check)
+    }).collect();
+    let checks = std::rc::Rc::new(checks);
+    for (_, check) in checks.iter() {
+        let c_t = checks.clone();
+        let s_t = state.clone();
+        let changed = changed.clone();
+        check.connect_toggled(move |check| {
+            let selected: Vec<u32> = c_t.iter().filter(|(_, c)| c.is_active()).map(|(index, _)| *index).collect();
+            if selected.is_empty() {
+                check.set_active(true);
+                return;
+            }
+            s_t.lock().unwrap().editions = (selected.len() < c_t.len()).then_some(selected);
+            changed();
+        });
+    }
+    scroll.set_visible(!editions.is_empty());
+}
+
 fn refresh_drives(list: &gtk4::ListBox) {
     while let Some(child) = list.first_child() { list.remove(&child); }
-    let lsblk_bin = get_local_bin("lsblk");
-    if let Ok(out) = Command::new(lsblk_bin).args(["-pno", "NAME,SIZE,MODEL,TRAN"]).output() {
-        let stdout = String::from_utf8_lossy(&out.stdout);
-        for line in stdout.lines().filter(|l| l.contains("usb")) {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() >= 2 {
-                let row = libadwaita::ActionRow::builder()
-                .title(parts[0])
-                .subtitle(parts[1..].join(" "))
-                .activatable(true)
-                .build();
-                row.add_prefix(&gtk4::Image::from_icon_name("drive-removable-media-symbolic"));
-                list.append(&row);
+    for drive in windusb::list_usb_drives() {
+        let hazards = windusb::drive_hazards(&drive.path);
+        let row = libadwaita::ActionRow::builder()
+        .title(drive.path.as_str())
+        .subtitle(drive.description.as_str())
+        .activatable(hazards.is_empty())
+        .build();
+        row.add_prefix(&gtk4::Image::from_icon_name("drive-removable-media-symbolic"));
+        if !hazards.is_empty() {
+            let reasons: Vec<String> = hazards.iter().map(|h| h.to_string()).collect();
+            row.set_subtitle(&format!("{} — in use: {}", drive.description, reasons.join("; ")));
+            row.add_suffix(&gtk4::Image::from_icon_name("changes-prevent-symbolic"));
+            row.set_sensitive(false);
+        }
+        list.append(&row);
+    }
+}
+
+fn select_drive_row(list: &gtk4::ListBox, drive: &str) -> bool {
+    let mut child = list.first_child();
+    while let Some(widget) = child {
+        if let Some(row) = widget.downcast_ref::<libadwaita::ActionRow>() {
+            if row.title() == drive {
+                list.select_row(Some(row));
+                return true;
             }
         }
+        child = widget.next_sibling();
     }
+    false
 }
 
 fn escalate_privileges() {
@@ -0,0 +1,216 @@
+use std::path::Path;
+
+use crate::drives::drive_size_with;
+use crate::flash::{SPLIT_SIZE_MB, UEFI_NTFS_PARTITION_MB};
+use crate::iso::{inspect, list_editions, IsoEntry};
+use crate::layout::DriveLayout;
+use crate::runner::{CommandRunner, SystemRunner};
+use crate::wim::Edition;
+
+const MIB: u64 = 1024 * 1024;
+const GIB: u64 = 1024 * MIB;
+
+/// The partition starts 1 MiB in and the backup GPT takes 33 sectors at the end.
+const PARTITION_TABLE_OVERHEAD: u64 = MIB + 33 * 512;
+
+/// Reserved sectors mkfs.fat puts in front of the FATs.
+const FAT32_RESERVED: u64 = 32 * 512;
+
+/// Header, XML data and lookup table repeated in every `.swm` piece, rounded up generously.
+const SPLIT_PART_OVERHEAD: u64 = 8 * MIB;
+
+/// Cluster size mkfs.ntfs picks for any volume a USB stick can have.
+const NTFS_CLUSTER: u64 = 4096;
+
+/// Every file and directory takes one record in the NTFS master file table.
+const NTFS_MFT_RECORD: u64 = 1024;
+
+/// `$LogFile`, the MFT and its mirror and the other metadata files of a
+/// freshly made NTFS volume, rounded up generously.
+const NTFS_RESERVED: u64 = 72 * MIB;
+
+/// Less spare room than this after writing is worth a warning.
+const LOW_SPACE_MARGIN: u64 = 256 * MIB;
+
+/// Space the installer needs on a drive compared to what the drive offers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpaceCheck {
+    /// Bytes the extracted files take on the chosen layout, with the install
+    /// image split on FAT32 and whole on NTFS.
+    pub required: u64,
+    /// Bytes available for files once the drive is partitioned and formatted.
+    pub available: u64,
+}
+
+impl SpaceCheck {
+    pub fn fits(&self) -> bool {
+        self.required <= self.available
+    }
+
+    /// Fits, but with less than a few hundred MiB to spare.
+    pub fn is_tight(&self) -> bool {
+        self.fits() && self.available - self.required < LOW_SPACE_MARGIN
+    }
+}
+
+/// Default FAT32 cluster size Windows uses for a volume of this size. mkfs.fat
+/// never picks larger clusters, so estimates based on it err on the safe side.
+pub fn fat32_cluster_size(volume: u64) -> u64 {
+    match volume {
+        v if v <= 8 * GIB => 4096,
+        v if v <= 16 * GIB => 8192,
+        v if v <= 32 * GIB => 16384,
+        _ => 32768,
+    }
+}
+
+/// Bytes left for files on a drive of `drive_size` after partitioning and
+/// formatting it as FAT32.
+pub fn usable_space(drive_size: u64) -> u64 {
+    let volume = drive_size.saturating_sub(PARTITION_TABLE_OVERHEAD);
+    let cluster = fat32_cluster_size(volume);
+    // Two copies of the FAT, four bytes per cluster.
+    let fats = 2 * 4 * (volume / cluster);
+    volume.saturating_sub(FAT32_RESERVED + fats)
+}
+
+/// Bytes the ISO contents occupy on a FAT32 volume with `cluster`-sized
+/// clusters, with the install image split into `.swm` pieces.
+pub fn required_space(entries: &[IsoEntry], cluster: u64) -> u64 {
+    let split_size = SPLIT_SIZE_MB * MIB;
+
+    // The root directory lives in a cluster of its own.
+    let mut total = cluster;
+    for entry in entries {
+        if entry.is_dir {
+            total += cluster;
+        } else if is_install_image(&entry.path) {
+            let parts = entry.size.div_ceil(split_size).max(1);
+            total += entry.size + parts * (SPLIT_PART_OVERHEAD + cluster);
+        } else {
+            total += entry.size.div_ceil(cluster) * cluster;
+        }
+    }
+    total
+}
+
+/// Bytes left for files on a drive of `drive_size` after giving the
+/// UEFI:NTFS partition its share and formatting the rest as NTFS.
+pub fn ntfs_usable_space(drive_size: u64) -> u64 {
+    let volume = drive_size.saturating_sub(PARTITION_TABLE_OVERHEAD + UEFI_NTFS_PARTITION_MB * MIB);
+    // One bit per cluster in `$Bitmap`.
+    let bitmap = (volume / NTFS_CLUSTER).div_ceil(8);
+    volume.saturating_sub(NTFS_RESERVED + bitmap)
+}
+
+/// Bytes the ISO contents occupy on NTFS, with the install image copied whole.
+pub fn ntfs_required_space(entries: &[IsoEntry]) -> u64 {
+    // The root directory index.
+    let mut total = NTFS_CLUSTER;
+    for entry in entries {
+        total += NTFS_MFT_RECORD;
+        total += if entry.is_dir {
+            NTFS_CLUSTER
+        } else {
+            entry.size.div_ceil(NTFS_CLUSTER) * NTFS_CLUSTER
+        };
+    }
+    total
+}
+
+fn is_install_image(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path == "sources/install.wim" || path == "sources/install.esd"
+}
+
+/// Compares the ISO contents against a drive of `drive_size` bytes
+/// partitioned and formatted with `layout`.
+pub fn check_space(entries: &[IsoEntry], drive_size: u64, layout: DriveLayout) -> SpaceCheck {
+    match layout {
+        DriveLayout::Fat32 => {
+            let volume = drive_size.saturating_sub(PARTITION_TABLE_OVERHEAD);
+            SpaceCheck {
+                required: required_space(entries, fat32_cluster_size(volume)),
+                available: usable_space(drive_size),
+            }
+        }
+        DriveLayout::Ntfs => SpaceCheck {
+            required: ntfs_required_space(entries),
+            available: ntfs_usable_space(drive_size),
+        },
+    }
+}
+
+/// Checks whether `iso` fits on `drive` written with `layout`, once cut down
+/// to the `editions` selected (all of them if empty); `None` if either cannot
+/// be measured.
+pub fn check_iso_fits(
+    iso: &Path,
+    drive: &str,
+    editions: &[u32],
+    layout: DriveLayout,
+) -> Option<SpaceCheck> {
+    check_iso_fits_with(&SystemRunner, iso, drive, editions, layout)
+}
+
+/// Like [`check_iso_fits`], running `blockdev` (and `7z`, for images the
+/// native reader cannot open) through `runner`.
+pub fn check_iso_fits_with(
+    runner: &dyn CommandRunner,
+    iso: &Path,
+    drive: &str,
+    editions: &[u32],
+    layout: DriveLayout,
+) -> Option<SpaceCheck> {
+    let entries = select_editions(iso, &inspect(runner, iso)?.entries, editions);
+    space_for(&entries, drive_size_with(runner, drive)?, layout)
+}
+
+/// `entries` with the install image shrunk to what exporting only `selected`
+/// is expected to leave; unchanged if nothing is selected or the editions
+/// cannot be read.
+pub(crate) fn select_editions(iso: &Path, entries: &[IsoEntry], selected: &[u32]) -> Vec<IsoEntry> {
+    let mut entries = entries.to_vec();
+    let Some(editions) = list_editions(iso).filter(|_| !selected.is_empty()) else {
+        return entries;
+    };
+    for entry in entries
+        .iter_mut()
+        .filter(|e| !e.is_dir && is_install_image(&e.path))
+    {
+        entry.size = exported_size(entry.size, &editions, selected);
+    }
+    entries
+}
+
+/// Expected size of an install image of `image_size` bytes after exporting
+/// only the `selected` editions, from their installed sizes in the WIM XML.
+/// Editions share most of their files, so the image is about as large as
+/// its largest edition: the selection is weighed against that one, counting
+/// each edition as if it shared nothing with the others. Never more than the
+/// whole image.
+pub fn exported_size(image_size: u64, editions: &[Edition], selected: &[u32]) -> u64 {
+    let largest = editions.iter().map(|e| e.size).max().unwrap_or(0);
+    let chosen: u64 = editions
+        .iter()
+        .filter(|e| selected.contains(&e.index))
+        .map(|e| e.size)
+        .sum();
+    if largest == 0 || chosen == 0 {
+        return image_size;
+    }
+    let scaled = image_size as u128 * chosen as u128 / largest as u128;
+    scaled.min(image_size as u128) as u64
+}
+
+/// `None` when the listing could not be parsed, since an empty ISO always "fits".
+pub(crate) fn space_for(
+    entries: &[IsoEntry],
+    drive_size: u64,
+    layout: DriveLayout,
+) -> Option<SpaceCheck> {
+    if entries.is_empty() {
+        return None;
+    }
+    Some(check_space(entries, drive_size, layout))
+}
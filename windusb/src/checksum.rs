@@ -0,0 +1,269 @@
+//! Hashes ISOs and checks them against a catalogue of official Microsoft images.
+
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// Catalogue shipped with the crate.
+const BUNDLED_CATALOGUE: &str = include_str!("../data/iso-hashes.json");
+
+/// Catalogue file looked up under the user's config directory.
+const USER_CATALOGUE: &str = "windusb/iso-hashes.json";
+
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Progress is reported at most this often, in bytes hashed.
+const PROGRESS_STEP: u64 = 64 * 1024 * 1024;
+
+/// One official ISO and the hashes Microsoft publishes for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KnownImage {
+    /// File name the ISO is downloaded as, e.g. "Win11_23H2_English_x64v2.iso".
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Release and edition, e.g. "Windows 11 23H2".
+    pub edition: String,
+    pub language: String,
+    pub build: String,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub sha1: Option<String>,
+}
+
+impl KnownImage {
+    /// Short description such as "Windows 11 23H2 · English · build 22631".
+    pub fn summary(&self) -> String {
+        format!(
+            "{} · {} · build {}",
+            self.edition, self.language, self.build
+        )
+    }
+
+    fn matches(&self, hashes: &IsoHashes) -> bool {
+        let same = |known: &Option<String>, actual: &str| {
+            known
+                .as_deref()
+                .is_some_and(|k| k.trim().eq_ignore_ascii_case(actual))
+        };
+        same(&self.sha256, &hashes.sha256) || same(&self.sha1, &hashes.sha1)
+    }
+}
+
+/// Lowercase hex digests of a whole ISO.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct IsoHashes {
+    pub sha256: String,
+    pub sha1: String,
+}
+
+/// How an ISO compares to the catalogue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The hash belongs to an official image.
+    Verified(KnownImage),
+    /// Neither the hash nor the file name is in the catalogue.
+    Unknown,
+    /// The file is named like an official image but its hash differs, which
+    /// usually means a truncated or corrupted download.
+    Mismatch(KnownImage),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Verified(image) => write!(f, "verified official image ({})", image.summary()),
+            Verdict::Unknown => f.write_str("unknown image, not in the checksum catalogue"),
+            Verdict::Mismatch(image) => write!(
+                f,
+                "checksum mismatch: this is not the official {} ({}); the download may be corrupt",
+                image.file.as_deref().unwrap_or("image"),
+                image.summary()
+            ),
+        }
+    }
+}
+
+/// Known-good ISO hashes: the bundled list plus any the user adds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Catalogue {
+    images: Vec<KnownImage>,
+}
+
+impl Catalogue {
+    /// Parses a JSON array of [`KnownImage`]s.
+    pub fn parse(json: &str) -> io::Result<Self> {
+        let images = serde_json::from_str(json)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(Catalogue { images })
+    }
+
+    pub fn load_file(path: &Path) -> io::Result<Self> {
+        Catalogue::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The catalogue shipped with WindUSB.
+    pub fn bundled() -> Self {
+        Catalogue::parse(BUNDLED_CATALOGUE).expect("bundled ISO catalogue is valid JSON")
+    }
+
+    /// The bundled catalogue extended with the user's, if there is one. A
+    /// missing or unreadable user catalogue is skipped.
+    pub fn load() -> Self {
+        let mut catalogue = Catalogue::bundled();
+        if let Some(user) = user_catalogue_path().and_then(|p| Catalogue::load_file(&p).ok()) {
+            catalogue.extend(user);
+        }
+        catalogue
+    }
+
+    pub fn extend(&mut self, other: Catalogue) {
+        self.images.extend(other.images);
+    }
+
+    pub fn images(&self) -> &[KnownImage] {
+        &self.images
+    }
+
+    /// Compares the hashes of the ISO named `file_name` against the catalogue.
+    pub fn check(&self, file_name: &str, hashes: &IsoHashes) -> Verdict {
+        if let Some(image) = self.images.iter().find(|i| i.matches(hashes)) {
+            return Verdict::Verified(image.clone());
+        }
+        let same_name = self.images.iter().find(|i| {
+            i.file
+                .as_deref()
+                .is_some_and(|f| f.eq_ignore_ascii_case(file_name))
+        });
+        match same_name {
+            Some(image) => Verdict::Mismatch(image.clone()),
+            None => Verdict::Unknown,
+        }
+    }
+}
+
+/// `iso-hashes.json` in the invoking user's config directory. Under pkexec
+/// `HOME` is root's, so the `USER_HOME` the launcher passes along wins.
+pub fn user_catalogue_path() -> Option<PathBuf> {
+    let config = match env::var_os("USER_HOME") {
+        Some(home) => PathBuf::from(home).join(".config"),
+        None => match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        },
+    };
+    Some(config.join(USER_CATALOGUE))
+}
+
+/// Computes the SHA-256 and SHA-1 of `path` in one pass. `progress` gets the
+/// bytes hashed so far and the file size; setting `cancel` stops hashing with
+/// [`io::ErrorKind::Interrupted`].
+pub fn hash_file(
+    path: &Path,
+    cancel: &AtomicBool,
+    mut progress: impl FnMut(u64, u64),
+) -> io::Result<IsoHashes> {
+    let mut file = File::open(path)?;
+    let total = file.metadata()?.len();
+    let mut sha256 = Sha256::new();
+    let mut sha1 = Sha1::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut hashed = 0;
+    let mut reported = 0;
+    progress(0, total);
+    loop {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "checksum cancelled",
+            ));
+        }
+        let n = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        sha256.update(&buf[..n]);
+        sha1.update(&buf[..n]);
+        hashed += n as u64;
+        if hashed - reported >= PROGRESS_STEP {
+            progress(hashed, total);
+            reported = hashed;
+        }
+    }
+    progress(hashed, total);
+    Ok(IsoHashes {
+        sha256: hex(&sha256.finalize()),
+        sha1: hex(&sha1.finalize()),
+    })
+}
+
+/// SHA-256 of everything `reader` yields, as lowercase hex. `progress` gets
+/// the bytes hashed after each chunk.
+pub fn sha256(mut reader: impl Read, mut progress: impl FnMut(u64)) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    let mut hashed = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        hasher.update(&buf[..n]);
+        hashed += n as u64;
+        progress(hashed);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Progress of [`verify_iso`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChecksumEvent {
+    Hashing { hashed: u64, total: u64 },
+    Done { hashes: IsoHashes, verdict: Verdict },
+    Failed(String),
+}
+
+/// Hashes `iso` on a background thread and checks it against `catalogue`,
+/// reporting to `tx`. Setting the returned flag abandons the check without
+/// a final event, e.g. when the user picks another ISO.
+pub fn verify_iso(
+    iso: PathBuf,
+    catalogue: Catalogue,
+    tx: mpsc::Sender<ChecksumEvent>,
+) -> Arc<AtomicBool> {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let flag = cancel.clone();
+    thread::spawn(move || {
+        let result = hash_file(&iso, &flag, |hashed, total| {
+            let _ = tx.send(ChecksumEvent::Hashing { hashed, total });
+        });
+        let event = match result {
+            Ok(hashes) => {
+                let name = iso.file_name().unwrap_or_default().to_string_lossy();
+                let verdict = catalogue.check(&name, &hashes);
+                ChecksumEvent::Done { hashes, verdict }
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => return,
+            Err(err) => ChecksumEvent::Failed(err.to_string()),
+        };
+        let _ = tx.send(event);
+    });
+    cancel
+}
@@ -0,0 +1,205 @@
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::runner::{CommandRunner, Invocation, SystemRunner};
+
+/// Columns requested from `lsblk`; each maps to a [`BlockDevice`] field.
+const LSBLK_COLUMNS: &str =
+    "NAME,TYPE,SIZE,VENDOR,MODEL,SERIAL,RM,ROTA,TRAN,MOUNTPOINT,LABEL,FSTYPE";
+
+/// A disk or partition as reported by `lsblk -J -b`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct BlockDevice {
+    /// Device node, e.g. `/dev/sdb` or `/dev/sdb1`.
+    pub name: String,
+    /// `disk`, `part`, `loop`, `rom`, `crypt`, `lvm`, ...
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Size in bytes.
+    #[serde(default, deserialize_with = "number")]
+    pub size: u64,
+    #[serde(default, deserialize_with = "trimmed")]
+    pub vendor: Option<String>,
+    #[serde(default, deserialize_with = "trimmed")]
+    pub model: Option<String>,
+    #[serde(default, deserialize_with = "trimmed")]
+    pub serial: Option<String>,
+    #[serde(rename = "rm", default, deserialize_with = "flag")]
+    pub removable: bool,
+    #[serde(rename = "rota", default, deserialize_with = "flag")]
+    pub rotational: bool,
+    /// Transport, e.g. `usb`, `sata` or `nvme`. Only set on whole disks.
+    #[serde(rename = "tran", default, deserialize_with = "trimmed")]
+    pub transport: Option<String>,
+    #[serde(default)]
+    pub mountpoint: Option<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub fstype: Option<String>,
+    /// Partitions, or device-mapper and RAID devices stacked on top.
+    #[serde(default)]
+    pub children: Vec<BlockDevice>,
+}
+
+impl BlockDevice {
+    pub fn is_disk(&self) -> bool {
+        self.kind == "disk"
+    }
+
+    pub fn is_usb(&self) -> bool {
+        self.transport.as_deref() == Some("usb")
+    }
+
+    /// Vendor and model joined for display, e.g. `SanDisk Ultra Fit`.
+    pub fn display_name(&self) -> String {
+        let name: Vec<&str> = [self.vendor.as_deref(), self.model.as_deref()]
+            .into_iter()
+            .flatten()
+            .collect();
+        if name.is_empty() {
+            "Unknown device".to_string()
+        } else {
+            name.join(" ")
+        }
+    }
+
+    /// Every device below this one, depth first.
+    pub fn descendants(&self) -> Vec<&BlockDevice> {
+        let mut found = Vec::new();
+        for child in &self.children {
+            found.push(child);
+            found.extend(child.descendants());
+        }
+        found
+    }
+}
+
+#[derive(Deserialize)]
+struct LsblkOutput {
+    blockdevices: Vec<BlockDevice>,
+}
+
+/// lsblk before util-linux 2.33 prints every value as a string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Loose {
+    Bool(bool),
+    Number(u64),
+    Text(String),
+}
+
+fn number<'de, D: Deserializer<'de>>(de: D) -> Result<u64, D::Error> {
+    match Option::<Loose>::deserialize(de)? {
+        Some(Loose::Number(n)) => Ok(n),
+        Some(Loose::Text(s)) => s.trim().parse().map_err(serde::de::Error::custom),
+        Some(Loose::Bool(_)) => Err(serde::de::Error::custom("expected a number")),
+        None => Ok(0),
+    }
+}
+
+fn flag<'de, D: Deserializer<'de>>(de: D) -> Result<bool, D::Error> {
+    match Option::<Loose>::deserialize(de)? {
+        Some(Loose::Bool(b)) => Ok(b),
+        Some(Loose::Number(n)) => Ok(n != 0),
+        Some(Loose::Text(s)) => Ok(s.trim() == "1"),
+        None => Ok(false),
+    }
+}
+
+fn trimmed<'de, D: Deserializer<'de>>(de: D) -> Result<Option<String>, D::Error> {
+    let value = Option::<String>::deserialize(de)?;
+    Ok(value
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty()))
+}
+
+/// Parses the output of `lsblk -J -b -p`.
+pub fn parse_lsblk(json: &str) -> Result<Vec<BlockDevice>, serde_json::Error> {
+    serde_json::from_str::<LsblkOutput>(json).map(|out| out.blockdevices)
+}
+
+/// Lists every block device with its partitions, running `lsblk` through `runner`.
+pub fn list_block_devices_with(runner: &dyn CommandRunner) -> io::Result<Vec<BlockDevice>> {
+    let out =
+        runner.run(&Invocation::new("lsblk").args(["-J", "-b", "-p", "-o", LSBLK_COLUMNS]))?;
+    if !out.success() {
+        return Err(io::Error::other(out.stderr.trim().to_string()));
+    }
+    parse_lsblk(&out.stdout).map_err(io::Error::other)
+}
+
+/// A USB block device that can be selected as a flashing target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Drive {
+    /// Device node, e.g. `/dev/sdb`.
+    pub path: String,
+    /// Human readable size and model, e.g. `14.9G SanDisk Ultra Fit`.
+    pub description: String,
+    /// Everything `lsblk` reported about the disk and its partitions.
+    pub device: BlockDevice,
+}
+
+impl Drive {
+    fn from_device(device: BlockDevice) -> Self {
+        Drive {
+            path: device.name.clone(),
+            description: format!("{} {}", format_size(device.size), device.display_name()),
+            device,
+        }
+    }
+}
+
+/// Lists the USB drives currently attached to the system.
+pub fn list_usb_drives() -> Vec<Drive> {
+    list_usb_drives_with(&SystemRunner)
+}
+
+/// Like [`list_usb_drives`], running `lsblk` through `runner`.
+pub fn list_usb_drives_with(runner: &dyn CommandRunner) -> Vec<Drive> {
+    list_block_devices_with(runner)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|dev| dev.is_disk() && dev.is_usb())
+        .map(Drive::from_device)
+        .collect()
+}
+
+/// Formats a byte count the way `lsblk` does, e.g. `14.9G`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "K", "M", "G", "T", "P"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    let value = format!("{:.1}", value);
+    format!("{}{}", value.trim_end_matches(".0"), UNITS[unit])
+}
+
+/// Size of the block device in bytes, via `blockdev --getsize64`.
+pub fn drive_size_with(runner: &dyn CommandRunner, drive: &str) -> Option<u64> {
+    let out = runner
+        .run(&Invocation::new("blockdev").args(["--getsize64", drive]))
+        .ok()?;
+    out.stdout.trim().parse().ok()
+}
+
+/// Node of partition `number` on `drive` by the kernel's naming rule: disks
+/// whose name ends in a digit (`nvme0n1`, `mmcblk0`, `loop0`, `nbd0`, `md0`)
+/// separate the partition number with a `p`.
+pub fn partition_path(drive: &str, number: u32) -> String {
+    if drive.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", drive, number)
+    } else {
+        format!("{}{}", drive, number)
+    }
+}
+
+/// Returns `true` while the device node is still present.
+pub fn device_exists(drive: &str) -> bool {
+    Path::new(drive).exists()
+}
@@ -0,0 +1,295 @@
+use std::fmt;
+
+use crate::capacity::SpaceCheck;
+use crate::drives::format_size;
+use crate::event::Phase;
+use crate::runner::Invocation;
+use crate::safety::Hazard;
+use crate::unmount::BusyMount;
+
+/// Mismatched files named in the error message; the rest are only counted.
+const MAX_LISTED_MISMATCHES: usize = 5;
+
+/// How an external tool failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolError {
+    /// Tool name, e.g. `sgdisk`.
+    pub program: String,
+    /// Exit status, or `None` if the tool could not be started or was killed.
+    pub status: Option<i32>,
+    /// Trimmed stderr of the tool, or the spawn error.
+    pub stderr: String,
+}
+
+impl ToolError {
+    pub(crate) fn new(
+        invocation: &Invocation,
+        status: Option<i32>,
+        stderr: impl Into<String>,
+    ) -> Self {
+        ToolError {
+            program: invocation.program.clone(),
+            status,
+            stderr: stderr.into().trim().to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ToolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.status {
+            Some(code) => write!(f, "{} exited with status {}", self.program, code)?,
+            None => write!(f, "{} did not run to completion", self.program)?,
+        }
+        if !self.stderr.is_empty() {
+            write!(f, ": {}", self.stderr)?;
+        }
+        Ok(())
+    }
+}
+
+/// Failure of one step of the flashing pipeline.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlashError {
+    /// The ISO carries neither `sources/install.wim` nor `sources/install.esd`.
+    InvalidIso,
+    /// A selected edition index does not exist in the install image.
+    UnknownEdition(u32),
+    /// The ISO contents do not fit on the target drive.
+    InsufficientSpace(SpaceCheck),
+    /// The target device node vanished during the given phase.
+    DriveDisconnected(Phase),
+    /// The target hosts system data or is otherwise unsafe to wipe.
+    UnsafeDrive(Vec<Hazard>),
+    /// Filesystems on the drive stayed mounted, typically because programs
+    /// still have files open on them.
+    DriveBusy(Vec<BusyMount>),
+    /// A tool the chosen layout or scheme needs is not installed.
+    MissingTool(String),
+    /// The UEFI:NTFS image for the NTFS layout is not at the given path.
+    MissingUefiNtfs(String),
+    /// The run was cancelled through [`Flasher::cancel`](crate::Flasher::cancel).
+    Cancelled,
+    /// The private mount point directories could not be created.
+    CreateMountPoints(String),
+    FlushBuffers(ToolError),
+    WipeSignatures(ToolError),
+    ZapPartitionTable(ToolError),
+    CreatePartition(ToolError),
+    /// The hybrid MBR entry could not be marked active.
+    SetBootFlag(ToolError),
+    ReloadPartitionTable(ToolError),
+    /// The node of the new partition did not show up in time.
+    PartitionNotFound(String),
+    Format(ToolError),
+    /// `ms-sys` failed to write the BIOS boot code.
+    BootCode(ToolError),
+    /// Writing the UEFI:NTFS image to the system partition failed.
+    UefiNtfs(ToolError),
+    MountUsb(ToolError),
+    MountIso(ToolError),
+    Extract(ToolError),
+    /// The private directory for exported editions could not be created.
+    Staging(String),
+    Export(ToolError),
+    /// Reading a file out of the ISO or writing it to the drive failed.
+    ExtractFiles(String),
+    Split(ToolError),
+    Sync(ToolError),
+    Unmount(ToolError),
+    /// The drive could not be read back, or the ISO could not be re-read.
+    Verify(String),
+    /// Files on the drive differ from the ISO, described one per entry.
+    VerifyMismatch(Vec<String>),
+    /// `wimlib-imagex verify` rejected the install image on the drive.
+    VerifyImage(ToolError),
+}
+
+impl FlashError {
+    /// [`code`](Self::code) of [`FlashError::Cancelled`], for telling a
+    /// cancelled run apart from a failed one in a [`FlashEvent::Failed`](crate::FlashEvent::Failed).
+    pub const CANCELLED: &'static str = "cancelled";
+
+    /// Stable machine-readable identifier, used in JSON output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            FlashError::InvalidIso => "invalid_iso",
+            FlashError::UnknownEdition(_) => "unknown_edition",
+            FlashError::InsufficientSpace(_) => "insufficient_space",
+            FlashError::DriveDisconnected(_) => "drive_disconnected",
+            FlashError::UnsafeDrive(_) => "unsafe_drive",
+            FlashError::DriveBusy(_) => "drive_busy",
+            FlashError::MissingTool(_) => "missing_tool",
+            FlashError::MissingUefiNtfs(_) => "uefi_ntfs_missing",
+            FlashError::Cancelled => FlashError::CANCELLED,
+            FlashError::CreateMountPoints(_) => "create_mount_points_failed",
+            FlashError::FlushBuffers(_) => "flush_buffers_failed",
+            FlashError::WipeSignatures(_) => "wipe_failed",
+            FlashError::ZapPartitionTable(_) => "zap_partition_table_failed",
+            FlashError::CreatePartition(_) => "create_partition_failed",
+            FlashError::SetBootFlag(_) => "set_boot_flag_failed",
+            FlashError::ReloadPartitionTable(_) => "reload_partition_table_failed",
+            FlashError::PartitionNotFound(_) => "partition_not_found",
+            FlashError::Format(_) => "format_failed",
+            FlashError::BootCode(_) => "boot_code_failed",
+            FlashError::UefiNtfs(_) => "uefi_ntfs_failed",
+            FlashError::MountUsb(_) => "mount_usb_failed",
+            FlashError::MountIso(_) => "mount_iso_failed",
+            FlashError::Extract(_) | FlashError::ExtractFiles(_) => "extract_failed",
+            FlashError::Staging(_) => "staging_failed",
+            FlashError::Export(_) => "export_failed",
+            FlashError::Split(_) => "split_failed",
+            FlashError::Sync(_) => "sync_failed",
+            FlashError::Unmount(_) => "unmount_failed",
+            FlashError::Verify(_) => "verify_failed",
+            FlashError::VerifyMismatch(_) => "verify_mismatch",
+            FlashError::VerifyImage(_) => "verify_image_failed",
+        }
+    }
+
+    /// The failing tool, if the error came from one.
+    pub fn tool_error(&self) -> Option<&ToolError> {
+        match self {
+            FlashError::InvalidIso
+            | FlashError::UnknownEdition(_)
+            | FlashError::InsufficientSpace(_)
+            | FlashError::DriveDisconnected(_)
+            | FlashError::UnsafeDrive(_)
+            | FlashError::DriveBusy(_)
+            | FlashError::MissingTool(_)
+            | FlashError::MissingUefiNtfs(_)
+            | FlashError::Cancelled
+            | FlashError::CreateMountPoints(_)
+            | FlashError::PartitionNotFound(_)
+            | FlashError::ExtractFiles(_)
+            | FlashError::Staging(_)
+            | FlashError::Verify(_)
+            | FlashError::VerifyMismatch(_) => None,
+            FlashError::FlushBuffers(e)
+            | FlashError::WipeSignatures(e)
+            | FlashError::ZapPartitionTable(e)
+            | FlashError::CreatePartition(e)
+            | FlashError::SetBootFlag(e)
+            | FlashError::ReloadPartitionTable(e)
+            | FlashError::Format(e)
+            | FlashError::BootCode(e)
+            | FlashError::UefiNtfs(e)
+            | FlashError::MountUsb(e)
+            | FlashError::MountIso(e)
+            | FlashError::Extract(e)
+            | FlashError::Export(e)
+            | FlashError::Split(e)
+            | FlashError::Sync(e)
+            | FlashError::Unmount(e)
+            | FlashError::VerifyImage(e) => Some(e),
+        }
+    }
+}
+
+impl fmt::Display for FlashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let step = match self {
+            FlashError::InvalidIso => return f.write_str("Invalid ISO: install.wim/esd not found"),
+            FlashError::UnknownEdition(index) => {
+                return write!(f, "The install image has no edition with index {}", index);
+            }
+            FlashError::InsufficientSpace(space) => {
+                return write!(
+                    f,
+                    "Drive too small: the ISO needs {}, but only {} is available",
+                    format_size(space.required),
+                    format_size(space.available)
+                );
+            }
+            FlashError::DriveDisconnected(phase) => {
+                let when = match phase {
+                    Phase::Formatting => "before formatting",
+                    Phase::CopyingBootFiles => "while extracting boot files",
+                    Phase::ExportingImage => "while exporting the selected editions",
+                    Phase::CopyingImage => "while copying the install image",
+                    Phase::SplittingImage => "while splitting the install image",
+                    Phase::Syncing => "during final sync",
+                    Phase::Verifying => "while verifying the written files",
+                };
+                return write!(f, "Drive disconnected {}.", when);
+            }
+            FlashError::UnsafeDrive(hazards) => {
+                let reasons: Vec<String> = hazards.iter().map(|h| h.to_string()).collect();
+                return write!(f, "Refusing to erase the drive: {}", reasons.join("; "));
+            }
+            FlashError::DriveBusy(mounts) => {
+                let busy: Vec<String> = mounts.iter().map(|m| m.to_string()).collect();
+                return write!(
+                    f,
+                    "The drive is still in use: {}. Close those programs and try again",
+                    busy.join("; ")
+                );
+            }
+            FlashError::MissingTool(tool) => {
+                return write!(
+                    f,
+                    "{} is not installed, but the chosen layout needs it; the drive was not touched",
+                    tool
+                );
+            }
+            FlashError::MissingUefiNtfs(path) => {
+                return write!(
+                    f,
+                    "The UEFI:NTFS boot image {} is missing; the drive was not touched",
+                    path
+                );
+            }
+            FlashError::Cancelled => return f.write_str("Cancelled"),
+            FlashError::CreateMountPoints(reason) => {
+                return write!(f, "Could not create temporary mount points ({})", reason);
+            }
+            FlashError::Staging(reason) => {
+                return write!(f, "Could not create the export directory ({})", reason);
+            }
+            FlashError::PartitionNotFound(node) => {
+                return write!(f, "The new partition {} did not appear", node);
+            }
+            FlashError::ExtractFiles(reason) => {
+                return write!(f, "Extracting files from the ISO failed ({})", reason);
+            }
+            FlashError::Verify(reason) => {
+                return write!(f, "Verifying the drive failed ({})", reason);
+            }
+            FlashError::VerifyMismatch(files) => {
+                write!(
+                    f,
+                    "{} file(s) on the drive do not match the ISO: ",
+                    files.len()
+                )?;
+                f.write_str(&files[..files.len().min(MAX_LISTED_MISMATCHES)].join("; "))?;
+                if files.len() > MAX_LISTED_MISMATCHES {
+                    write!(f, "; and {} more", files.len() - MAX_LISTED_MISMATCHES)?;
+                }
+                return Ok(());
+            }
+            FlashError::FlushBuffers(_) => "Could not flush drive buffers",
+            FlashError::WipeSignatures(_) => "Could not wipe existing filesystem signatures",
+            FlashError::ZapPartitionTable(_) => "Could not erase the partition table",
+            FlashError::CreatePartition(_) => "Could not create the partition",
+            FlashError::SetBootFlag(_) => "Marking the partition bootable failed",
+            FlashError::ReloadPartitionTable(_) => "Kernel did not pick up the new partition table",
+            FlashError::Format(_) => "Formatting failed",
+            FlashError::BootCode(_) => "Writing the BIOS boot code failed",
+            FlashError::UefiNtfs(_) => "Writing the UEFI:NTFS boot partition failed",
+            FlashError::MountUsb(_) => "Failed to mount USB drive",
+            FlashError::MountIso(_) => "Failed to mount the ISO",
+            FlashError::Extract(_) => "Extracting files from the ISO failed",
+            FlashError::Export(_) => "Exporting the selected editions failed",
+            FlashError::Split(_) => "Splitting the install image failed",
+            FlashError::Sync(_) => "Flushing data to the drive failed",
+            FlashError::Unmount(_) => "Unmounting failed",
+            FlashError::VerifyImage(_) => "The install image on the drive is damaged",
+        };
+        match self.tool_error() {
+            Some(tool) => write!(f, "{} ({})", step, tool),
+            None => f.write_str(step),
+        }
+    }
+}
+
+impl std::error::Error for FlashError {}
@@ -0,0 +1,103 @@
+use serde::Serialize;
+
+/// Stage of the flashing pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Formatting,
+    CopyingBootFiles,
+    ExportingImage,
+    CopyingImage,
+    SplittingImage,
+    Syncing,
+    Verifying,
+}
+
+impl Phase {
+    /// Short name of the phase for progress summaries.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Phase::Formatting => "Formatting",
+            Phase::CopyingBootFiles => "Copying boot files",
+            Phase::ExportingImage => "Exporting editions",
+            Phase::CopyingImage => "Copying install image",
+            Phase::SplittingImage => "Splitting install image",
+            Phase::Syncing => "Flushing to drive",
+            Phase::Verifying => "Verifying",
+        }
+    }
+}
+
+/// Snapshot of the pipeline state attached to every progress event.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Progress {
+    pub phase: Phase,
+    /// Human readable status line.
+    pub message: String,
+    /// Overall completion in `0.0..=1.0`.
+    pub fraction: f64,
+    /// Bytes processed so far in the current phase.
+    pub bytes_done: Option<u64>,
+    /// Bytes the current phase has to process, when known.
+    pub bytes_total: Option<u64>,
+    /// File being written, relative to the drive root.
+    pub current_file: Option<String>,
+    /// Recent throughput of the current phase.
+    pub bytes_per_sec: Option<f64>,
+    /// Estimated seconds until the current phase completes.
+    pub eta_secs: Option<u64>,
+}
+
+impl Progress {
+    pub fn new(phase: Phase, message: impl Into<String>, fraction: f64) -> Self {
+        Progress {
+            phase,
+            message: message.into(),
+            fraction,
+            bytes_done: None,
+            bytes_total: None,
+            current_file: None,
+            bytes_per_sec: None,
+            eta_secs: None,
+        }
+    }
+
+    pub fn bytes(mut self, done: u64, total: Option<u64>) -> Self {
+        self.bytes_done = Some(done);
+        self.bytes_total = total;
+        self
+    }
+
+    pub fn file(mut self, path: impl Into<String>) -> Self {
+        self.current_file = Some(path.into());
+        self
+    }
+
+    /// Sets the throughput and derives the ETA from the remaining bytes.
+    pub fn rate(mut self, bytes_per_sec: Option<f64>) -> Self {
+        self.bytes_per_sec = bytes_per_sec;
+        self.eta_secs = match (bytes_per_sec, self.bytes_done, self.bytes_total) {
+            (Some(rate), Some(done), Some(total)) if rate > 0.0 => {
+                Some((total.saturating_sub(done) as f64 / rate).ceil() as u64)
+            }
+            _ => None,
+        };
+        self
+    }
+}
+
+/// Notification emitted by [`run_flasher`](crate::run_flasher).
+///
+/// Serializes to a flat JSON object tagged by `"event"`, suitable for
+/// newline-delimited JSON streams.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum FlashEvent {
+    Progress(Progress),
+    Finished,
+    Failed {
+        /// Stable machine-readable identifier of the failure.
+        code: &'static str,
+        message: String,
+    },
+}
@@ -0,0 +1,1060 @@
+use std::cell::Cell;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::capacity::{select_editions, space_for};
+use crate::drives::{device_exists, drive_size_with, partition_path};
+use crate::error::{FlashError, ToolError};
+use crate::event::{FlashEvent, Phase, Progress};
+use crate::hotplug::wait_for_node;
+use crate::iso::{inspect, list_editions, ImageFormat, InstallImage, IsoEntry};
+use crate::isofs::IsoImage;
+use crate::layout::{DriveLayout, PartitionScheme};
+use crate::mounts::{MountPoint, StagingDir};
+use crate::progress::{parse_7z_progress, RateMeter, WimlibProgress};
+use crate::runner::{
+    run_tool, run_tool_with_output, CommandRunner, Invocation, Output, SystemRunner,
+};
+use crate::safety::DiskInspector;
+use crate::tools::local_data;
+use crate::unmount::release_drive_with;
+use crate::verify::{bytes_to_compare, compare_tree};
+use crate::writeback::DeviceWriteback;
+
+/// Largest `.swm` chunk in MiB, keeping every file below the FAT32 4 GiB limit.
+pub(crate) const SPLIT_SIZE_MB: u64 = 3400;
+
+/// Largest file FAT32 can hold.
+const FAT32_MAX_FILE_SIZE: u64 = 4 * 1024 * 1024 * 1024 - 1;
+
+/// Where selected editions are exported by default; unlike `/tmp` it is
+/// rarely a RAM-backed tmpfs.
+pub(crate) const DEFAULT_STAGING_DIR: &str = "/var/tmp";
+
+/// Size of the UEFI:NTFS system partition at the end of an NTFS stick.
+pub(crate) const UEFI_NTFS_PARTITION_MB: u64 = 2;
+
+/// Bundled FAT image holding the UEFI:NTFS driver and chain loader.
+const UEFI_NTFS_IMAGE: &str = "uefi-ntfs.img";
+
+/// How long the new partition's device node may take to appear.
+const PARTITION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Window over which throughput and ETA are averaged.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Wipes `drive` and writes the Windows installer from `iso` onto it.
+///
+/// Shorthand for [`Flasher::new`] followed by [`Flasher::run`].
+pub fn run_flasher(
+    drive: &str,
+    iso: &Path,
+    tx: mpsc::Sender<FlashEvent>,
+) -> Result<(), FlashError> {
+    Flasher::new(drive, iso).run(tx)
+}
+
+/// Configured flashing job for one drive and one ISO.
+pub struct Flasher {
+    drive: String,
+    iso: PathBuf,
+    runner: Arc<dyn CommandRunner>,
+    inspector: DiskInspector,
+    editions: Vec<u32>,
+    staging_dir: PathBuf,
+    verify: bool,
+    scheme: PartitionScheme,
+    layout: DriveLayout,
+    uefi_ntfs_image: PathBuf,
+    cancel: Arc<AtomicBool>,
+}
+
+/// The flasher's runner as seen by the pipeline: once the job is cancelled
+/// the running tool is stopped and no further tools start.
+#[derive(Clone)]
+struct Tools {
+    runner: Arc<dyn CommandRunner>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl CommandRunner for Tools {
+    fn run(&self, invocation: &Invocation) -> io::Result<Output> {
+        self.runner
+            .run_cancellable(invocation, &mut |_| {}, &self.cancel)
+    }
+
+    fn run_with_output(
+        &self,
+        invocation: &Invocation,
+        on_line: &mut dyn FnMut(&str),
+    ) -> io::Result<Output> {
+        self.runner
+            .run_cancellable(invocation, on_line, &self.cancel)
+    }
+
+    fn has_tool(&self, program: &str) -> bool {
+        self.runner.has_tool(program)
+    }
+}
+
+/// Bytes reaching the drive, counted from just before the files are copied.
+#[derive(Clone)]
+struct DriveWrites {
+    device: Option<DeviceWriteback>,
+    start: u64,
+}
+
+impl DriveWrites {
+    fn start(drive: &str) -> Self {
+        let device = DeviceWriteback::for_device(drive);
+        let start = device.as_ref().and_then(|d| d.bytes_written()).unwrap_or(0);
+        DriveWrites { device, start }
+    }
+
+    /// Bytes the drive has written since the copy started.
+    fn written(&self) -> Option<u64> {
+        let now = self.device.as_ref()?.bytes_written()?;
+        Some(now.saturating_sub(self.start))
+    }
+
+    /// Bytes still cached for the drive, from the bdi stats or else as the
+    /// `copied` bytes not yet written.
+    fn pending(&self, copied: u64) -> Option<u64> {
+        let device = self.device.as_ref()?;
+        device
+            .pending_bytes()
+            .or_else(|| Some(copied.saturating_sub(self.written()?)))
+    }
+}
+
+/// How the install image is being written to the drive, for progress reports.
+#[derive(Clone, Copy)]
+struct ImageWrite {
+    verb: &'static str,
+    /// Overall fraction at which writing the image starts.
+    start: f64,
+    total: u64,
+}
+
+/// Latest progress the running step reported, read by the progress thread.
+#[derive(Debug, Clone, Default)]
+struct Reported {
+    done: u64,
+    total: Option<u64>,
+    /// Overrides `done / total` when the step spans several tool runs.
+    fraction: Option<f64>,
+    file: Option<String>,
+}
+
+impl Flasher {
+    pub fn new(drive: impl Into<String>, iso: impl Into<PathBuf>) -> Self {
+        Flasher {
+            drive: drive.into(),
+            iso: iso.into(),
+            runner: Arc::new(SystemRunner),
+            inspector: DiskInspector::new(),
+            editions: Vec::new(),
+            staging_dir: PathBuf::from(DEFAULT_STAGING_DIR),
+            verify: false,
+            scheme: PartitionScheme::default(),
+            layout: DriveLayout::default(),
+            uefi_ntfs_image: local_data(UEFI_NTFS_IMAGE),
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Routes every external tool through `runner` instead of spawning processes.
+    pub fn runner(mut self, runner: Arc<dyn CommandRunner>) -> Self {
+        self.runner = runner;
+        self
+    }
+
+    /// Checks the drive with `inspector` right before wiping it.
+    pub fn inspector(mut self, inspector: DiskInspector) -> Self {
+        self.inspector = inspector;
+        self
+    }
+
+    /// Writes only the images with these 1-based indexes, exported into a
+    /// fresh install image, instead of the whole file. Empty means all.
+    pub fn editions(mut self, indexes: impl IntoIterator<Item = u32>) -> Self {
+        self.editions = indexes.into_iter().collect();
+        self
+    }
+
+    /// Host directory the selected editions are exported to before being
+    /// copied or split onto the drive.
+    pub fn staging_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.staging_dir = dir.into();
+        self
+    }
+
+    /// After writing, reads every file back from the drive and compares it
+    /// with the ISO, and checks the install image with `wimlib-imagex verify`.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// Partition table to write; GPT by default.
+    pub fn scheme(mut self, scheme: PartitionScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Filesystem layout; FAT32 with a split install image by default.
+    pub fn layout(mut self, layout: DriveLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// FAT image with the UEFI:NTFS chain loader written to the system
+    /// partition of NTFS sticks, instead of the bundled one.
+    pub fn uefi_ntfs_image(mut self, image: impl Into<PathBuf>) -> Self {
+        self.uefi_ntfs_image = image.into();
+        self
+    }
+
+    /// Stops the run once `cancel` is set from another thread: the running
+    /// tool is terminated, our mounts are released and the drive, if it was
+    /// already being written, is left without a partition table.
+    pub fn cancel(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Runs the whole pipeline on the calling thread.
+    ///
+    /// Progress is streamed through `tx`; the run always ends with either
+    /// [`FlashEvent::Finished`] or [`FlashEvent::Failed`], mirroring the returned result.
+    pub fn run(&self, tx: mpsc::Sender<FlashEvent>) -> Result<(), FlashError> {
+        let result = self.flash(&tx).map_err(|err| {
+            if self.cancel.load(Ordering::Relaxed) {
+                FlashError::Cancelled
+            } else {
+                err
+            }
+        });
+        let _ = tx.send(match &result {
+            Ok(()) => FlashEvent::Finished,
+            Err(err) => FlashEvent::Failed {
+                code: err.code(),
+                message: err.to_string(),
+            },
+        });
+        result
+    }
+
+    fn tools(&self) -> Tools {
+        Tools {
+            runner: self.runner.clone(),
+            cancel: self.cancel.clone(),
+        }
+    }
+
+    fn flash(&self, tx: &mpsc::Sender<FlashEvent>) -> Result<(), FlashError> {
+        let runner = &self.tools();
+        let contents = inspect(runner, &self.iso).ok_or(FlashError::InvalidIso)?;
+        let image = contents.image.ok_or(FlashError::InvalidIso)?;
+        if let Some(available) = list_editions(&self.iso).filter(|_| !self.editions.is_empty()) {
+            let unknown = self
+                .editions
+                .iter()
+                .find(|index| !available.iter().any(|e| e.index == **index));
+            if let Some(&index) = unknown {
+                return Err(FlashError::UnknownEdition(index));
+            }
+        }
+        let space = drive_size_with(runner, &self.drive).and_then(|size| {
+            let entries = select_editions(&self.iso, &contents.entries, &self.editions);
+            space_for(&entries, size, self.layout)
+        });
+        if let Some(space) = space.filter(|space| !space.fits()) {
+            return Err(FlashError::InsufficientSpace(space));
+        }
+        self.check_requirements(runner)?;
+
+        let usb = Arc::new(self.mount_point("usb")?);
+        let iso = Arc::new(self.mount_point("iso")?);
+        let wiped = Cell::new(false);
+        let result = self.write_drive(&contents.entries, &image, &usb, &iso, &wiped, tx);
+        // A drive cancelled before its wipe still holds what it held before.
+        let erase = self.cancel.load(Ordering::Relaxed) && wiped.get();
+        // Dropping the mount points unmounts whatever the run left mounted.
+        drop((usb, iso));
+        if erase {
+            self.erase_partition_table();
+        }
+        result
+    }
+
+    /// Makes sure the tools and files the layout and scheme call for are
+    /// there, before anything on the drive is erased.
+    fn check_requirements(&self, runner: &dyn CommandRunner) -> Result<(), FlashError> {
+        let mut needed = Vec::new();
+        if self.layout == DriveLayout::Ntfs {
+            if !self.uefi_ntfs_image.is_file() {
+                return Err(FlashError::MissingUefiNtfs(
+                    self.uefi_ntfs_image.to_string_lossy().into_owned(),
+                ));
+            }
+            needed.extend(["mkfs.ntfs", "dd"]);
+        }
+        match self.scheme {
+            PartitionScheme::Gpt => {}
+            PartitionScheme::Mbr => needed.push("parted"),
+            PartitionScheme::Hybrid => needed.push("sfdisk"),
+        }
+        if self.scheme.boots_bios() {
+            needed.push("ms-sys");
+        }
+        match needed.into_iter().find(|tool| !runner.has_tool(tool)) {
+            Some(tool) => Err(FlashError::MissingTool(tool.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn mount_point(&self, kind: &str) -> Result<MountPoint, FlashError> {
+        MountPoint::create(kind, self.runner.clone())
+            .map_err(|err| FlashError::CreateMountPoints(err.to_string()))
+    }
+
+    /// Partitions the drive, copies the ISO contents over and flushes them,
+    /// then reads them back if asked to.
+    fn write_drive(
+        &self,
+        entries: &[IsoEntry],
+        image: &InstallImage,
+        usb: &Arc<MountPoint>,
+        iso: &Arc<MountPoint>,
+        wiped: &Cell<bool>,
+        tx: &mpsc::Sender<FlashEvent>,
+    ) -> Result<(), FlashError> {
+        let runner = &self.tools();
+        let part = self.partition_drive(wiped, tx)?;
+
+        self.mount_data(runner, usb, &part, &[])
+            .map_err(FlashError::MountUsb)?;
+        iso.mount(
+            runner,
+            Invocation::new("mount")
+                .args(["-o", "loop,ro"])
+                .arg(self.iso.to_string_lossy()),
+        )
+        .map_err(FlashError::MountIso)?;
+
+        let writes = DriveWrites::start(&self.drive);
+        let boot_bytes = entries
+            .iter()
+            .filter(|e| !e.is_dir && !e.path.eq_ignore_ascii_case(image.path()))
+            .map(|e| e.size)
+            .sum();
+        let split =
+            self.copy_files(image, boot_bytes, &usb.as_arg(), &iso.as_arg(), &writes, tx)?;
+        self.sync_and_unmount(usb, iso, &writes, tx)?;
+        if self.verify {
+            self.verify_drive(image, split, &part, usb, tx)?;
+        }
+        Ok(())
+    }
+
+    /// Erases the partition table of a drive whose run was cancelled, so
+    /// that a half-written installer is not mistaken for a working one.
+    fn erase_partition_table(&self) {
+        if device_exists(&self.drive) {
+            let _ = self
+                .runner
+                .run(&Invocation::new("wipefs").args(["-af", &self.drive]));
+        }
+    }
+
+    /// Replaces the partition table with a single FAT32 partition and returns its node.
+    /// Sets `wiped` once the old contents start being erased.
+    fn partition_drive(
+        &self,
+        wiped: &Cell<bool>,
+        tx: &mpsc::Sender<FlashEvent>,
+    ) -> Result<String, FlashError> {
+        let runner = &self.tools();
+        let drive = self.drive.as_str();
+        send_progress(
+            tx,
+            Progress::new(
+                Phase::Formatting,
+                format!("Formatting drive {}...", drive),
+                0.02,
+            ),
+        );
+        let hazards = self.inspector.hazards(drive);
+        if !hazards.is_empty() {
+            return Err(FlashError::UnsafeDrive(hazards));
+        }
+
+        release_drive_with(runner, &self.inspector, drive).map_err(FlashError::DriveBusy)?;
+
+        if !device_exists(drive) {
+            return Err(FlashError::DriveDisconnected(Phase::Formatting));
+        }
+
+        run_tool(
+            runner,
+            Invocation::new("blockdev").args(["--flushbufs", drive]),
+        )
+        .map_err(FlashError::FlushBuffers)?;
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(FlashError::Cancelled);
+        }
+        wiped.set(true);
+        run_tool(runner, Invocation::new("wipefs").args(["-af", drive]))
+            .map_err(FlashError::WipeSignatures)?;
+        run_tool(runner, Invocation::new("sgdisk").args(["-Z", drive]))
+            .map_err(FlashError::ZapPartitionTable)?;
+        self.create_partitions(runner)?;
+        run_tool(runner, Invocation::new("partprobe").arg(drive))
+            .map_err(FlashError::ReloadPartitionTable)?;
+        let part = self.wait_for_partition(runner, 1)?;
+        let format = match self.layout {
+            DriveLayout::Fat32 => Invocation::new("mkfs.fat").args(["-F32", "-I", &part]),
+            DriveLayout::Ntfs => Invocation::new("mkfs.ntfs").args(["-Q", &part]),
+        };
+        let formatted = run_tool(runner, format);
+        if !device_exists(drive) {
+            return Err(FlashError::DriveDisconnected(Phase::Formatting));
+        }
+        formatted.map_err(FlashError::Format)?;
+        if self.layout == DriveLayout::Ntfs {
+            let esp = self.wait_for_partition(runner, 2)?;
+            run_tool(
+                runner,
+                Invocation::new("dd")
+                    .arg(format!("if={}", self.uefi_ntfs_image.to_string_lossy()))
+                    .arg(format!("of={}", esp))
+                    .args(["bs=1M", "conv=fsync"]),
+            )
+            .map_err(FlashError::UefiNtfs)?;
+        }
+        if self.scheme.boots_bios() {
+            self.install_boot_code(runner, &part)?;
+        }
+        Ok(part)
+    }
+
+    /// Mounts the data partition: FAT32 by probing, NTFS with the kernel's
+    /// read-write ntfs3 driver, falling back to ntfs-3g where it is missing.
+    /// Left to probe, `mount` may pick the old read-only ntfs driver.
+    fn mount_data(
+        &self,
+        runner: &dyn CommandRunner,
+        usb: &MountPoint,
+        part: &str,
+        options: &[&str],
+    ) -> Result<Output, ToolError> {
+        match self.layout {
+            DriveLayout::Fat32 => usb.mount(
+                runner,
+                Invocation::new("mount")
+                    .args(options.iter().copied())
+                    .arg(part),
+            ),
+            DriveLayout::Ntfs => usb
+                .mount(
+                    runner,
+                    Invocation::new("mount")
+                        .args(["-t", "ntfs3"])
+                        .args(options.iter().copied())
+                        .arg(part),
+                )
+                .or_else(|_| {
+                    usb.mount(
+                        runner,
+                        Invocation::new("ntfs-3g")
+                            .args(options.iter().copied())
+                            .arg(part),
+                    )
+                }),
+        }
+    }
+
+    /// Writes the partition table of the chosen scheme: the data partition
+    /// spanning the drive, followed for NTFS by the small UEFI:NTFS system
+    /// partition at its end.
+    fn create_partitions(&self, runner: &dyn CommandRunner) -> Result<(), FlashError> {
+        let drive = self.drive.as_str();
+        let ntfs = self.layout == DriveLayout::Ntfs;
+        let gpt = if ntfs {
+            Invocation::new("sgdisk").args([
+                &format!("-n=1:0:-{}M", UEFI_NTFS_PARTITION_MB),
+                "-t=1:0700",
+                "-n=2:0:0",
+                "-t=2:ef00",
+                drive,
+            ])
+        } else {
+            Invocation::new("sgdisk").args(["-n=1:0:0", "-t=1:0700", drive])
+        };
+        let mbr = if ntfs {
+            let esp_start = format!("-{}MiB", UEFI_NTFS_PARTITION_MB);
+            Invocation::new("parted").args([
+                "-s", "--", drive, "mklabel", "msdos", "mkpart", "primary", "ntfs", "1MiB",
+                &esp_start, "mkpart", "primary", "fat16", &esp_start, "100%", "set", "1", "boot",
+                "on", "set", "2", "esp", "on",
+            ])
+        } else {
+            Invocation::new("parted").args([
+                "-s", drive, "mklabel", "msdos", "mkpart", "primary", "fat32", "1MiB", "100%",
+                "set", "1", "boot", "on",
+            ])
+        };
+        // `-h` mirrors the listed partitions into the first MBR entries and
+        // puts the protective 0xEE entry after them.
+        let hybrid = Invocation::new("sgdisk").args(["-h", if ntfs { "1:2" } else { "1" }, drive]);
+        // sgdisk flags the protective entry active; `--label-nested` points
+        // sfdisk at the hybrid MBR rather than the GPT to move the flag.
+        let activate =
+            Invocation::new("sfdisk").args(["--label-nested", "dos", "--activate", drive, "1"]);
+        match self.scheme {
+            PartitionScheme::Gpt => run_tool(runner, gpt).map(drop),
+            PartitionScheme::Mbr => run_tool(runner, mbr).map(drop),
+            PartitionScheme::Hybrid => run_tool(runner, gpt)
+                .and_then(|_| run_tool(runner, hybrid))
+                .map(drop),
+        }
+        .map_err(FlashError::CreatePartition)?;
+        if self.scheme == PartitionScheme::Hybrid {
+            run_tool(runner, activate).map_err(FlashError::SetBootFlag)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the stick start from BIOS: Windows 7 MBR code that chains to
+    /// the active partition, and an NT 6 boot record that loads `bootmgr`.
+    fn install_boot_code(&self, runner: &dyn CommandRunner, part: &str) -> Result<(), FlashError> {
+        let boot_record = match self.layout {
+            DriveLayout::Fat32 => "-8",
+            DriveLayout::Ntfs => "-n",
+        };
+        run_tool(runner, Invocation::new("ms-sys").args([boot_record, part]))
+            .map_err(FlashError::BootCode)?;
+        run_tool(runner, Invocation::new("ms-sys").args(["-7", &self.drive]))
+            .map_err(FlashError::BootCode)?;
+        Ok(())
+    }
+
+    /// Finds the node of partition `number` just created and waits for udev
+    /// to bring it up.
+    fn wait_for_partition(
+        &self,
+        runner: &dyn CommandRunner,
+        number: u32,
+    ) -> Result<String, FlashError> {
+        let drive = self.drive.as_str();
+        let _ = runner.run(&Invocation::new("udevadm").args(["settle", "--timeout=10"]));
+        let part = self
+            .inspector
+            .partition_node(drive, number)
+            .unwrap_or_else(|| partition_path(drive, number));
+        if wait_for_node(Path::new(&part), PARTITION_TIMEOUT) {
+            return Ok(part);
+        }
+        if !device_exists(drive) {
+            return Err(FlashError::DriveDisconnected(Phase::Formatting));
+        }
+        Err(FlashError::PartitionNotFound(part))
+    }
+
+    /// Extracts everything but the install image, then writes the image in
+    /// FAT32-sized pieces, or whole onto NTFS. Files are read in-process,
+    /// with 7z as the fallback for images the native reader cannot open.
+    /// Progress comes from the bytes extracted and from the tools' own
+    /// progress output.
+    /// Returns whether the image was split.
+    fn copy_files(
+        &self,
+        image: &InstallImage,
+        boot_bytes: u64,
+        usb_mt: &str,
+        iso_mt: &str,
+        writes: &DriveWrites,
+        tx: &mpsc::Sender<FlashEvent>,
+    ) -> Result<bool, FlashError> {
+        let runner = &self.tools();
+        let drive = self.drive.as_str();
+        let install_full_path = format!("{}/{}", iso_mt, image.path());
+        let wim_size = std::fs::metadata(&install_full_path).map_or(4_000_000_000, |m| m.len());
+        let staging = if self.editions.is_empty() {
+            None
+        } else {
+            let dir = StagingDir::create_in(&self.staging_dir)
+                .map_err(|err| FlashError::Staging(err.to_string()))?;
+            Some(dir)
+        };
+
+        let is_active = Arc::new(Mutex::new(true));
+        let phase = Arc::new(Mutex::new(Phase::CopyingBootFiles));
+        let reported = Arc::new(Mutex::new(Reported {
+            total: Some(boot_bytes),
+            ..Reported::default()
+        }));
+        let target = Arc::new(Mutex::new(ImageWrite {
+            verb: "Splitting",
+            start: 0.25,
+            total: wim_size,
+        }));
+
+        let is_active_t = is_active.clone();
+        let phase_t = phase.clone();
+        let reported_t = reported.clone();
+        let target_t = target.clone();
+        let tx_t = tx.clone();
+        let drive_t = drive.to_string();
+        let writes_t = writes.clone();
+        let copied_image = format!("{}/{}", usb_mt, image.path());
+        let copied_image_t = copied_image.clone();
+        let image_path = image.path();
+
+        thread::spawn(move || {
+            let mut meter = RateMeter::new(RATE_WINDOW);
+            let mut metered_phase = Phase::CopyingBootFiles;
+
+            while *is_active_t.lock().unwrap() {
+                if !device_exists(&drive_t) {
+                    break;
+                }
+
+                let current_phase = *phase_t.lock().unwrap();
+                if current_phase != metered_phase {
+                    meter.reset();
+                    metered_phase = current_phase;
+                }
+                let reported = reported_t.lock().unwrap().clone();
+                let (progress, done) = match current_phase {
+                    Phase::CopyingBootFiles => {
+                        // Count what reached the stick, not what sits in the page cache.
+                        let done = writes_t
+                            .written()
+                            .map_or(reported.done, |w| w.min(reported.done));
+                        let fraction = ratio(done, boot_bytes);
+                        let mut progress = Progress::new(
+                            current_phase,
+                            "Extracting boot files...",
+                            0.05 + fraction * 0.20,
+                        )
+                        .bytes(done, Some(boot_bytes));
+                        if let Some(file) = reported.file {
+                            progress = progress.file(file);
+                        }
+                        (progress, done)
+                    }
+                    Phase::ExportingImage => {
+                        let fraction = reported
+                            .fraction
+                            .unwrap_or_else(|| ratio(reported.done, reported.total.unwrap_or(0)));
+                        let progress = Progress::new(
+                            current_phase,
+                            format!("Exporting selected editions: {:.0} MB", mb(reported.done)),
+                            0.25 + fraction * 0.25,
+                        )
+                        .bytes(reported.done, reported.total);
+                        (progress, reported.done)
+                    }
+                    _ => {
+                        let target = *target_t.lock().unwrap();
+                        let (done, total) = if target.verb == "Copying" {
+                            let copied = std::fs::metadata(&copied_image_t).map_or(0, |m| m.len());
+                            (copied, target.total)
+                        } else {
+                            (reported.done, reported.total.unwrap_or(target.total))
+                        };
+                        let progress = Progress::new(
+                            current_phase,
+                            format!(
+                                "{} install.wim: {:.0} / {:.0} MB",
+                                target.verb,
+                                mb(done),
+                                mb(total)
+                            ),
+                            target.start + ratio(done, total) * (0.80 - target.start),
+                        )
+                        .bytes(done, Some(total))
+                        .file(image_path);
+                        (progress, done)
+                    }
+                };
+                send_progress(&tx_t, progress.rate(meter.record(done)));
+                thread::sleep(Duration::from_millis(500));
+            }
+        });
+
+        let extracted = match IsoImage::open(&self.iso) {
+            Ok(iso) => iso
+                .extract(
+                    Path::new(usb_mt),
+                    |path| {
+                        !self.cancel.load(Ordering::Relaxed)
+                            && !path.eq_ignore_ascii_case(image.path())
+                    },
+                    |path, written| {
+                        let mut reported = reported.lock().unwrap();
+                        reported.done = written;
+                        reported.file = Some(path.to_string());
+                    },
+                )
+                .map(|_| ())
+                .map_err(|err| FlashError::ExtractFiles(err.to_string())),
+            Err(_) => run_tool_with_output(
+                runner,
+                Invocation::new("7z")
+                    .arg("x")
+                    .arg(self.iso.to_string_lossy())
+                    .arg(format!("-o{}", usb_mt))
+                    .arg(format!("-xr!{}", image.file_name()))
+                    .args(["-bsp1", "-y"]),
+                |line| {
+                    if let Some(progress) = parse_7z_progress(line) {
+                        let mut reported = reported.lock().unwrap();
+                        reported.done = (progress.fraction * boot_bytes as f64) as u64;
+                        reported.file = progress.file.or(reported.file.take());
+                    }
+                },
+            )
+            .map(|_| ())
+            .map_err(FlashError::Extract),
+        };
+        if !device_exists(drive) {
+            *is_active.lock().unwrap() = false;
+            return Err(FlashError::DriveDisconnected(Phase::CopyingBootFiles));
+        }
+        if let Err(err) = extracted {
+            *is_active.lock().unwrap() = false;
+            return Err(err);
+        }
+
+        let enter = |next: Phase| {
+            *reported.lock().unwrap() = Reported::default();
+            *phase.lock().unwrap() = next;
+        };
+        let dst_path = format!("{}/sources/install.{}", usb_mt, image.split_extension());
+        let split = |source: &str| {
+            enter(Phase::SplittingImage);
+            let mut wimlib = WimlibProgress::default();
+            run_tool_with_output(
+                runner,
+                Invocation::new("wimlib-imagex").args([
+                    "split",
+                    source,
+                    &dst_path,
+                    &SPLIT_SIZE_MB.to_string(),
+                ]),
+                |line| {
+                    if wimlib.update(line) {
+                        let mut reported = reported.lock().unwrap();
+                        reported.done = wimlib.done();
+                        reported.total = wimlib.total();
+                    }
+                },
+            )
+            .map(|_| true)
+            .map_err(FlashError::Split)
+        };
+        let copy = |source: &str, start: f64, total: u64| {
+            *target.lock().unwrap() = ImageWrite {
+                verb: "Copying",
+                start,
+                total,
+            };
+            enter(Phase::CopyingImage);
+            run_tool(runner, Invocation::new("cp").args([source, &copied_image])).map(|_| false)
+        };
+        let ntfs = self.layout == DriveLayout::Ntfs;
+        let written = match &staging {
+            None if ntfs => copy(&install_full_path, 0.25, wim_size).map_err(FlashError::Extract),
+            None => split(&install_full_path),
+            Some(dir) => {
+                enter(Phase::ExportingImage);
+                let staging = dir.path().join(format!(
+                    "install.{}",
+                    image.path().rsplit('.').next().unwrap_or("wim")
+                ));
+                let exported = self.export_editions(image, &install_full_path, &staging, &reported);
+                let staging = staging.to_string_lossy().into_owned();
+                match exported {
+                    Err(err) => Err(err),
+                    Ok(size) if ntfs || size.is_some_and(|size| size <= FAT32_MAX_FILE_SIZE) => {
+                        copy(&staging, 0.50, size.unwrap_or(wim_size)).map_err(FlashError::Export)
+                    }
+                    Ok(size) => {
+                        *target.lock().unwrap() = ImageWrite {
+                            verb: "Splitting",
+                            start: 0.50,
+                            total: size.unwrap_or(wim_size),
+                        };
+                        split(&staging)
+                    }
+                }
+            }
+        };
+        drop(staging);
+
+        *is_active.lock().unwrap() = false;
+
+        if !device_exists(drive) {
+            return Err(FlashError::DriveDisconnected(*phase.lock().unwrap()));
+        }
+        written
+    }
+
+    /// Exports the selected editions of `source` into a new image at
+    /// `staging` and returns its size, if it can be measured.
+    fn export_editions(
+        &self,
+        image: &InstallImage,
+        source: &str,
+        staging: &Path,
+        reported: &Mutex<Reported>,
+    ) -> Result<Option<u64>, FlashError> {
+        let staging_str = staging.to_string_lossy();
+        let count = self.editions.len() as f64;
+        let mut finished = 0;
+        for (i, index) in self.editions.iter().enumerate() {
+            let mut export = Invocation::new("wimlib-imagex").args([
+                "export",
+                source,
+                &index.to_string(),
+                &staging_str,
+            ]);
+            // Keep ESDs solid; re-compressing them as plain WIMs doubles their size.
+            if image.format == ImageFormat::Esd {
+                export = export.arg("--solid");
+            }
+            let mut wimlib = WimlibProgress::default();
+            run_tool_with_output(&self.tools(), export, |line| {
+                if wimlib.update(line) {
+                    let mut reported = reported.lock().unwrap();
+                    reported.done = finished + wimlib.done();
+                    reported.fraction = Some(
+                        (i as f64 + ratio(wimlib.done(), wimlib.total().unwrap_or(0))) / count,
+                    );
+                }
+            })
+            .map_err(FlashError::Export)?;
+            finished += wimlib.total().unwrap_or(0);
+        }
+        Ok(std::fs::metadata(staging).ok().map(|m| m.len()))
+    }
+
+    /// Drops the drive's cached blocks, remounts it read-only and compares
+    /// every extracted file with the ISO, then checks the install image.
+    fn verify_drive(
+        &self,
+        image: &InstallImage,
+        split: bool,
+        part: &str,
+        usb: &MountPoint,
+        tx: &mpsc::Sender<FlashEvent>,
+    ) -> Result<(), FlashError> {
+        let runner = &self.tools();
+        send_progress(
+            tx,
+            Progress::new(Phase::Verifying, "Verifying files on the drive...", 0.99),
+        );
+        // Unmounting dropped the file pages; dropping the buffer cache as well
+        // makes every read below come from the stick itself.
+        for device in [part, self.drive.as_str()] {
+            run_tool(
+                runner,
+                Invocation::new("blockdev").args(["--flushbufs", device]),
+            )
+            .map_err(FlashError::FlushBuffers)?;
+        }
+        self.mount_data(runner, usb, part, &["-o", "ro"])
+            .map_err(FlashError::MountUsb)?;
+
+        let result = self.compare_drive(image, split, &usb.as_arg(), tx);
+        let unmounted = usb.unmount(runner, false).map_err(FlashError::Unmount);
+        if !device_exists(&self.drive) {
+            return Err(FlashError::DriveDisconnected(Phase::Verifying));
+        }
+        result.and(unmounted.map(|_| ()))
+    }
+
+    fn compare_drive(
+        &self,
+        image: &InstallImage,
+        split: bool,
+        usb_mt: &str,
+        tx: &mpsc::Sender<FlashEvent>,
+    ) -> Result<(), FlashError> {
+        let failed = |err: std::io::Error| FlashError::Verify(err.to_string());
+        let iso = IsoImage::open(&self.iso).map_err(failed)?;
+        let include = |path: &str| {
+            !self.cancel.load(Ordering::Relaxed) && !path.eq_ignore_ascii_case(image.path())
+        };
+        let total = bytes_to_compare(&iso, include).map_err(failed)?;
+        let mut meter = RateMeter::new(RATE_WINDOW);
+        let mismatches = compare_tree(&iso, Path::new(usb_mt), include, |path, done| {
+            let mb = done as f64 / 1024.0 / 1024.0;
+            send_progress(
+                tx,
+                Progress::new(
+                    Phase::Verifying,
+                    format!(
+                        "Verifying files: {:.0} / {:.0} MB",
+                        mb,
+                        total as f64 / 1024.0 / 1024.0
+                    ),
+                    0.99,
+                )
+                .bytes(done, Some(total))
+                .file(path)
+                .rate(meter.record(done)),
+            );
+        })
+        .map_err(failed)?;
+        if !mismatches.is_empty() {
+            return Err(FlashError::VerifyMismatch(
+                mismatches.iter().map(|m| m.to_string()).collect(),
+            ));
+        }
+
+        let (written, refs) = if split {
+            let pieces = format!("{}/sources/install*.{}", usb_mt, image.split_extension());
+            let first = format!("{}/sources/install.{}", usb_mt, image.split_extension());
+            (first, Some(format!("--ref={}", pieces)))
+        } else {
+            (format!("{}/{}", usb_mt, image.path()), None)
+        };
+        send_progress(
+            tx,
+            Progress::new(Phase::Verifying, "Verifying the install image...", 0.99)
+                .file(image.path()),
+        );
+        let mut verify = Invocation::new("wimlib-imagex").args(["verify", &written]);
+        if let Some(refs) = refs {
+            verify = verify.arg(refs);
+        }
+        run_tool(&self.tools(), verify)
+            .map(|_| ())
+            .map_err(FlashError::VerifyImage)
+    }
+
+    /// Flushes the page cache to the stick and unmounts both mount points,
+    /// reporting how much data is still cached for the drive while `sync` runs.
+    fn sync_and_unmount(
+        &self,
+        usb: &Arc<MountPoint>,
+        iso: &Arc<MountPoint>,
+        writes: &DriveWrites,
+        tx: &mpsc::Sender<FlashEvent>,
+    ) -> Result<(), FlashError> {
+        let drive = self.drive.as_str();
+        let copied = directory_size(&self.tools(), &usb.as_arg()).unwrap_or(0.0) as u64;
+        let initial_pending = writes.pending(copied).unwrap_or(0).max(1) as f64;
+        let usb_t = usb.clone();
+        let iso_t = iso.clone();
+        let unmount_done = Arc::new(Mutex::new(false));
+        let unmount_error = Arc::new(Mutex::new(None));
+        let unmount_done_t = unmount_done.clone();
+        let unmount_err_t = unmount_error.clone();
+        let drive_check = drive.to_string();
+        let runner_t = self.tools();
+        // A lazy unmount can leave the superblock and its cached pages alive,
+        // which the read-only remount for verifying would then read back.
+        let lazy = !self.verify;
+
+        thread::spawn(move || {
+            let runner = &runner_t;
+            let result = run_tool(runner, Invocation::new("sync"))
+                .map_err(FlashError::Sync)
+                .and_then(|_| usb_t.unmount(runner, lazy).map_err(FlashError::Unmount))
+                .and_then(|_| iso_t.unmount(runner, true).map_err(FlashError::Unmount));
+
+            if !device_exists(&drive_check) {
+                *unmount_err_t.lock().unwrap() =
+                    Some(FlashError::DriveDisconnected(Phase::Syncing));
+            } else if let Err(err) = result {
+                *unmount_err_t.lock().unwrap() = Some(err);
+            }
+            *unmount_done_t.lock().unwrap() = true;
+        });
+
+        let mut meter = RateMeter::new(RATE_WINDOW);
+        let mut spin_idx = 0;
+        let spinners = ["-", "\\", "|", "/"];
+        loop {
+            if *unmount_done.lock().unwrap() {
+                break;
+            }
+
+            if let Some(err) = unmount_error.lock().unwrap().clone() {
+                return Err(err);
+            }
+
+            if !device_exists(drive) {
+                return Err(FlashError::DriveDisconnected(Phase::Syncing));
+            }
+
+            let pending = writes.pending(copied).unwrap_or(0) as f64;
+            let sync_progress = 0.80 + ((1.0 - (pending / initial_pending)).max(0.0) * 0.19);
+            let flushed = (initial_pending - pending).max(0.0) as u64;
+            let rate = meter.record(flushed);
+
+            if pending <= 10.0 * 1024.0 * 1024.0 {
+                spin_idx = (spin_idx + 1) % spinners.len();
+                send_progress(
+                    tx,
+                    Progress::new(
+                        Phase::Syncing,
+                        format!("Finishing writes... {}", spinners[spin_idx]),
+                        0.99,
+                    )
+                    .bytes(flushed, Some(initial_pending as u64))
+                    .rate(rate),
+                );
+            } else {
+                let mb_left = pending / 1024.0 / 1024.0;
+                send_progress(
+                    tx,
+                    Progress::new(
+                        Phase::Syncing,
+                        format!("Flushing cache: {:.1} MB left", mb_left),
+                        sync_progress.min(0.99),
+                    )
+                    .bytes(flushed, Some(initial_pending as u64))
+                    .rate(rate),
+                );
+            }
+            thread::sleep(Duration::from_millis(200));
+        }
+
+        let err = unmount_error.lock().unwrap().take();
+        err.map_or(Ok(()), Err)
+    }
+}
+
+fn send_progress(tx: &mpsc::Sender<FlashEvent>, progress: Progress) {
+    let _ = tx.send(FlashEvent::Progress(progress));
+}
+
+fn ratio(done: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        (done as f64 / total as f64).min(1.0)
+    }
+}
+
+fn mb(bytes: u64) -> f64 {
+    bytes as f64 / 1024.0 / 1024.0
+}
+
+fn directory_size(runner: &dyn CommandRunner, path: &str) -> Option<f64> {
+    let out = runner
+        .run(&Invocation::new("du").args(["-sb", path]))
+        .ok()?;
+    out.stdout.split_whitespace().next()?.parse::<f64>().ok()
+}
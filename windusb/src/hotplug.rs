@@ -0,0 +1,229 @@
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Kernel uevent multicast group (as opposed to group 2, re-broadcast by udev).
+const KERNEL_GROUP: u32 = 1;
+
+/// Events arriving this soon after a disk change are folded into one
+/// notification, which also gives udev time to create the device node.
+const SETTLE_DELAY: Duration = Duration::from_millis(750);
+
+/// Longest wait between two checks for a device node, in case an inotify
+/// event is missed or the directory cannot be watched.
+const NODE_RECHECK: Duration = Duration::from_millis(250);
+
+/// Kernel device event as broadcast over `NETLINK_KOBJECT_UEVENT`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UEvent {
+    pub action: String,
+    pub subsystem: String,
+    /// Kernel name of the device, e.g. `sdb`.
+    pub devname: Option<String>,
+    /// `disk` or `partition` for block devices.
+    pub devtype: Option<String>,
+}
+
+impl UEvent {
+    /// Parses a raw kernel uevent: an `action@devpath` header followed by
+    /// NUL-separated `KEY=value` pairs.
+    pub fn parse(buf: &[u8]) -> Option<UEvent> {
+        let mut fields = buf.split(|&b| b == 0).filter(|f| !f.is_empty());
+        let header = String::from_utf8_lossy(fields.next()?);
+        if !header.contains('@') {
+            return None;
+        }
+        let mut event = UEvent::default();
+        for field in fields {
+            let field = String::from_utf8_lossy(field);
+            let Some((key, value)) = field.split_once('=') else {
+                continue;
+            };
+            match key {
+                "ACTION" => event.action = value.to_string(),
+                "SUBSYSTEM" => event.subsystem = value.to_string(),
+                "DEVNAME" => {
+                    event.devname = Some(value.rsplit('/').next().unwrap_or(value).to_string())
+                }
+                "DEVTYPE" => event.devtype = Some(value.to_string()),
+                _ => {}
+            }
+        }
+        if event.action.is_empty() {
+            return None;
+        }
+        Some(event)
+    }
+
+    /// Device node path, e.g. `/dev/sdb`.
+    pub fn device_path(&self) -> Option<String> {
+        self.devname.as_ref().map(|name| format!("/dev/{}", name))
+    }
+
+    /// A whole disk appeared, vanished or changed media.
+    pub fn is_disk_change(&self) -> bool {
+        self.subsystem == "block"
+            && self.devtype.as_deref() == Some("disk")
+            && matches!(self.action.as_str(), "add" | "remove" | "change")
+    }
+}
+
+/// Netlink socket subscribed to kernel uevents.
+pub struct UeventSocket {
+    fd: OwnedFd,
+}
+
+impl UeventSocket {
+    pub fn open() -> io::Result<Self> {
+        let raw = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_KOBJECT_UEVENT,
+            )
+        };
+        if raw < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+
+        let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        addr.nl_groups = KERNEL_GROUP;
+        let rc = unsafe {
+            libc::bind(
+                fd.as_raw_fd(),
+                &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(UeventSocket { fd })
+    }
+
+    /// Waits for the next uevent, or returns `Ok(None)` once `timeout` elapses.
+    /// Messages that are not uevents are skipped.
+    pub fn recv_timeout(&self, timeout: Option<Duration>) -> io::Result<Option<UEvent>> {
+        let timeout_ms = timeout.map_or(-1, |t| t.as_millis().min(i32::MAX as u128) as i32);
+        let mut buf = [0u8; 8192];
+        loop {
+            let mut pfd = libc::pollfd {
+                fd: self.fd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pfd, 1, timeout_ms) };
+            if ready < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if ready == 0 {
+                return Ok(None);
+            }
+            let len =
+                unsafe { libc::recv(self.fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len(), 0) };
+            if len < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Some(event) = UEvent::parse(&buf[..len as usize]) {
+                return Ok(Some(event));
+            }
+        }
+    }
+}
+
+/// Watches for disks being plugged or unplugged and sends one notification
+/// per burst of changes. The thread ends once the receiver is dropped.
+pub fn watch_drives(tx: mpsc::Sender<()>) -> io::Result<thread::JoinHandle<()>> {
+    let socket = UeventSocket::open()?;
+    Ok(thread::spawn(move || {
+        watch_drives_with(|timeout| socket.recv_timeout(timeout), &tx)
+    }))
+}
+
+/// Runs the loop of [`watch_drives`] on uevents from `recv`, which behaves
+/// like [`UeventSocket::recv_timeout`]. Returns once the receiver is dropped
+/// or `recv` fails for good. An overflowing receive buffer (`ENOBUFS`) means
+/// events were lost, so it is reported as a change rather than ending the watch.
+pub fn watch_drives_with<F>(mut recv: F, tx: &mpsc::Sender<()>)
+where
+    F: FnMut(Option<Duration>) -> io::Result<Option<UEvent>>,
+{
+    loop {
+        let changed = match recv(None) {
+            Ok(Some(event)) => event.is_disk_change(),
+            Ok(None) => false,
+            Err(err) if err.raw_os_error() == Some(libc::ENOBUFS) => true,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => false,
+            Err(_) => return,
+        };
+        if changed {
+            while let Ok(Some(_)) = recv(Some(SETTLE_DELAY)) {}
+            if tx.send(()).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Waits up to `timeout` for the device node at `path` to appear, woken by
+/// inotify events on its directory. Returns whether it exists.
+pub fn wait_for_node(path: &Path, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let watch = path.parent().and_then(|dir| watch_directory(dir).ok());
+    loop {
+        if path.exists() {
+            return true;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return false;
+        }
+        let wait = left.min(NODE_RECHECK);
+        match &watch {
+            Some(fd) => wait_for_entries(fd, wait),
+            None => thread::sleep(wait),
+        }
+    }
+}
+
+/// Inotify descriptor reporting entries created in, or moved into, `dir`.
+fn watch_directory(dir: &Path) -> io::Result<OwnedFd> {
+    let raw = unsafe { libc::inotify_init1(libc::IN_CLOEXEC | libc::IN_NONBLOCK) };
+    if raw < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(raw) };
+    let dir = CString::new(dir.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mask = libc::IN_CREATE | libc::IN_MOVED_TO | libc::IN_ATTRIB;
+    if unsafe { libc::inotify_add_watch(fd.as_raw_fd(), dir.as_ptr(), mask) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+/// Blocks until the inotify descriptor has events or `timeout` elapses,
+/// then discards them.
+fn wait_for_entries(fd: &OwnedFd, timeout: Duration) {
+    let mut pfd = libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    if unsafe { libc::poll(&mut pfd, 1, timeout.as_millis() as i32) } > 0 {
+        let mut buf = [0u8; 4096];
+        while unsafe { libc::read(fd.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len()) } > 0 {}
+    }
+}
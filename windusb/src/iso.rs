@@ -0,0 +1,182 @@
+use std::path::Path;
+
+use crate::isofs::IsoImage;
+use crate::runner::{CommandRunner, Invocation, SystemRunner};
+use crate::wim::{self, Edition};
+
+/// Container format of the Windows installation image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Wim,
+    Esd,
+}
+
+/// Location of the installation image inside a Windows ISO.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InstallImage {
+    pub format: ImageFormat,
+}
+
+impl InstallImage {
+    /// Path of the image relative to the ISO root.
+    pub fn path(&self) -> &'static str {
+        match self.format {
+            ImageFormat::Wim => "sources/install.wim",
+            ImageFormat::Esd => "sources/install.esd",
+        }
+    }
+
+    /// File name of the image, used to exclude it from the bulk copy.
+    pub fn file_name(&self) -> &'static str {
+        match self.format {
+            ImageFormat::Wim => "install.wim",
+            ImageFormat::Esd => "install.esd",
+        }
+    }
+
+    /// Extension of the pieces written by `wimlib-imagex split`.
+    pub fn split_extension(&self) -> &'static str {
+        match self.format {
+            ImageFormat::Wim => "swm",
+            ImageFormat::Esd => "esd",
+        }
+    }
+}
+
+/// A file or directory inside the ISO.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IsoEntry {
+    /// Path relative to the ISO root, e.g. `sources/boot.wim`.
+    pub path: String,
+    /// Size in bytes; zero for directories.
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+/// Looks for `sources/install.wim` or `sources/install.esd` in the ISO.
+pub fn find_install_image(iso: &Path) -> Option<InstallImage> {
+    find_install_image_with(&SystemRunner, iso)
+}
+
+/// Like [`find_install_image`], falling back to `7z` through `runner` when
+/// the ISO cannot be read natively.
+pub fn find_install_image_with(runner: &dyn CommandRunner, iso: &Path) -> Option<InstallImage> {
+    inspect(runner, iso)?.image
+}
+
+/// Lists every file and directory in the ISO.
+pub fn list_iso_with(runner: &dyn CommandRunner, iso: &Path) -> Option<Vec<IsoEntry>> {
+    inspect(runner, iso).map(|contents| contents.entries)
+}
+
+/// Editions in the ISO's install image, read from the WIM XML metadata.
+/// `None` if the ISO cannot be read natively or the metadata is unreadable,
+/// as with encrypted ESDs.
+pub fn list_editions(iso: &Path) -> Option<Vec<Edition>> {
+    let image = IsoImage::open(iso).ok()?;
+    let file = [ImageFormat::Wim, ImageFormat::Esd]
+        .into_iter()
+        .find_map(|format| image.lookup(InstallImage { format }.path()).ok())?;
+    wim::read_editions(&mut image.reader(&file)).ok()
+}
+
+/// What the flasher needs to know about an ISO.
+pub(crate) struct Contents {
+    pub image: Option<InstallImage>,
+    pub entries: Vec<IsoEntry>,
+}
+
+/// Reads the ISO in-process, or through `7z l` for images the native reader
+/// does not understand.
+pub(crate) fn inspect(runner: &dyn CommandRunner, iso: &Path) -> Option<Contents> {
+    if let Ok(entries) = IsoImage::open(iso).and_then(|image| image.entries()) {
+        let image = [ImageFormat::Wim, ImageFormat::Esd]
+            .into_iter()
+            .map(|format| InstallImage { format })
+            .find(|image| {
+                entries
+                    .iter()
+                    .any(|e| !e.is_dir && e.path.eq_ignore_ascii_case(image.path()))
+            });
+        return Some(Contents { image, entries });
+    }
+    let stdout = listing(runner, iso)?;
+    Some(Contents {
+        image: detect_install_image(&stdout),
+        entries: parse_listing(&stdout),
+    })
+}
+
+/// Raw `7z l` output for the ISO.
+fn listing(runner: &dyn CommandRunner, iso: &Path) -> Option<String> {
+    let out = runner
+        .run(&Invocation::new("7z").arg("l").arg(iso.to_string_lossy()))
+        .ok()?;
+    Some(out.stdout)
+}
+
+/// Parses the table printed by `7z l`. Column positions are taken from the
+/// dashed rule under the header, so names containing spaces survive; output
+/// without that rule yields no entries.
+pub fn parse_listing(stdout: &str) -> Vec<IsoEntry> {
+    let mut lines = stdout.lines().skip_while(|l| !l.starts_with("-----"));
+    let Some(rule) = lines.next() else {
+        return Vec::new();
+    };
+    let mut columns = Vec::new();
+    let mut start = None;
+    for (i, c) in rule.char_indices().chain([(rule.len(), ' ')]) {
+        match (c, start) {
+            ('-', None) => start = Some(i),
+            (' ', Some(s)) => {
+                columns.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    let [_, attr, size, _, name] = columns[..] else {
+        return Vec::new();
+    };
+    let field = |line: &str, (from, to): (usize, usize)| -> String {
+        line.get(from..to.min(line.len()))
+            .unwrap_or("")
+            .trim()
+            .to_string()
+    };
+
+    lines
+        .take_while(|l| !l.starts_with("-----"))
+        .filter_map(|line| {
+            let path = line.get(name.0..)?.trim();
+            if path.is_empty() {
+                return None;
+            }
+            Some(IsoEntry {
+                path: path.replace('\\', "/"),
+                size: field(line, size).parse().unwrap_or(0),
+                is_dir: field(line, attr).starts_with('D'),
+            })
+        })
+        .collect()
+}
+
+fn detect_install_image(listing: &str) -> Option<InstallImage> {
+    let stdout = listing.to_lowercase();
+    if stdout.contains("sources/install.wim") {
+        Some(InstallImage {
+            format: ImageFormat::Wim,
+        })
+    } else if stdout.contains("sources/install.esd") {
+        Some(InstallImage {
+            format: ImageFormat::Esd,
+        })
+    } else {
+        None
+    }
+}
+
+/// Returns `true` if the ISO carries a Windows installation image.
+pub fn is_valid_windows_iso(iso: &Path) -> bool {
+    find_install_image(iso).is_some()
+}
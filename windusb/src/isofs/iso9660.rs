@@ -0,0 +1,155 @@
+use std::fs::File;
+use std::io;
+
+use super::{invalid, is_safe_name, le32, read_block, utf16_be, Extent, IsoFile, SECTOR};
+
+/// Volume descriptors start at sector 16.
+const FIRST_DESCRIPTOR: u64 = 16;
+
+const PRIMARY: u8 = 1;
+const SUPPLEMENTARY: u8 = 2;
+const TERMINATOR: u8 = 255;
+
+const FLAG_DIRECTORY: u8 = 0x02;
+/// More records for the same file follow (files above 4 GiB).
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+/// The directory tree of an ISO 9660 volume, Joliet if available.
+pub(super) struct Volume {
+    pub joliet: bool,
+    pub root: IsoFile,
+}
+
+/// One directory record.
+struct Record {
+    file: IsoFile,
+    multi_extent: bool,
+}
+
+impl Volume {
+    pub fn open(file: &File) -> io::Result<Self> {
+        let mut primary = None;
+        let mut joliet = None;
+        for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + 64 {
+            let desc = read_block(file, sector * SECTOR, SECTOR as usize)?;
+            if &desc[1..6] != b"CD001" {
+                break;
+            }
+            match desc[0] {
+                PRIMARY if primary.is_none() => primary = Some(desc),
+                SUPPLEMENTARY if joliet.is_none() && is_joliet(&desc) => joliet = Some(desc),
+                TERMINATOR => break,
+                _ => {}
+            }
+        }
+        let (desc, joliet) = match (joliet, primary) {
+            (Some(desc), _) => (desc, true),
+            (None, Some(desc)) => (desc, false),
+            (None, None) => return Err(invalid("not an ISO 9660 image")),
+        };
+        let mut root = parse_record(&desc[156..190], joliet)
+            .ok_or_else(|| invalid("malformed root directory record"))?
+            .file;
+        root.name.clear();
+        Ok(Volume { joliet, root })
+    }
+
+    /// Children of a directory, given its raw contents.
+    pub fn parse_dir(&self, data: &[u8]) -> io::Result<Vec<IsoFile>> {
+        let mut children: Vec<IsoFile> = Vec::new();
+        let mut continued = false;
+        for sector in data.chunks(SECTOR as usize) {
+            let mut pos = 0;
+            while pos < sector.len() {
+                let len = sector[pos] as usize;
+                // Records never span sectors; the rest of this one is padding.
+                if len == 0 || pos + len > sector.len() {
+                    break;
+                }
+                let record = parse_record(&sector[pos..pos + len], self.joliet)
+                    .ok_or_else(|| invalid("malformed directory record"))?;
+                pos += len;
+                // Also drops the "." and ".." records of every directory.
+                if !is_safe_name(&record.file.name) {
+                    continue;
+                }
+                match children.last_mut() {
+                    Some(last) if continued && last.name == record.file.name => {
+                        last.size += record.file.size;
+                        last.extents.extend(record.file.extents);
+                    }
+                    _ => children.push(record.file),
+                }
+                continued = record.multi_extent;
+            }
+        }
+        Ok(children)
+    }
+}
+
+/// A supplementary descriptor is Joliet when its escape sequence names UCS-2 level 1, 2 or 3.
+fn is_joliet(desc: &[u8]) -> bool {
+    desc[88] == 0x25 && desc[89] == 0x2F && matches!(desc[90], 0x40 | 0x43 | 0x45)
+}
+
+fn parse_record(rec: &[u8], joliet: bool) -> Option<Record> {
+    if rec.len() < 34 {
+        return None;
+    }
+    let ext_attr = rec[1] as u64;
+    let lba = le32(rec, 2) as u64;
+    let size = le32(rec, 10) as u64;
+    let flags = rec[25];
+    let name_len = rec[32] as usize;
+    let raw = rec.get(33..33 + name_len)?;
+
+    let name = match raw {
+        [0] => ".".to_string(),
+        [1] => "..".to_string(),
+        _ if joliet => strip_version(utf16_be(raw)),
+        _ => rock_ridge_name(rec, name_len).unwrap_or_else(|| {
+            let name = strip_version(raw.iter().map(|&b| b as char).collect());
+            name.strip_suffix('.').map(str::to_string).unwrap_or(name)
+        }),
+    };
+
+    let extents = if size > 0 {
+        vec![Extent::Disk {
+            offset: (lba + ext_attr) * SECTOR,
+            len: size,
+        }]
+    } else {
+        Vec::new()
+    };
+    Some(Record {
+        file: IsoFile::new(name, size, flags & FLAG_DIRECTORY != 0, extents),
+        multi_extent: flags & FLAG_MULTI_EXTENT != 0,
+    })
+}
+
+/// Drops the `;1` file version suffix.
+fn strip_version(mut name: String) -> String {
+    if let Some(version) = name.rfind(';') {
+        name.truncate(version);
+    }
+    name
+}
+
+/// Alternate name from the Rock Ridge `NM` entries in the system use area.
+fn rock_ridge_name(rec: &[u8], name_len: usize) -> Option<String> {
+    // The file identifier is padded to an even offset.
+    let mut pos = 33 + name_len + (1 - name_len % 2);
+    let mut name = Vec::new();
+    while pos + 4 <= rec.len() {
+        let len = rec[pos + 2] as usize;
+        if len < 4 || pos + len > rec.len() {
+            break;
+        }
+        // NM flags: 0x02 current directory, 0x04 parent; neither carries a name.
+        if &rec[pos..pos + 2] == b"NM" && len > 5 && rec[pos + 4] & 0x06 == 0 {
+            name.extend_from_slice(&rec[pos + 5..pos + len]);
+        }
+        pos += len;
+    }
+    (!name.is_empty()).then(|| String::from_utf8_lossy(&name).into_owned())
+}
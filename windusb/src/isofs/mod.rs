@@ -0,0 +1,360 @@
+//! In-process reader for optical disc images.
+//!
+//! Understands UDF 1.02–2.60 (including the metadata partition introduced in
+//! 2.50), ISO 9660 with Joliet, and plain ISO 9660 with Rock Ridge names.
+//! Windows ISOs are UDF bridge discs whose ISO 9660 tree only holds a
+//! README, so UDF is preferred whenever it is present.
+
+mod iso9660;
+mod udf;
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::iso::IsoEntry;
+
+/// Size of a CD-ROM sector, and of every descriptor block we read.
+pub(crate) const SECTOR: u64 = 2048;
+
+/// Copy buffer for extraction.
+const COPY_CHUNK: usize = 1024 * 1024;
+
+/// Directories larger than this are treated as corrupt; Windows media keep
+/// theirs well below a megabyte.
+const MAX_DIR_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Deeper trees, which are what a directory that contains one of its own
+/// ancestors unrolls into, are rejected.
+const MAX_DEPTH: usize = 64;
+
+/// Entries a walk gives up after, bounding trees that list the same
+/// directories over and over.
+const MAX_ENTRIES: usize = 1_000_000;
+
+/// Which directory tree of the image is being read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IsoFormat {
+    Udf,
+    Joliet,
+    Iso9660,
+}
+
+/// Where a run of file bytes lives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Extent {
+    /// `len` bytes starting at byte `offset` of the image.
+    Disk { offset: u64, len: u64 },
+    /// Allocated but unrecorded; reads as zeros.
+    Sparse(u64),
+    /// Data embedded in the UDF file entry itself.
+    Inline(Arc<[u8]>),
+}
+
+impl Extent {
+    fn len(&self) -> u64 {
+        match self {
+            Extent::Disk { len, .. } | Extent::Sparse(len) => *len,
+            Extent::Inline(data) => data.len() as u64,
+        }
+    }
+}
+
+/// A file or directory in the image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IsoFile {
+    pub name: String,
+    pub size: u64,
+    pub is_dir: bool,
+    extents: Vec<Extent>,
+}
+
+impl IsoFile {
+    pub(crate) fn new(name: String, size: u64, is_dir: bool, extents: Vec<Extent>) -> Self {
+        IsoFile {
+            name,
+            size,
+            is_dir,
+            extents,
+        }
+    }
+}
+
+enum Backend {
+    Iso9660(iso9660::Volume),
+    Udf(udf::Volume),
+}
+
+/// An opened ISO image.
+pub struct IsoImage {
+    file: File,
+    backend: Backend,
+}
+
+impl IsoImage {
+    /// Opens the image at `path`, picking the richest directory tree it carries.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let backend = match udf::Volume::open(&file)? {
+            Some(volume) => Backend::Udf(volume),
+            None => Backend::Iso9660(iso9660::Volume::open(&file)?),
+        };
+        Ok(IsoImage { file, backend })
+    }
+
+    pub fn format(&self) -> IsoFormat {
+        match &self.backend {
+            Backend::Udf(_) => IsoFormat::Udf,
+            Backend::Iso9660(volume) if volume.joliet => IsoFormat::Joliet,
+            Backend::Iso9660(_) => IsoFormat::Iso9660,
+        }
+    }
+
+    pub fn root(&self) -> IsoFile {
+        match &self.backend {
+            Backend::Iso9660(volume) => volume.root.clone(),
+            Backend::Udf(volume) => volume.root.clone(),
+        }
+    }
+
+    /// Lists the children of `dir`.
+    pub fn read_dir(&self, dir: &IsoFile) -> io::Result<Vec<IsoFile>> {
+        if !dir.is_dir {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", dir.name),
+            ));
+        }
+        if dir.size > MAX_DIR_SIZE {
+            return Err(invalid(format!(
+                "directory {} is implausibly large ({} bytes)",
+                dir.name, dir.size
+            )));
+        }
+        let mut data = Vec::with_capacity(dir.size as usize);
+        self.reader(dir).read_to_end(&mut data)?;
+        match &self.backend {
+            Backend::Iso9660(volume) => volume.parse_dir(&data),
+            Backend::Udf(volume) => volume.parse_dir(&self.file, &data),
+        }
+    }
+
+    /// Finds a file by its `/`-separated path, ignoring case.
+    pub fn lookup(&self, path: &str) -> io::Result<IsoFile> {
+        let mut current = self.root();
+        for part in path.split('/').filter(|p| !p.is_empty()) {
+            current = self
+                .read_dir(&current)?
+                .into_iter()
+                .find(|child| child.name.eq_ignore_ascii_case(part))
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path))
+                })?;
+        }
+        Ok(current)
+    }
+
+    /// Every file and directory below the root, parents before children.
+    pub fn walk(&self) -> io::Result<Vec<(String, IsoFile)>> {
+        let mut found = Vec::new();
+        let mut pending = vec![(String::new(), self.root())];
+        while let Some((prefix, dir)) = pending.pop() {
+            if prefix.matches('/').count() >= MAX_DEPTH {
+                return Err(invalid(format!("{} is nested too deeply", prefix)));
+            }
+            if found.len() > MAX_ENTRIES {
+                return Err(invalid("directory tree lists too many entries"));
+            }
+            let mut children = self.read_dir(&dir)?;
+            children.sort_by(|a, b| a.name.cmp(&b.name));
+            for child in children {
+                let path = if prefix.is_empty() {
+                    child.name.clone()
+                } else {
+                    format!("{}/{}", prefix, child.name)
+                };
+                if child.is_dir {
+                    pending.push((path.clone(), child.clone()));
+                }
+                found.push((path, child));
+            }
+        }
+        Ok(found)
+    }
+
+    /// Flat listing of the whole image.
+    pub fn entries(&self) -> io::Result<Vec<IsoEntry>> {
+        Ok(self
+            .walk()?
+            .into_iter()
+            .map(|(path, file)| IsoEntry {
+                path,
+                size: file.size,
+                is_dir: file.is_dir,
+            })
+            .collect())
+    }
+
+    /// Streams the contents of `file`.
+    pub fn reader<'a>(&'a self, file: &'a IsoFile) -> FileReader<'a> {
+        FileReader {
+            image: &self.file,
+            extents: &file.extents,
+            size: file.size,
+            index: 0,
+            offset: 0,
+            remaining: file.size,
+        }
+    }
+
+    /// Copies every file `include` accepts below `dest`, creating directories
+    /// as needed. `progress` receives the path being written and the bytes
+    /// written so far; the total is returned.
+    pub fn extract(
+        &self,
+        dest: &Path,
+        include: impl Fn(&str) -> bool,
+        mut progress: impl FnMut(&str, u64),
+    ) -> io::Result<u64> {
+        let mut written = 0;
+        let mut buf = vec![0; COPY_CHUNK];
+        for (path, file) in self.walk()? {
+            if !include(&path) {
+                continue;
+            }
+            let target = dest.join(&path);
+            let annotate =
+                |err: io::Error| io::Error::new(err.kind(), format!("{}: {}", path, err));
+            if file.is_dir {
+                fs::create_dir_all(&target).map_err(annotate)?;
+                continue;
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).map_err(annotate)?;
+            }
+            let mut out = File::create(&target).map_err(annotate)?;
+            let mut reader = self.reader(&file);
+            loop {
+                let n = reader.read(&mut buf).map_err(annotate)?;
+                if n == 0 {
+                    break;
+                }
+                out.write_all(&buf[..n]).map_err(annotate)?;
+                written += n as u64;
+                progress(&path, written);
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// Reader over a file's extents.
+pub struct FileReader<'a> {
+    image: &'a File,
+    extents: &'a [Extent],
+    size: u64,
+    index: usize,
+    /// Position inside `extents[index]`.
+    offset: u64,
+    remaining: u64,
+}
+
+impl Read for FileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.remaining > 0 {
+            let Some(extent) = self.extents.get(self.index) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "file is shorter than its recorded size",
+                ));
+            };
+            let left = extent.len() - self.offset;
+            if left == 0 {
+                self.index += 1;
+                self.offset = 0;
+                continue;
+            }
+            let n = (buf.len() as u64).min(left).min(self.remaining) as usize;
+            match extent {
+                Extent::Disk { offset, .. } => self
+                    .image
+                    .read_exact_at(&mut buf[..n], offset + self.offset)?,
+                Extent::Sparse(_) => buf[..n].fill(0),
+                Extent::Inline(data) => {
+                    let start = self.offset as usize;
+                    buf[..n].copy_from_slice(&data[start..start + n]);
+                }
+            }
+            self.offset += n as u64;
+            self.remaining -= n as u64;
+            return Ok(n);
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for FileReader<'_> {
+    /// Positions past the end are clamped to the end of the file.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let current = self.size - self.remaining;
+        let target = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => current.checked_add_signed(delta),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file"))?
+        .min(self.size);
+
+        self.remaining = self.size - target;
+        self.index = 0;
+        self.offset = target;
+        while let Some(extent) = self.extents.get(self.index) {
+            if self.offset < extent.len() {
+                break;
+            }
+            self.offset -= extent.len();
+            self.index += 1;
+        }
+        Ok(target)
+    }
+}
+
+pub(crate) fn read_block(file: &File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    file.read_exact_at(&mut buf, offset)?;
+    Ok(buf)
+}
+
+pub(crate) fn le16(b: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([b[at], b[at + 1]])
+}
+
+pub(crate) fn le32(b: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+}
+
+pub(crate) fn le64(b: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+}
+
+pub(crate) fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Whether a name read from the image can be used as one path component:
+/// anything that is empty, "." or "..", or carries a `/` or NUL would let the
+/// image place files outside the directory it is extracted into.
+pub(crate) fn is_safe_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..") && !name.contains(['/', '\0'])
+}
+
+/// Decodes big-endian UCS-2 as used by Joliet and UDF.
+pub(crate) fn utf16_be(bytes: &[u8]) -> String {
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|c| u16::from_be_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
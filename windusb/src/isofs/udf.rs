@@ -0,0 +1,379 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io;
+use std::sync::Arc;
+
+use super::{
+    invalid, is_safe_name, le16, le32, le64, read_block, utf16_be, Extent, IsoFile, SECTOR,
+};
+
+/// The anchor volume descriptor pointer always sits at sector 256.
+const ANCHOR_SECTOR: u64 = 256;
+
+// Descriptor tag identifiers (ECMA-167 3/7.2.1 and 4/7.2.1).
+const TAG_ANCHOR: u16 = 2;
+const TAG_PARTITION: u16 = 5;
+const TAG_LOGICAL_VOLUME: u16 = 6;
+const TAG_TERMINATING: u16 = 8;
+const TAG_FILE_SET: u16 = 256;
+const TAG_FILE_IDENTIFIER: u16 = 257;
+const TAG_ALLOCATION_EXTENT: u16 = 258;
+const TAG_FILE_ENTRY: u16 = 261;
+const TAG_EXTENDED_FILE_ENTRY: u16 = 266;
+
+const FILE_TYPE_DIRECTORY: u8 = 4;
+
+/// Partition maps that fit in a logical volume descriptor; each is at least
+/// six bytes long.
+const MAX_PARTITION_MAPS: usize = (SECTOR as usize - 440) / 6;
+
+// File characteristics of a file identifier descriptor.
+const FID_DIRECTORY: u8 = 0x02;
+const FID_DELETED: u8 = 0x04;
+const FID_PARENT: u8 = 0x08;
+
+/// How a partition reference number maps onto the disc.
+enum PartitionMap {
+    /// Blocks are relative to the start sector of a physical partition.
+    Physical { start: u64 },
+    /// UDF 2.50+: blocks are offsets into the metadata file.
+    Metadata { extents: Vec<Extent> },
+}
+
+/// A UDF logical volume.
+pub(super) struct Volume {
+    block_size: u64,
+    maps: Vec<PartitionMap>,
+    pub root: IsoFile,
+}
+
+/// `long_ad`: a block in a given partition.
+#[derive(Debug, Clone, Copy)]
+struct LongAd {
+    len: u32,
+    block: u32,
+    partition: u16,
+}
+
+impl LongAd {
+    fn parse(b: &[u8]) -> Self {
+        LongAd {
+            len: le32(b, 0),
+            block: le32(b, 4),
+            partition: le16(b, 8),
+        }
+    }
+}
+
+impl Volume {
+    /// Reads the UDF volume, or `None` if the image carries no UDF descriptors.
+    pub fn open(file: &File) -> io::Result<Option<Self>> {
+        if !has_nsr_descriptor(file)? {
+            return Ok(None);
+        }
+        let anchor = read_block(file, ANCHOR_SECTOR * SECTOR, SECTOR as usize)?;
+        check_tag(&anchor, TAG_ANCHOR)?;
+        let vds_len = le32(&anchor, 16) as u64;
+        let vds_start = le32(&anchor, 20) as u64;
+
+        let mut partitions = Vec::new();
+        let mut logical_volume = None;
+        for sector in vds_start..vds_start + vds_len.div_ceil(SECTOR) {
+            let desc = read_block(file, sector * SECTOR, SECTOR as usize)?;
+            match le16(&desc, 0) {
+                TAG_PARTITION => partitions.push((le16(&desc, 22), le32(&desc, 188) as u64)),
+                TAG_LOGICAL_VOLUME if logical_volume.is_none() => logical_volume = Some(desc),
+                TAG_TERMINATING => break,
+                _ => {}
+            }
+        }
+        let lvd = logical_volume.ok_or_else(|| invalid("UDF logical volume descriptor missing"))?;
+        let block_size = le32(&lvd, 212) as u64;
+        if block_size != SECTOR {
+            return Err(invalid(format!(
+                "unsupported UDF block size {}",
+                block_size
+            )));
+        }
+        let file_set = LongAd::parse(&lvd[248..264]);
+
+        let physical_start = |number: u16| -> io::Result<u64> {
+            partitions
+                .iter()
+                .find(|(n, _)| *n == number)
+                .map(|&(_, start)| start)
+                .ok_or_else(|| invalid(format!("UDF partition {} missing", number)))
+        };
+
+        let map_count = le32(&lvd, 268) as usize;
+        let mut volume = Volume {
+            block_size,
+            maps: Vec::with_capacity(map_count.min(MAX_PARTITION_MAPS)),
+            root: IsoFile::new(String::new(), 0, true, Vec::new()),
+        };
+        let mut pos = 440;
+        for _ in 0..map_count {
+            let map_len = lvd.get(pos + 1).copied().unwrap_or(0) as usize;
+            let map = lvd
+                .get(pos..pos + map_len)
+                .filter(|map| map.len() >= 6)
+                .ok_or_else(|| invalid("malformed UDF partition map"))?;
+            match map[0] {
+                1 => volume.maps.push(PartitionMap::Physical {
+                    start: physical_start(le16(map, 4))?,
+                }),
+                2 if map[5..].starts_with(b"*UDF Metadata Partition") => {
+                    let start = physical_start(le16(map, 38))?;
+                    let entry_block = le32(map, 40) as u64;
+                    let physical = Volume {
+                        block_size,
+                        maps: vec![PartitionMap::Physical { start }],
+                        root: volume.root.clone(),
+                    };
+                    let metadata = physical.file_entry(
+                        file,
+                        LongAd {
+                            len: SECTOR as u32,
+                            block: entry_block as u32,
+                            partition: 0,
+                        },
+                        String::new(),
+                    )?;
+                    volume.maps.push(PartitionMap::Metadata {
+                        extents: metadata.extents,
+                    });
+                }
+                // Sparable and other type 2 maps read like the physical partition they sit on.
+                2 => volume.maps.push(PartitionMap::Physical {
+                    start: physical_start(le16(map, 38))?,
+                }),
+                other => return Err(invalid(format!("unknown UDF partition map type {}", other))),
+            }
+            pos += map_len;
+        }
+
+        let fsd = volume.read_logical(file, file_set)?;
+        check_tag(&fsd, TAG_FILE_SET)?;
+        volume.root = volume.file_entry(file, LongAd::parse(&fsd[400..416]), String::new())?;
+        Ok(Some(volume))
+    }
+
+    /// Children of a directory, given its raw contents.
+    pub fn parse_dir(&self, file: &File, data: &[u8]) -> io::Result<Vec<IsoFile>> {
+        let mut children = Vec::new();
+        let mut pos = 0;
+        while pos + 38 <= data.len() {
+            let fid = &data[pos..];
+            check_tag(fid, TAG_FILE_IDENTIFIER)?;
+            let characteristics = fid[18];
+            let name_len = fid[19] as usize;
+            let icb = LongAd::parse(&fid[20..36]);
+            let impl_len = le16(fid, 36) as usize;
+            let name_start = 38 + impl_len;
+            let raw_name = fid
+                .get(name_start..name_start + name_len)
+                .ok_or_else(|| invalid("truncated UDF file identifier"))?;
+            pos += (name_start + name_len).div_ceil(4) * 4;
+
+            let name = decode_dstring(raw_name);
+            if characteristics & (FID_PARENT | FID_DELETED) != 0 || !is_safe_name(&name) {
+                continue;
+            }
+            let mut child = self.file_entry(file, icb, name)?;
+            child.is_dir |= characteristics & FID_DIRECTORY != 0;
+            children.push(child);
+        }
+        Ok(children)
+    }
+
+    /// Reads the (extended) file entry at `icb` and resolves its allocation descriptors.
+    fn file_entry(&self, file: &File, icb: LongAd, name: String) -> io::Result<IsoFile> {
+        let entry = self.read_logical(file, icb)?;
+        let (ea_len_at, fixed_len) = match le16(&entry, 0) {
+            TAG_FILE_ENTRY => (168, 176),
+            TAG_EXTENDED_FILE_ENTRY => (208, 216),
+            other => {
+                return Err(invalid(format!(
+                    "expected a UDF file entry, found tag {}",
+                    other
+                )))
+            }
+        };
+        let is_dir = entry[16 + 11] == FILE_TYPE_DIRECTORY;
+        let ad_type = le16(&entry, 16 + 18) & 0x07;
+        let size = le64(&entry, 56);
+        let ea_len = le32(&entry, ea_len_at) as usize;
+        let ad_len = le32(&entry, ea_len_at + 4) as usize;
+        let ads = entry
+            .get(fixed_len + ea_len..fixed_len + ea_len + ad_len)
+            .ok_or_else(|| invalid("UDF allocation descriptors overrun the file entry"))?;
+
+        let extents = if ad_type == 3 {
+            vec![Extent::Inline(Arc::from(
+                &ads[..(size as usize).min(ads.len())],
+            ))]
+        } else {
+            self.allocation_extents(file, ads, ad_type, icb.partition, size)?
+        };
+        Ok(IsoFile::new(name, size, is_dir, extents))
+    }
+
+    /// Turns short or long allocation descriptors into image extents, following
+    /// allocation extent descriptors when the list continues elsewhere.
+    fn allocation_extents(
+        &self,
+        file: &File,
+        ads: &[u8],
+        ad_type: u16,
+        partition: u16,
+        size: u64,
+    ) -> io::Result<Vec<Extent>> {
+        let ad_size = match ad_type {
+            0 => 8,
+            1 => 16,
+            _ => {
+                return Err(invalid(
+                    "extended UDF allocation descriptors are not supported",
+                ))
+            }
+        };
+        let mut extents = Vec::new();
+        let mut remaining = size;
+        let mut list = ads.to_vec();
+        let mut pos = 0;
+        let mut followed = HashSet::new();
+        while pos + ad_size <= list.len() && remaining > 0 {
+            let raw_len = le32(&list, pos);
+            let ad = LongAd {
+                len: raw_len & 0x3FFF_FFFF,
+                block: le32(&list, pos + 4),
+                partition: if ad_type == 1 {
+                    le16(&list, pos + 8)
+                } else {
+                    partition
+                },
+            };
+            pos += ad_size;
+            if ad.len == 0 {
+                break;
+            }
+            match raw_len >> 30 {
+                // The descriptor list continues in an allocation extent descriptor.
+                3 => {
+                    if !followed.insert((ad.partition, ad.block)) {
+                        return Err(invalid("UDF allocation extents form a loop"));
+                    }
+                    let next = self.read_logical(file, ad)?;
+                    check_tag(&next, TAG_ALLOCATION_EXTENT)?;
+                    let len = le32(&next, 20) as usize;
+                    list = next
+                        .get(24..24 + len)
+                        .ok_or_else(|| invalid("truncated UDF allocation extent"))?
+                        .to_vec();
+                    pos = 0;
+                }
+                0 => {
+                    let len = (ad.len as u64).min(remaining);
+                    extents.extend(self.map_extent(ad.partition, ad.block, len)?);
+                    remaining -= len;
+                }
+                _ => {
+                    let len = (ad.len as u64).min(remaining);
+                    extents.push(Extent::Sparse(len));
+                    remaining -= len;
+                }
+            }
+        }
+        Ok(extents)
+    }
+
+    /// Maps `len` bytes starting at a logical block onto the image.
+    fn map_extent(&self, partition: u16, block: u32, len: u64) -> io::Result<Vec<Extent>> {
+        let map = self
+            .maps
+            .get(partition as usize)
+            .ok_or_else(|| invalid(format!("UDF partition reference {} missing", partition)))?;
+        match map {
+            PartitionMap::Physical { start } => Ok(vec![Extent::Disk {
+                offset: (start + block as u64) * self.block_size,
+                len,
+            }]),
+            PartitionMap::Metadata { extents } => {
+                let mut skip = block as u64 * self.block_size;
+                let mut left = len;
+                let mut mapped = Vec::new();
+                for extent in extents {
+                    if left == 0 {
+                        break;
+                    }
+                    let Extent::Disk { offset, len } = *extent else {
+                        return Err(invalid("UDF metadata file is not fully recorded"));
+                    };
+                    if skip >= len {
+                        skip -= len;
+                        continue;
+                    }
+                    let take = (len - skip).min(left);
+                    mapped.push(Extent::Disk {
+                        offset: offset + skip,
+                        len: take,
+                    });
+                    left -= take;
+                    skip = 0;
+                }
+                if left > 0 {
+                    return Err(invalid("UDF block lies beyond the metadata file"));
+                }
+                Ok(mapped)
+            }
+        }
+    }
+
+    /// Reads one logical block.
+    fn read_logical(&self, file: &File, ad: LongAd) -> io::Result<Vec<u8>> {
+        let mut block = Vec::with_capacity(self.block_size as usize);
+        for extent in self.map_extent(ad.partition, ad.block, self.block_size)? {
+            if let Extent::Disk { offset, len } = extent {
+                block.extend(read_block(file, offset, len as usize)?);
+            }
+        }
+        Ok(block)
+    }
+}
+
+/// Looks for the `NSR02`/`NSR03` volume structure that marks a UDF volume.
+fn has_nsr_descriptor(file: &File) -> io::Result<bool> {
+    for sector in 16..16 + 64 {
+        let desc = match read_block(file, sector * SECTOR, SECTOR as usize) {
+            Ok(desc) => desc,
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        match &desc[1..6] {
+            b"NSR02" | b"NSR03" => return Ok(true),
+            b"BEA01" | b"CD001" | b"CDW02" | b"BOOT2" => {}
+            _ => return Ok(false),
+        }
+    }
+    Ok(false)
+}
+
+fn check_tag(desc: &[u8], expected: u16) -> io::Result<()> {
+    let found = le16(desc, 0);
+    if found != expected {
+        return Err(invalid(format!(
+            "expected UDF descriptor tag {}, found {}",
+            expected, found
+        )));
+    }
+    Ok(())
+}
+
+/// Decodes an OSTA compressed unicode identifier.
+fn decode_dstring(raw: &[u8]) -> String {
+    match raw.split_first() {
+        Some((8, rest)) => rest.iter().map(|&b| b as char).collect(),
+        Some((16, rest)) => utf16_be(rest),
+        _ => String::new(),
+    }
+}
@@ -0,0 +1,94 @@
+//! How the stick is partitioned.
+
+use std::fmt;
+
+/// Partition table written to the stick.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PartitionScheme {
+    /// GPT only; boots on UEFI firmware.
+    #[default]
+    Gpt,
+    /// MBR with the partition marked active and Windows boot code, for
+    /// BIOS/CSM machines as well as UEFI.
+    Mbr,
+    /// GPT plus a hybrid MBR that mirrors the partition, marked active and
+    /// with Windows boot code, for firmware that wants either.
+    Hybrid,
+}
+
+impl PartitionScheme {
+    pub const ALL: [PartitionScheme; 3] = [
+        PartitionScheme::Gpt,
+        PartitionScheme::Mbr,
+        PartitionScheme::Hybrid,
+    ];
+
+    /// Name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            PartitionScheme::Gpt => "gpt",
+            PartitionScheme::Mbr => "mbr",
+            PartitionScheme::Hybrid => "hybrid",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        PartitionScheme::ALL
+            .into_iter()
+            .find(|scheme| scheme.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Whether the stick also starts from BIOS or CSM, not only UEFI.
+    pub fn boots_bios(self) -> bool {
+        self != PartitionScheme::Gpt
+    }
+}
+
+impl fmt::Display for PartitionScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PartitionScheme::Gpt => f.write_str("GPT (UEFI)"),
+            PartitionScheme::Mbr => f.write_str("MBR (BIOS and UEFI)"),
+            PartitionScheme::Hybrid => f.write_str("Hybrid GPT + MBR (BIOS and UEFI)"),
+        }
+    }
+}
+
+/// Filesystem the Windows files are written to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DriveLayout {
+    /// One FAT32 partition, with the install image split into `.swm` chunks
+    /// below the 4 GiB file limit.
+    #[default]
+    Fat32,
+    /// An NTFS partition holding the ISO contents unmodified, plus a small
+    /// FAT partition with the UEFI:NTFS driver that lets firmware boot it.
+    Ntfs,
+}
+
+impl DriveLayout {
+    pub const ALL: [DriveLayout; 2] = [DriveLayout::Fat32, DriveLayout::Ntfs];
+
+    /// Name used on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            DriveLayout::Fat32 => "fat32",
+            DriveLayout::Ntfs => "ntfs",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        DriveLayout::ALL
+            .into_iter()
+            .find(|layout| layout.name().eq_ignore_ascii_case(name))
+    }
+}
+
+impl fmt::Display for DriveLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriveLayout::Fat32 => f.write_str("FAT32 (install image split)"),
+            DriveLayout::Ntfs => f.write_str("NTFS + UEFI:NTFS (install image kept whole)"),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Flashing engine behind WindUSB-GUI.
+//!
+//! Enumerates USB drives, inspects Windows ISOs and writes them to a stick as a
+//! FAT32 or NTFS UEFI installer, reporting progress as typed [`FlashEvent`]s.
+//! The GTK front end is one consumer of this crate; provisioning tools can
+//! drive [`run_flasher`] directly.
+
+pub mod capacity;
+pub mod checksum;
+pub mod drives;
+pub mod error;
+pub mod event;
+pub mod flash;
+pub mod hotplug;
+pub mod iso;
+pub mod isofs;
+pub mod layout;
+pub mod mounts;
+pub mod platform;
+pub mod progress;
+pub mod runner;
+pub mod safety;
+pub mod stats;
+pub mod tools;
+pub mod unmount;
+pub mod verify;
+pub mod wim;
+pub mod writeback;
+
+pub use capacity::{check_iso_fits, SpaceCheck};
+pub use checksum::{verify_iso, Catalogue, ChecksumEvent, IsoHashes, KnownImage, Verdict};
+pub use drives::{
+    device_exists, list_block_devices_with, list_usb_drives, list_usb_drives_with, partition_path,
+    BlockDevice, Drive,
+};
+pub use error::{FlashError, ToolError};
+pub use event::{FlashEvent, Phase, Progress};
+pub use flash::{run_flasher, Flasher};
+pub use iso::{
+    find_install_image, find_install_image_with, is_valid_windows_iso, list_editions,
+    list_iso_with, ImageFormat, InstallImage, IsoEntry,
+};
+pub use isofs::{IsoFile, IsoFormat, IsoImage};
+pub use layout::{DriveLayout, PartitionScheme};
+pub use mounts::MountPoint;
+pub use platform::{detect_platform, Generation, PlatformInfo, PlatformWarning};
+pub use runner::{CommandRunner, Invocation, Output, SystemRunner};
+pub use safety::{drive_hazards, DiskInspector, Hazard};
+pub use stats::{FlashStats, PhaseTiming};
+pub use unmount::{release_drive, BusyMount, BusyProcess};
+pub use verify::{FileMismatch, Mismatch};
+pub use wim::{Architecture, Edition, WindowsVersion};
+pub use writeback::DeviceWriteback;
@@ -0,0 +1,255 @@
+//! Private mount points that are unmounted and removed however a run ends.
+//!
+//! Every [`MountPoint`] is a fresh `mkdtemp` directory named after the
+//! owning process. Dropping it, including while unwinding from a panic,
+//! unmounts whatever is still mounted there; [`release_all`] does the same
+//! from a signal handler, and [`sweep_stale`] cleans up after runs that
+//! died without either, along with the editions they were exporting.
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::error::ToolError;
+use crate::flash::DEFAULT_STAGING_DIR;
+use crate::runner::{run_tool, CommandRunner, Invocation, Output, SystemRunner};
+use crate::safety::unescape;
+
+/// Where mount points are created.
+const MOUNT_ROOT: &str = "/tmp";
+
+/// Name prefixes of our mount points; the owner's pid follows.
+const PREFIXES: [&str; 2] = ["windusb_usb_", "windusb_iso_"];
+
+/// Name prefix of the directories selected editions are exported into.
+const EXPORT_PREFIX: &str = "windusb_export_";
+
+/// Mount points of this process that have not been dropped yet.
+static ACTIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// A private directory for one mount, torn down on drop.
+pub struct MountPoint {
+    path: PathBuf,
+    /// Runs the teardown; unlike the pipeline's runner it is never cancelled.
+    runner: Arc<dyn CommandRunner>,
+    mounted: AtomicBool,
+}
+
+impl MountPoint {
+    /// Creates `/tmp/windusb_<kind>_<pid>_XXXXXX`, readable only by us.
+    pub fn create(kind: &str, runner: Arc<dyn CommandRunner>) -> io::Result<Self> {
+        MountPoint::create_in(Path::new(MOUNT_ROOT), kind, runner)
+    }
+
+    /// Like [`create`](Self::create) in another directory.
+    pub fn create_in(root: &Path, kind: &str, runner: Arc<dyn CommandRunner>) -> io::Result<Self> {
+        let path = private_dir(root, &format!("windusb_{}_", kind))?;
+        lock_active().push(path.clone());
+        Ok(MountPoint {
+            path,
+            runner,
+            mounted: AtomicBool::new(false),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path as a tool argument.
+    pub fn as_arg(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    /// Runs `mount` (an invocation without its target) onto this directory.
+    pub fn mount(
+        &self,
+        runner: &dyn CommandRunner,
+        mount: Invocation,
+    ) -> Result<Output, ToolError> {
+        let out = run_tool(runner, mount.arg(self.as_arg()))?;
+        self.mounted.store(true, Ordering::Relaxed);
+        Ok(out)
+    }
+
+    /// Unmounts the directory, detaching it lazily if `lazy` is set.
+    pub fn unmount(&self, runner: &dyn CommandRunner, lazy: bool) -> Result<Output, ToolError> {
+        let mut umount = Invocation::new("umount");
+        if lazy {
+            umount = umount.arg("-l");
+        }
+        let out = run_tool(runner, umount.arg(self.as_arg()))?;
+        self.mounted.store(false, Ordering::Relaxed);
+        Ok(out)
+    }
+
+    pub fn is_mounted(&self) -> bool {
+        self.mounted.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for MountPoint {
+    fn drop(&mut self) {
+        let runner = self.runner.clone();
+        if self.is_mounted() && self.unmount(runner.as_ref(), false).is_err() {
+            let _ = self.unmount(runner.as_ref(), true);
+        }
+        // Never removes contents: a directory that is still mounted stays.
+        let _ = fs::remove_dir(&self.path);
+        lock_active().retain(|path| *path != self.path);
+    }
+}
+
+/// A private directory the selected editions are exported into, removed
+/// with its contents on drop. A fresh one per run means `wimlib-imagex
+/// export` never appends to an image a previous run left behind.
+pub(crate) struct StagingDir {
+    path: PathBuf,
+}
+
+impl StagingDir {
+    /// Creates `<root>/windusb_export_<pid>_XXXXXX`, readable only by us.
+    pub(crate) fn create_in(root: &Path) -> io::Result<Self> {
+        Ok(StagingDir {
+            path: private_dir(root, EXPORT_PREFIX)?,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+/// `mkdtemp` of `<root>/<prefix><pid>_XXXXXX`.
+fn private_dir(root: &Path, prefix: &str) -> io::Result<PathBuf> {
+    let template = root.join(format!("{}{}_XXXXXX", prefix, std::process::id()));
+    let template = CString::new(template.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let raw = template.into_raw();
+    let created = unsafe { libc::mkdtemp(raw) };
+    let template = unsafe { CString::from_raw(raw) };
+    if created.is_null() {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PathBuf::from(std::ffi::OsStr::from_bytes(
+        template.as_bytes(),
+    )))
+}
+
+fn lock_active() -> std::sync::MutexGuard<'static, Vec<PathBuf>> {
+    ACTIVE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Lazily unmounts and removes every mount point this process still holds.
+/// For signal handlers that exit without running destructors.
+pub fn release_all() {
+    let paths = std::mem::take(&mut *lock_active());
+    for path in paths {
+        let _ = SystemRunner.run(
+            &Invocation::new("umount")
+                .arg("-l")
+                .arg(path.to_string_lossy()),
+        );
+        let _ = fs::remove_dir(&path);
+    }
+}
+
+/// Unmounts and removes mount points left in `/tmp`, and exported editions
+/// left in `/var/tmp`, by runs that are no longer alive. Returns the paths
+/// removed.
+pub fn sweep_stale(runner: &dyn CommandRunner) -> Vec<PathBuf> {
+    let mut removed = sweep_stale_in(runner, Path::new(MOUNT_ROOT), Path::new("/proc/mounts"));
+    removed.extend(sweep_exports_in(Path::new(DEFAULT_STAGING_DIR)));
+    removed
+}
+
+/// Removes the export directories of dead runs from `root`, and the
+/// `windusb_export_<random>.<ext>` images older releases left there.
+pub fn sweep_exports_in(root: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let mut removed = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(rest) = name.strip_prefix(EXPORT_PREFIX) else {
+            continue;
+        };
+        let path = entry.path();
+        // Never follow a symlink someone else planted under our prefix.
+        let Ok(kind) = entry.file_type() else {
+            continue;
+        };
+        let gone = if kind.is_dir() {
+            !owner(rest).is_some_and(is_alive) && fs::remove_dir_all(&path).is_ok()
+        } else {
+            kind.is_file() && owner(rest).is_none() && fs::remove_file(&path).is_ok()
+        };
+        if gone {
+            removed.push(path);
+        }
+    }
+    removed
+}
+
+/// Like [`sweep_stale`] for mount points in `root`, with the mount table read from `mounts`.
+pub fn sweep_stale_in(runner: &dyn CommandRunner, root: &Path, mounts: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(root) else {
+        return Vec::new();
+    };
+    let mounted = mounted_paths(mounts);
+    let mut removed = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(rest) = PREFIXES.iter().find_map(|p| name.strip_prefix(p)) else {
+            continue;
+        };
+        if owner(rest).is_some_and(is_alive) || !entry.path().is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        if mounted.contains(&path) {
+            let _ = runner.run(
+                &Invocation::new("umount")
+                    .arg("-l")
+                    .arg(path.to_string_lossy()),
+            );
+        }
+        if fs::remove_dir(&path).is_ok() {
+            removed.push(path);
+        }
+    }
+    removed
+}
+
+/// Pid in `<pid>_XXXXXX`; older releases named mount points `windusb_usb_<random>`.
+fn owner(rest: &str) -> Option<libc::pid_t> {
+    let (pid, _) = rest.split_once('_')?;
+    pid.parse().ok().filter(|pid| *pid > 0)
+}
+
+fn is_alive(pid: libc::pid_t) -> bool {
+    let signalled = unsafe { libc::kill(pid, 0) } == 0;
+    signalled || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Mount points listed in a `/proc/mounts`-style table.
+fn mounted_paths(mounts: &Path) -> Vec<PathBuf> {
+    let text = fs::read_to_string(mounts).unwrap_or_default();
+    text.lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|field| PathBuf::from(unescape(field)))
+        .collect()
+}
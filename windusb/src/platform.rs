@@ -0,0 +1,244 @@
+//! Works out which machines an ISO can boot and install on.
+
+use std::fmt;
+use std::path::Path;
+
+use crate::iso::{inspect, list_editions, IsoEntry};
+use crate::layout::PartitionScheme;
+use crate::runner::{CommandRunner, SystemRunner};
+use crate::wim::{Architecture, Edition, WindowsVersion};
+
+/// Removable-media UEFI loaders and the firmware architecture that starts each.
+const UEFI_LOADERS: [(&str, Architecture); 4] = [
+    ("efi/boot/bootx64.efi", Architecture::X64),
+    ("efi/boot/bootaa64.efi", Architecture::Arm64),
+    ("efi/boot/bootia32.efi", Architecture::X86),
+    ("efi/boot/bootarm.efi", Architecture::Arm),
+];
+
+/// Windows release family.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Generation {
+    Vista,
+    Windows7,
+    Windows8,
+    Windows8_1,
+    Windows10,
+    Windows11,
+    /// Windows Server, with its release name such as "2022" or "2008 R2".
+    Server(&'static str),
+}
+
+impl Generation {
+    /// Maps an image version to its release. `server` selects the Server
+    /// release sharing the client's kernel version.
+    pub fn from_version(version: WindowsVersion, server: bool) -> Option<Self> {
+        let generation = match (version.major, version.minor, server) {
+            (6, 0, false) => Generation::Vista,
+            (6, 0, true) => Generation::Server("2008"),
+            (6, 1, false) => Generation::Windows7,
+            (6, 1, true) => Generation::Server("2008 R2"),
+            (6, 2, false) => Generation::Windows8,
+            (6, 2, true) => Generation::Server("2012"),
+            (6, 3, false) => Generation::Windows8_1,
+            (6, 3, true) => Generation::Server("2012 R2"),
+            (10, 0, false) if version.build >= 22000 => Generation::Windows11,
+            (10, 0, false) => Generation::Windows10,
+            (10, 0, true) => Generation::Server(match version.build {
+                b if b >= 26100 => "2025",
+                b if b >= 20348 => "2022",
+                b if b >= 17763 => "2019",
+                _ => "2016",
+            }),
+            _ => return None,
+        };
+        Some(generation)
+    }
+}
+
+impl fmt::Display for Generation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Generation::Vista => f.write_str("Windows Vista"),
+            Generation::Windows7 => f.write_str("Windows 7"),
+            Generation::Windows8 => f.write_str("Windows 8"),
+            Generation::Windows8_1 => f.write_str("Windows 8.1"),
+            Generation::Windows10 => f.write_str("Windows 10"),
+            Generation::Windows11 => f.write_str("Windows 11"),
+            Generation::Server(release) => write!(f, "Windows Server {}", release),
+        }
+    }
+}
+
+/// Something about the ISO that will keep the stick from booting, or from
+/// booting where the user probably expects it to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlatformWarning {
+    /// No `efi/boot/boot*.efi`, and no BIOS boot path either: the ISO lacks
+    /// `bootmgr` or the chosen scheme has no MBR to start it from.
+    NoUefiLoader,
+    /// Only a 32-bit UEFI loader, which 64-bit firmware ignores.
+    Only32BitUefi,
+    /// ARM images only install on ARM devices.
+    ArmOnly(Architecture),
+    /// Windows 7 expects CSM video support and has no USB 3 drivers.
+    Windows7OnUefi,
+    /// The loaders on the ISO do not match the architecture of the images.
+    LoaderMismatch {
+        loaders: Vec<Architecture>,
+        images: Vec<Architecture>,
+    },
+}
+
+impl fmt::Display for PlatformWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlatformWarning::NoUefiLoader => f.write_str(
+                "the ISO has no UEFI boot loader (efi/boot/boot*.efi) and cannot start from BIOS with \
+                 this partition scheme, so the USB will not boot",
+            ),
+            PlatformWarning::Only32BitUefi => f.write_str(
+                "32-bit Windows only boots on rare 32-bit UEFI firmware, not on typical 64-bit PCs",
+            ),
+            PlatformWarning::ArmOnly(arch) => {
+                write!(f, "{} Windows only boots on {} devices", arch, arch)
+            }
+            PlatformWarning::Windows7OnUefi => f.write_str(
+                "Windows 7 needs CSM/legacy video support to boot via UEFI and lacks USB 3 drivers",
+            ),
+            PlatformWarning::LoaderMismatch { loaders, images } => write!(
+                f,
+                "the boot loader is for {} but the images are for {}",
+                join(loaders),
+                join(images)
+            ),
+        }
+    }
+}
+
+fn join(archs: &[Architecture]) -> String {
+    archs
+        .iter()
+        .map(|a| a.to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// What the ISO targets, from its boot files and WIM metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlatformInfo {
+    /// Architectures of the install images, or of the UEFI loaders if the
+    /// metadata is unreadable.
+    pub architectures: Vec<Architecture>,
+    pub generation: Option<Generation>,
+    /// Firmware architectures with a removable-media UEFI loader on the ISO.
+    pub uefi_loaders: Vec<Architecture>,
+    /// `bootmgr` is present, so the ISO can also start from BIOS.
+    pub bios_bootable: bool,
+}
+
+impl PlatformInfo {
+    pub fn from_contents(entries: &[IsoEntry], editions: &[Edition]) -> Self {
+        let has = |path: &str| {
+            entries
+                .iter()
+                .any(|e| !e.is_dir && e.path.eq_ignore_ascii_case(path))
+        };
+        let uefi_loaders: Vec<Architecture> = UEFI_LOADERS
+            .iter()
+            .filter(|(path, _)| has(path))
+            .map(|(_, arch)| *arch)
+            .collect();
+
+        let mut architectures = Vec::new();
+        for arch in editions.iter().filter_map(|e| e.architecture) {
+            if !architectures.contains(&arch) {
+                architectures.push(arch);
+            }
+        }
+        let from_metadata = !architectures.is_empty();
+        if !from_metadata {
+            architectures = uefi_loaders.clone();
+        }
+
+        // Newest release wins on the rare ISO mixing several.
+        let generation = editions
+            .iter()
+            .filter_map(|e| {
+                let server = e
+                    .installation_type
+                    .as_deref()
+                    .is_some_and(|t| t.starts_with("Server"));
+                Some((e.version?, server))
+            })
+            .max()
+            .and_then(|(version, server)| Generation::from_version(version, server));
+
+        PlatformInfo {
+            architectures,
+            generation,
+            uefi_loaders,
+            bios_bootable: has("bootmgr"),
+        }
+    }
+
+    /// Short description such as "Windows 11 · x64".
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(generation) = self.generation {
+            parts.push(generation.to_string());
+        }
+        if !self.architectures.is_empty() {
+            parts.push(join(&self.architectures));
+        }
+        parts.join(" · ")
+    }
+
+    /// Problems worth telling the user about before writing the stick with
+    /// `scheme`.
+    pub fn warnings(&self, scheme: PartitionScheme) -> Vec<PlatformWarning> {
+        let mut warnings = Vec::new();
+        let boots_bios = self.bios_bootable && scheme.boots_bios();
+        if self.uefi_loaders.is_empty() {
+            if !boots_bios {
+                warnings.push(PlatformWarning::NoUefiLoader);
+            }
+        } else if self.uefi_loaders == [Architecture::X86] {
+            warnings.push(PlatformWarning::Only32BitUefi);
+        }
+        if !self.uefi_loaders.is_empty()
+            && !self
+                .architectures
+                .iter()
+                .any(|arch| self.uefi_loaders.contains(arch))
+        {
+            warnings.push(PlatformWarning::LoaderMismatch {
+                loaders: self.uefi_loaders.clone(),
+                images: self.architectures.clone(),
+            });
+        }
+        for arch in [Architecture::Arm64, Architecture::Arm] {
+            if self.architectures == [arch] {
+                warnings.push(PlatformWarning::ArmOnly(arch));
+            }
+        }
+        // From BIOS/CSM Windows 7 gets the video support it expects.
+        if self.generation == Some(Generation::Windows7) && !boots_bios {
+            warnings.push(PlatformWarning::Windows7OnUefi);
+        }
+        warnings
+    }
+}
+
+/// Detects what `iso` targets; `None` if the ISO cannot be listed.
+pub fn detect_platform(iso: &Path) -> Option<PlatformInfo> {
+    detect_platform_with(&SystemRunner, iso)
+}
+
+/// Like [`detect_platform`], listing the ISO through `runner` when it
+/// cannot be read natively.
+pub fn detect_platform_with(runner: &dyn CommandRunner, iso: &Path) -> Option<PlatformInfo> {
+    let contents = inspect(runner, iso)?;
+    let editions = list_editions(iso).unwrap_or_default();
+    Some(PlatformInfo::from_contents(&contents.entries, &editions))
+}
@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Rolling-window throughput estimate over a monotonically growing byte counter.
+#[derive(Debug, Clone)]
+pub struct RateMeter {
+    window: Duration,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RateMeter {
+    pub fn new(window: Duration) -> Self {
+        RateMeter {
+            window,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Forgets all samples, e.g. when a new phase starts.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Records the counter value now and returns bytes per second over the window.
+    pub fn record(&mut self, bytes: u64) -> Option<f64> {
+        self.record_at(Instant::now(), bytes)
+    }
+
+    pub fn record_at(&mut self, now: Instant, bytes: u64) -> Option<f64> {
+        self.samples.push_back((now, bytes));
+        while self.samples.len() > 2 {
+            match self.samples.front() {
+                Some(&(t, _)) if now.duration_since(t) > self.window => {
+                    self.samples.pop_front();
+                }
+                _ => break,
+            }
+        }
+        let &(first_t, first_b) = self.samples.front()?;
+        let elapsed = now.duration_since(first_t).as_secs_f64();
+        if elapsed <= 0.0 {
+            return None;
+        }
+        Some(bytes.saturating_sub(first_b) as f64 / elapsed)
+    }
+}
+
+/// Completion an external tool printed on one progress line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolProgress {
+    /// Completion in `0.0..=1.0`.
+    pub fraction: f64,
+    pub bytes_done: Option<u64>,
+    pub bytes_total: Option<u64>,
+    /// File being processed, as the tool names it.
+    pub file: Option<String>,
+}
+
+/// Parses a 7-Zip `-bsp1` line such as `42% 17 - sources\boot.wim`.
+pub fn parse_7z_progress(line: &str) -> Option<ToolProgress> {
+    let (percent, rest) = line.trim().split_once('%')?;
+    let percent: f64 = percent.trim().parse().ok()?;
+    let file = rest
+        .split_once(" - ")
+        .map(|(_, name)| name.trim().replace('\\', "/"))
+        .filter(|name| !name.is_empty());
+    Some(ToolProgress {
+        fraction: (percent / 100.0).clamp(0.0, 1.0),
+        bytes_done: None,
+        bytes_total: None,
+        file,
+    })
+}
+
+/// Parses wimlib-imagex progress such as
+/// `Archiving file data: 1203 MiB of 4764 MiB (25%) done` or
+/// `Writing "install.swm" (part 1 of 2): 0 MiB of 4764 MiB (0%) written`.
+pub fn parse_wimlib_progress(line: &str) -> Option<ToolProgress> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let bytes = |number: &str, unit: &str| -> Option<u64> {
+        Some(number.parse::<u64>().ok()? << unit_shift(unit)?)
+    };
+    // "<done> <unit> of <total> <unit> (<percent>%)"; the split message has
+    // an earlier "(part 1 of 2)" to skip.
+    let (done, total, percent) = (2..words.len().saturating_sub(3))
+        .filter(|&of| words[of] == "of")
+        .find_map(|of| {
+            let percent: f64 = words[of + 3]
+                .trim_start_matches('(')
+                .trim_end_matches([')', ':'])
+                .strip_suffix('%')?
+                .parse()
+                .ok()?;
+            Some((
+                bytes(words[of - 2], words[of - 1])?,
+                bytes(words[of + 1], words[of + 2])?,
+                percent,
+            ))
+        })?;
+    let file = line
+        .strip_prefix("Writing \"")
+        .and_then(|rest| rest.split('"').next())
+        .map(str::to_string);
+    Some(ToolProgress {
+        fraction: (percent / 100.0).clamp(0.0, 1.0),
+        bytes_done: Some(done),
+        bytes_total: Some(total),
+        file,
+    })
+}
+
+fn unit_shift(unit: &str) -> Option<u32> {
+    match unit {
+        "bytes" => Some(0),
+        "KiB" => Some(10),
+        "MiB" => Some(20),
+        "GiB" => Some(30),
+        "TiB" => Some(40),
+        _ => None,
+    }
+}
+
+/// Folds the progress lines of one wimlib-imagex run into bytes done of the
+/// whole run. `split` prints a cumulative line as each part begins and
+/// per-part counters in between.
+#[derive(Debug, Clone, Default)]
+pub struct WimlibProgress {
+    /// Bytes done when the current part began.
+    base: u64,
+    done: u64,
+    total: Option<u64>,
+}
+
+impl WimlibProgress {
+    /// Takes one line of output; returns `false` if it carried no progress.
+    pub fn update(&mut self, line: &str) -> bool {
+        let Some(progress) = parse_wimlib_progress(line) else {
+            return false;
+        };
+        let (Some(done), Some(total)) = (progress.bytes_done, progress.bytes_total) else {
+            return false;
+        };
+        let done = if progress.file.is_some() {
+            self.base = done;
+            self.total = Some(total);
+            done
+        } else {
+            match self.total {
+                Some(whole) if total < whole => (self.base + done).min(whole),
+                _ => {
+                    self.total = Some(total);
+                    done
+                }
+            }
+        };
+        self.done = self.done.max(done);
+        true
+    }
+
+    pub fn done(&self) -> u64 {
+        self.done
+    }
+
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+}
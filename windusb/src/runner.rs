@@ -0,0 +1,283 @@
+use std::fmt;
+use std::io::{self, Read};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::ToolError;
+use crate::tools::{find_tool, local_bin};
+
+/// How often a running tool is checked for cancellation.
+const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+/// One external tool invocation. `program` is the bare tool name (`sgdisk`,
+/// `7z`, ...); resolving it to a path is up to the [`CommandRunner`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl Invocation {
+    pub fn new(program: impl Into<String>) -> Self {
+        Invocation {
+            program: program.into(),
+            args: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+}
+
+impl fmt::Display for Invocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+/// Captured result of a finished invocation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Output {
+    /// Exit code, or `None` if the tool was killed by a signal.
+    pub status: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.status == Some(0)
+    }
+}
+
+/// Executes external tools on behalf of the engine.
+///
+/// [`SystemRunner`] spawns real processes; tests substitute a fake that
+/// records invocations and scripts their results.
+pub trait CommandRunner: Send + Sync {
+    /// Runs the invocation to completion. An `Err` means the tool could not be started.
+    fn run(&self, invocation: &Invocation) -> io::Result<Output>;
+
+    /// Like [`run`](Self::run), handing every line of stdout to `on_line`.
+    /// The default calls `on_line` once the tool has finished; runners that
+    /// spawn processes stream lines as they are printed.
+    fn run_with_output(
+        &self,
+        invocation: &Invocation,
+        on_line: &mut dyn FnMut(&str),
+    ) -> io::Result<Output> {
+        let out = self.run(invocation)?;
+        output_lines(&out.stdout).for_each(on_line);
+        Ok(out)
+    }
+
+    /// Like [`run_with_output`](Self::run_with_output), giving up once
+    /// `cancel` is set. The default only refuses to start tools after
+    /// cancellation; [`SystemRunner`] also terminates the one running.
+    fn run_cancellable(
+        &self,
+        invocation: &Invocation,
+        on_line: &mut dyn FnMut(&str),
+        cancel: &AtomicBool,
+    ) -> io::Result<Output> {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(cancelled());
+        }
+        self.run_with_output(invocation, on_line)
+    }
+
+    /// Whether `program` can be started at all. Assumed by default; checked
+    /// before the drive is touched so a missing tool cannot strand a wiped stick.
+    fn has_tool(&self, program: &str) -> bool {
+        let _ = program;
+        true
+    }
+}
+
+/// Error for a tool that was not started, or was stopped, because the job was cancelled.
+pub fn cancelled() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "cancelled")
+}
+
+/// Non-empty lines of tool output. Carriage returns and backspaces, which
+/// progress meters use to redraw in place, also end a line.
+pub fn output_lines(text: &str) -> impl Iterator<Item = &str> {
+    text.split(['\n', '\r', '\u{8}'])
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+}
+
+/// Runs tools as child processes, preferring the AppImage's bundled binaries.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, invocation: &Invocation) -> io::Result<Output> {
+        let out = Command::new(local_bin(&invocation.program))
+            .args(&invocation.args)
+            .stdin(Stdio::null())
+            .output()?;
+        Ok(Output {
+            status: out.status.code(),
+            stdout: String::from_utf8_lossy(&out.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&out.stderr).into_owned(),
+        })
+    }
+
+    fn run_with_output(
+        &self,
+        invocation: &Invocation,
+        on_line: &mut dyn FnMut(&str),
+    ) -> io::Result<Output> {
+        stream(invocation, on_line, None)
+    }
+
+    fn run_cancellable(
+        &self,
+        invocation: &Invocation,
+        on_line: &mut dyn FnMut(&str),
+        cancel: &AtomicBool,
+    ) -> io::Result<Output> {
+        if cancel.load(Ordering::Relaxed) {
+            return Err(cancelled());
+        }
+        stream(invocation, on_line, Some(cancel))
+    }
+
+    fn has_tool(&self, program: &str) -> bool {
+        find_tool(program).is_some()
+    }
+}
+
+/// Spawns the tool and hands `on_line` its stdout as it is printed. Once
+/// `cancel` is set the tool gets SIGTERM.
+fn stream(
+    invocation: &Invocation,
+    on_line: &mut dyn FnMut(&str),
+    cancel: Option<&AtomicBool>,
+) -> io::Result<Output> {
+    let mut child = Command::new(local_bin(&invocation.program))
+        .args(&invocation.args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let stderr = thread::spawn(move || {
+        let mut text = Vec::new();
+        let _ = stderr.read_to_end(&mut text);
+        String::from_utf8_lossy(&text).into_owned()
+    });
+
+    let pid = child.id() as libc::pid_t;
+    let reading = AtomicBool::new(true);
+    let text = thread::scope(|scope| {
+        // The child is not reaped before this scope ends, so `pid` stays ours.
+        if let Some(cancel) = cancel {
+            scope.spawn(|| {
+                while reading.load(Ordering::Relaxed) {
+                    if cancel.load(Ordering::Relaxed) {
+                        unsafe { libc::kill(pid, libc::SIGTERM) };
+                        break;
+                    }
+                    thread::sleep(CANCEL_POLL);
+                }
+            });
+        }
+        let text = read_lines(child.stdout.take().expect("stdout is piped"), on_line);
+        reading.store(false, Ordering::Relaxed);
+        text
+    })?;
+
+    let status = match cancel {
+        Some(cancel) => wait_or_terminate(&mut child, cancel)?,
+        None => child.wait()?,
+    };
+    Ok(Output {
+        status: status.code(),
+        stdout: String::from_utf8_lossy(&text).into_owned(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Reads `stdout` to the end, handing complete lines to `on_line`.
+fn read_lines(mut stdout: impl Read, on_line: &mut dyn FnMut(&str)) -> io::Result<Vec<u8>> {
+    let mut text = Vec::new();
+    let mut line_start = 0;
+    let mut buf = [0; 4096];
+    loop {
+        let n = match stdout.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        text.extend_from_slice(&buf[..n]);
+        let unread = &text[line_start..];
+        if let Some(end) = unread.iter().rposition(|b| matches!(b, b'\n' | b'\r' | 8)) {
+            let end = line_start + end;
+            output_lines(&String::from_utf8_lossy(&text[line_start..end])).for_each(&mut *on_line);
+            line_start = end + 1;
+        }
+    }
+    output_lines(&String::from_utf8_lossy(&text[line_start..])).for_each(&mut *on_line);
+    Ok(text)
+}
+
+/// Waits for a tool that closed its stdout, terminating it if `cancel` is set meanwhile.
+fn wait_or_terminate(child: &mut Child, cancel: &AtomicBool) -> io::Result<ExitStatus> {
+    let mut terminated = false;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if !terminated && cancel.load(Ordering::Relaxed) {
+            unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+            terminated = true;
+        }
+        thread::sleep(CANCEL_POLL);
+    }
+}
+
+/// Runs the invocation and turns a spawn failure or a non-zero exit into a
+/// [`ToolError`] carrying the tool's stderr.
+pub(crate) fn run_tool(
+    runner: &dyn CommandRunner,
+    invocation: Invocation,
+) -> Result<Output, ToolError> {
+    match runner.run(&invocation) {
+        Ok(out) if out.success() => Ok(out),
+        Ok(out) => Err(ToolError::new(&invocation, out.status, out.stderr)),
+        Err(err) => Err(ToolError::new(&invocation, None, err.to_string())),
+    }
+}
+
+/// [`run_tool`] that hands each line of the tool's stdout to `on_line`.
+pub(crate) fn run_tool_with_output(
+    runner: &dyn CommandRunner,
+    invocation: Invocation,
+    mut on_line: impl FnMut(&str),
+) -> Result<Output, ToolError> {
+    match runner.run_with_output(&invocation, &mut on_line) {
+        Ok(out) if out.success() => Ok(out),
+        Ok(out) => Err(ToolError::new(&invocation, out.status, out.stderr)),
+        Err(err) => Err(ToolError::new(&invocation, None, err.to_string())),
+    }
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::unmount::BusyProcess;
+
+/// Mountpoints whose backing disk must never be offered as a target.
+const SYSTEM_MOUNTPOINTS: [&str; 7] = ["/", "/boot", "/boot/efi", "/efi", "/usr", "/var", "/home"];
+
+/// Why a disk must not be wiped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Hazard {
+    /// A device on the disk backs a system mountpoint such as `/` or `/boot`.
+    SystemMount { device: String, mountpoint: String },
+    /// A device on the disk is in use as swap.
+    ActiveSwap { device: String },
+    /// A partition is an active LVM physical volume.
+    LvmMember { device: String, holder: String },
+    /// A partition is an unlocked LUKS container.
+    LuksMember { device: String, holder: String },
+    /// A partition is part of a running software RAID array.
+    RaidMember { device: String, holder: String },
+    /// A partition is claimed by some other device-mapper target.
+    DeviceMapperMember { device: String, holder: String },
+    /// The disk is neither removable nor attached over USB.
+    NotRemovable,
+}
+
+impl fmt::Display for Hazard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Hazard::SystemMount { device, mountpoint } => {
+                write!(f, "{} is mounted at {}", device, mountpoint)
+            }
+            Hazard::ActiveSwap { device } => write!(f, "{} is in use as swap", device),
+            Hazard::LvmMember { device, holder } => {
+                write!(f, "{} is an active LVM volume ({})", device, holder)
+            }
+            Hazard::LuksMember { device, holder } => {
+                write!(f, "{} is an unlocked LUKS container ({})", device, holder)
+            }
+            Hazard::RaidMember { device, holder } => {
+                write!(f, "{} is a member of RAID array {}", device, holder)
+            }
+            Hazard::DeviceMapperMember { device, holder } => {
+                write!(f, "{} is held by device-mapper target {}", device, holder)
+            }
+            Hazard::NotRemovable => f.write_str("not a removable or USB disk"),
+        }
+    }
+}
+
+/// Looks for reasons not to wipe a disk in `/proc/mounts`, `/proc/swaps` and
+/// the sysfs holder graph, and for whatever still has it mounted.
+#[derive(Debug, Clone)]
+pub struct DiskInspector {
+    proc_root: PathBuf,
+    sys_root: PathBuf,
+}
+
+impl Default for DiskInspector {
+    fn default() -> Self {
+        DiskInspector::with_roots("/proc", "/sys")
+    }
+}
+
+impl DiskInspector {
+    pub fn new() -> Self {
+        DiskInspector::default()
+    }
+
+    /// Reads procfs and sysfs below the given directories instead of `/proc` and `/sys`.
+    pub fn with_roots(proc_root: impl Into<PathBuf>, sys_root: impl Into<PathBuf>) -> Self {
+        DiskInspector {
+            proc_root: proc_root.into(),
+            sys_root: sys_root.into(),
+        }
+    }
+
+    /// Everything that makes `drive` (e.g. `/dev/sdb`) unsafe to wipe; empty if none.
+    pub fn hazards(&self, drive: &str) -> Vec<Hazard> {
+        let disk = kernel_name(drive);
+        let block = self.sys_root.join("block").join(&disk);
+        if !block.exists() {
+            return Vec::new();
+        }
+
+        let mut hazards = Vec::new();
+        if !self.is_removable(&block) {
+            hazards.push(Hazard::NotRemovable);
+        }
+
+        // Every kernel device whose data lives on this disk, mapped to the
+        // partition (or the disk itself) it sits on.
+        let mut stack: HashMap<String, String> = HashMap::new();
+        for member in self.members(&disk) {
+            stack.insert(member.clone(), member.clone());
+            let mut pending = self.holders(&member);
+            while let Some(holder) = pending.pop() {
+                if stack.contains_key(&holder) {
+                    continue;
+                }
+                let hazard = self.holder_hazard(&member, &holder);
+                if !hazards.contains(&hazard) {
+                    hazards.push(hazard);
+                }
+                pending.extend(self.holders(&holder));
+                stack.insert(holder, member.clone());
+            }
+        }
+        let dm_names = self.device_mapper_names();
+        let on_disk = |source: &str| -> Option<&String> {
+            if !source.starts_with("/dev/") {
+                return None;
+            }
+            let name = source
+                .strip_prefix("/dev/mapper/")
+                .and_then(|mapped| dm_names.get(mapped))
+                .cloned()
+                .unwrap_or_else(|| kernel_name(source));
+            stack.get(&name)
+        };
+
+        for (source, mountpoint) in self.mounts() {
+            if SYSTEM_MOUNTPOINTS.contains(&mountpoint.as_str()) {
+                if let Some(member) = on_disk(&source) {
+                    hazards.push(Hazard::SystemMount {
+                        device: format!("/dev/{}", member),
+                        mountpoint,
+                    });
+                }
+            }
+        }
+        for source in self.swaps() {
+            if let Some(member) = on_disk(&source) {
+                hazards.push(Hazard::ActiveSwap {
+                    device: format!("/dev/{}", member),
+                });
+            }
+        }
+        hazards
+    }
+
+    /// Filesystems of `drive` or its partitions that are mounted, as
+    /// `(device, mountpoint)` pairs from `mountinfo`, deepest mountpoint first.
+    pub fn mounts_on(&self, drive: &str) -> Vec<(String, String)> {
+        let members = self.members(&kernel_name(drive));
+        let text = fs::read_to_string(self.proc_root.join("self/mountinfo")).unwrap_or_default();
+        let mut mounts: Vec<(String, String)> = text
+            .lines()
+            .filter_map(|line| {
+                let (fields, source) = line.split_once(" - ")?;
+                let mountpoint = fields.split_whitespace().nth(4)?;
+                let source = unescape(source.split_whitespace().nth(1)?);
+                if !source.starts_with("/dev/") {
+                    return None;
+                }
+                let member = kernel_name(&source);
+                members
+                    .contains(&member)
+                    .then(|| (format!("/dev/{}", member), unescape(mountpoint)))
+            })
+            .collect();
+        mounts.sort_by_key(|(_, mountpoint)| std::cmp::Reverse(mountpoint.len()));
+        mounts
+    }
+
+    /// Processes with an open file, working directory, root or executable
+    /// below `mountpoint`, found through `/proc/<pid>/fd` and friends.
+    pub fn processes_using(&self, mountpoint: &str) -> Vec<BusyProcess> {
+        let Ok(entries) = fs::read_dir(&self.proc_root) else {
+            return Vec::new();
+        };
+        let mountpoint = Path::new(mountpoint);
+        let mut found = Vec::new();
+        for entry in entries.flatten() {
+            let Some(pid) = entry
+                .file_name()
+                .to_str()
+                .and_then(|n| n.parse::<u32>().ok())
+            else {
+                continue;
+            };
+            let dir = entry.path();
+            let mut links: Vec<PathBuf> = ["cwd", "root", "exe"]
+                .iter()
+                .map(|name| dir.join(name))
+                .collect();
+            if let Ok(fds) = fs::read_dir(dir.join("fd")) {
+                links.extend(fds.flatten().map(|fd| fd.path()));
+            }
+            let uses = links
+                .iter()
+                .any(|link| fs::read_link(link).is_ok_and(|target| target.starts_with(mountpoint)));
+            if uses {
+                found.push(BusyProcess {
+                    pid,
+                    command: read_trimmed(&dir.join("comm")).unwrap_or_default(),
+                });
+            }
+        }
+        found.sort_by_key(|process| process.pid);
+        found
+    }
+
+    /// Device node of partition `number` on `drive`, as listed in
+    /// `/sys/block/<disk>/<partition>/partition`; `None` until the kernel
+    /// knows about it.
+    pub fn partition_node(&self, drive: &str, number: u32) -> Option<String> {
+        let disk = kernel_name(drive);
+        let block = self.sys_root.join("block").join(&disk);
+        self.partitions(&block, &disk)
+            .into_iter()
+            .find(|part| {
+                read_trimmed(&block.join(part).join("partition"))
+                    .is_some_and(|n| n == number.to_string())
+            })
+            .map(|part| format!("/dev/{}", part))
+    }
+
+    /// The disk and its partitions.
+    fn members(&self, disk: &str) -> Vec<String> {
+        let block = self.sys_root.join("block").join(disk);
+        let mut members = vec![disk.to_string()];
+        members.extend(self.partitions(&block, disk));
+        members
+    }
+
+    fn is_removable(&self, block: &Path) -> bool {
+        if read_trimmed(&block.join("removable")).as_deref() == Some("1")
+            || block.join("loop").exists()
+        {
+            return true;
+        }
+        fs::canonicalize(block).is_ok_and(|dev| {
+            dev.components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with("usb"))
+        })
+    }
+
+    fn partitions(&self, block: &Path, disk: &str) -> Vec<String> {
+        let Ok(entries) = fs::read_dir(block) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .filter(|e| e.path().join("partition").exists())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(disk))
+            .collect()
+    }
+
+    fn holders(&self, name: &str) -> Vec<String> {
+        let Ok(entries) =
+            fs::read_dir(self.sys_root.join("class/block").join(name).join("holders"))
+        else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            .collect()
+    }
+
+    fn holder_hazard(&self, member: &str, holder: &str) -> Hazard {
+        let device = format!("/dev/{}", member);
+        if holder.starts_with("md") {
+            return Hazard::RaidMember {
+                device,
+                holder: format!("/dev/{}", holder),
+            };
+        }
+        let dm = self.sys_root.join("class/block").join(holder).join("dm");
+        let name = read_trimmed(&dm.join("name")).unwrap_or_else(|| holder.to_string());
+        let holder = format!("/dev/mapper/{}", name);
+        let uuid = read_trimmed(&dm.join("uuid")).unwrap_or_default();
+        if uuid.starts_with("LVM-") {
+            Hazard::LvmMember { device, holder }
+        } else if uuid.starts_with("CRYPT-") {
+            Hazard::LuksMember { device, holder }
+        } else {
+            Hazard::DeviceMapperMember { device, holder }
+        }
+    }
+
+    /// Device-mapper names (`/dev/mapper/<name>`) to kernel names (`dm-N`).
+    fn device_mapper_names(&self) -> HashMap<String, String> {
+        let mut names = HashMap::new();
+        let Ok(entries) = fs::read_dir(self.sys_root.join("class/block")) else {
+            return names;
+        };
+        for entry in entries.flatten() {
+            let kernel = entry.file_name().to_string_lossy().into_owned();
+            if let Some(name) = read_trimmed(&entry.path().join("dm/name")) {
+                names.insert(name, kernel);
+            }
+        }
+        names
+    }
+
+    /// `(source, mountpoint)` pairs from `/proc/mounts`.
+    fn mounts(&self) -> Vec<(String, String)> {
+        let text = fs::read_to_string(self.proc_root.join("mounts")).unwrap_or_default();
+        text.lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let source = fields.next()?;
+                let mountpoint = fields.next()?;
+                Some((unescape(source), unescape(mountpoint)))
+            })
+            .collect()
+    }
+
+    /// Swap devices from `/proc/swaps`; swap files are ignored.
+    fn swaps(&self) -> Vec<String> {
+        let text = fs::read_to_string(self.proc_root.join("swaps")).unwrap_or_default();
+        text.lines()
+            .skip(1)
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let source = fields.next()?;
+                (fields.next()? == "partition").then(|| unescape(source))
+            })
+            .collect()
+    }
+}
+
+/// Shorthand for [`DiskInspector::hazards`] on the live system.
+pub fn drive_hazards(drive: &str) -> Vec<Hazard> {
+    DiskInspector::new().hazards(drive)
+}
+
+/// Kernel name of a device node, resolving symlinks such as `/dev/disk/by-id/...`.
+fn kernel_name(path: &str) -> String {
+    let resolved = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    resolved
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+/// Undoes the octal escaping (`\040` for space) used in `/proc/mounts`.
+pub(crate) fn unescape(field: &str) -> String {
+    let mut out = Vec::with_capacity(field.len());
+    let bytes = field.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && i + 4 <= bytes.len()
+            && bytes[i + 1..i + 4].iter().all(u8::is_ascii_digit)
+        {
+            if let Ok(code) = u8::from_str_radix(&field[i + 1..i + 4], 8) {
+                out.push(code);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
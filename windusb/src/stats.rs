@@ -0,0 +1,226 @@
+//! Elapsed time, drive throughput and time remaining of a running flash,
+//! measured from the drive's own write counters so a slow stick can be told
+//! apart from a stuck tool.
+
+use std::time::{Duration, Instant};
+
+use crate::event::{Phase, Progress};
+use crate::progress::RateMeter;
+use crate::writeback::DeviceWriteback;
+
+/// Span the displayed write speed is averaged over.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Minimum time between two reads of the drive's counters.
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Time spent in one phase and what reached the drive meanwhile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhaseTiming {
+    pub phase: Phase,
+    pub elapsed: Duration,
+    /// Bytes the drive wrote during the phase, if its counters are readable.
+    pub bytes_written: Option<u64>,
+    /// `false` for the phase still running.
+    pub finished: bool,
+}
+
+#[derive(Debug, Clone)]
+struct PhaseSpan {
+    phase: Phase,
+    started: Instant,
+    ended: Option<Instant>,
+    written_at_start: Option<u64>,
+    written_at_end: Option<u64>,
+}
+
+/// Statistics of one flash, fed with its progress events and the drive's
+/// write counter.
+#[derive(Debug, Clone)]
+pub struct FlashStats {
+    started: Instant,
+    ended: Option<Instant>,
+    device: Option<DeviceWriteback>,
+    /// Bytes the flash is expected to write to the drive.
+    expected: Option<u64>,
+    /// Counter value when the flash started.
+    base: Option<u64>,
+    written: Option<u64>,
+    last_sample: Option<Instant>,
+    meter: RateMeter,
+    rate: Option<f64>,
+    phases: Vec<PhaseSpan>,
+    /// ETA of the current phase as reported by the flasher.
+    phase_eta: Option<u64>,
+}
+
+impl FlashStats {
+    /// Starts timing a flash to `drive` that should write about `expected` bytes.
+    pub fn new(drive: &str, expected: Option<u64>) -> Self {
+        let mut stats = FlashStats::started_at(Instant::now(), expected);
+        stats.device = DeviceWriteback::for_device(drive);
+        stats.base = stats.device.as_ref().and_then(|d| d.bytes_written());
+        stats
+    }
+
+    /// Starts timing at `now` without a drive; counter values are fed with
+    /// [`record_at`](Self::record_at).
+    pub fn started_at(now: Instant, expected: Option<u64>) -> Self {
+        FlashStats {
+            started: now,
+            ended: None,
+            device: None,
+            expected,
+            base: None,
+            written: None,
+            last_sample: None,
+            meter: RateMeter::new(RATE_WINDOW),
+            rate: None,
+            phases: Vec::new(),
+            phase_eta: None,
+        }
+    }
+
+    /// Reads the drive's write counter, at most every half second.
+    pub fn sample(&mut self) {
+        let now = Instant::now();
+        if self
+            .last_sample
+            .is_some_and(|last| now.duration_since(last) < SAMPLE_INTERVAL)
+        {
+            return;
+        }
+        if let Some(counter) = self.device.as_ref().and_then(|d| d.bytes_written()) {
+            self.record_at(now, counter);
+        }
+    }
+
+    /// Records the drive's raw "bytes written" counter at `now`. The first
+    /// value seen becomes the baseline unless one was read at start.
+    pub fn record_at(&mut self, now: Instant, counter: u64) {
+        if self.ended.is_some() {
+            return;
+        }
+        let base = *self.base.get_or_insert(counter);
+        let written = counter.saturating_sub(base);
+        self.last_sample = Some(now);
+        self.written = Some(written);
+        self.rate = self.meter.record_at(now, written);
+    }
+
+    pub fn observe(&mut self, progress: &Progress) {
+        self.observe_at(Instant::now(), progress);
+    }
+
+    /// Takes a progress event received at `now`, starting a new phase when it changes.
+    pub fn observe_at(&mut self, now: Instant, progress: &Progress) {
+        if self.ended.is_some() {
+            return;
+        }
+        self.phase_eta = progress.eta_secs;
+        if self.phases.last().map(|p| p.phase) == Some(progress.phase) {
+            return;
+        }
+        self.close_phase(now);
+        self.phases.push(PhaseSpan {
+            phase: progress.phase,
+            started: now,
+            ended: None,
+            written_at_start: self.written,
+            written_at_end: None,
+        });
+    }
+
+    pub fn finish(&mut self) {
+        self.finish_at(Instant::now());
+    }
+
+    /// Stops the clock, e.g. once the flash finished or failed.
+    pub fn finish_at(&mut self, now: Instant) {
+        if self.ended.is_none() {
+            self.close_phase(now);
+            self.ended = Some(now);
+        }
+    }
+
+    fn close_phase(&mut self, now: Instant) {
+        let written = self.written;
+        if let Some(span) = self.phases.last_mut().filter(|p| p.ended.is_none()) {
+            span.ended = Some(now);
+            span.written_at_end = written;
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed_at(Instant::now())
+    }
+
+    pub fn elapsed_at(&self, now: Instant) -> Duration {
+        self.ended.unwrap_or(now).duration_since(self.started)
+    }
+
+    /// Bytes the drive wrote since the flash started.
+    pub fn bytes_written(&self) -> Option<u64> {
+        self.written
+    }
+
+    /// Write speed of the drive over the last few seconds.
+    pub fn bytes_per_sec(&self) -> Option<f64> {
+        self.ended.is_none().then_some(self.rate).flatten()
+    }
+
+    /// Time until the flash completes: what is left to write at the drive's
+    /// current speed, or the flasher's own estimate while nothing is being
+    /// written to the drive.
+    pub fn eta(&self) -> Option<Duration> {
+        if self.ended.is_some() {
+            return None;
+        }
+        let phase = self.phases.last().map(|p| p.phase);
+        let from_drive = match (self.expected, self.written, self.rate) {
+            (Some(expected), Some(written), Some(rate))
+                if rate > 0.0 && written < expected && phase != Some(Phase::Verifying) =>
+            {
+                Some(Duration::from_secs_f64((expected - written) as f64 / rate))
+            }
+            _ => None,
+        };
+        from_drive.or_else(|| self.phase_eta.map(Duration::from_secs))
+    }
+
+    pub fn phases(&self) -> Vec<PhaseTiming> {
+        self.phases_at(Instant::now())
+    }
+
+    /// Per-phase breakdown so far, in the order the phases ran.
+    pub fn phases_at(&self, now: Instant) -> Vec<PhaseTiming> {
+        self.phases
+            .iter()
+            .map(|span| {
+                let end = span.ended.unwrap_or(now);
+                let written_at_end = span.written_at_end.or(self.written);
+                PhaseTiming {
+                    phase: span.phase,
+                    elapsed: end.duration_since(span.started),
+                    bytes_written: match (span.written_at_start, written_at_end) {
+                        (Some(start), Some(end)) => Some(end.saturating_sub(start)),
+                        (None, Some(end)) => Some(end),
+                        _ => None,
+                    },
+                    finished: span.ended.is_some(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Formats a duration as `m:ss`, or `h:mm:ss` from an hour on.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
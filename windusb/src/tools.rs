@@ -0,0 +1,43 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+/// Resolves an external tool, preferring the copy bundled in the AppImage's
+/// `bin-local` directory over whatever is on `PATH`.
+pub fn local_bin(bin_name: &str) -> String {
+    if let Ok(appdir) = std::env::var("APPDIR") {
+        let local_path = format!("{}/bin-local/{}", appdir, bin_name);
+        if Path::new(&local_path).exists() {
+            return local_path;
+        }
+    }
+    bin_name.to_string()
+}
+
+/// Where `bin_name` would be started from: the bundled copy, or the first
+/// executable of that name on `PATH`.
+pub fn find_tool(bin_name: &str) -> Option<PathBuf> {
+    let local = PathBuf::from(local_bin(bin_name));
+    if local.is_absolute() {
+        return Some(local);
+    }
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(bin_name))
+        .find(|candidate| {
+            candidate
+                .metadata()
+                .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        })
+}
+
+/// Resolves a data file shipped with WindUSB, preferring the AppImage's
+/// `share/windusb` directory over the system-wide install.
+pub fn local_data(name: &str) -> PathBuf {
+    if let Ok(appdir) = std::env::var("APPDIR") {
+        let local_path = Path::new(&appdir).join("share/windusb").join(name);
+        if local_path.exists() {
+            return local_path;
+        }
+    }
+    Path::new("/usr/share/windusb").join(name)
+}
@@ -0,0 +1,78 @@
+//! Releasing the filesystems a drive has mounted before it is erased.
+//!
+//! Each one gets a plain `umount`; nothing is lazily detached, so files a
+//! file manager or shell still holds open are reported instead of being cut
+//! off mid-write.
+
+use std::fmt;
+
+use crate::runner::{run_tool, CommandRunner, Invocation, SystemRunner};
+use crate::safety::DiskInspector;
+
+/// A process keeping a mountpoint busy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusyProcess {
+    pub pid: u32,
+    /// Short command name from `/proc/<pid>/comm`.
+    pub command: String,
+}
+
+impl fmt::Display for BusyProcess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (pid {})", self.command, self.pid)
+    }
+}
+
+/// A filesystem of the drive that could not be unmounted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusyMount {
+    /// Partition (or whole disk) node, e.g. `/dev/sdb1`.
+    pub device: String,
+    pub mountpoint: String,
+    /// Processes using it; empty if none could be found.
+    pub processes: Vec<BusyProcess>,
+    /// How `umount` failed.
+    pub reason: String,
+}
+
+impl fmt::Display for BusyMount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.device, self.mountpoint)?;
+        if self.processes.is_empty() {
+            return write!(f, " could not be unmounted ({})", self.reason);
+        }
+        let processes: Vec<String> = self.processes.iter().map(|p| p.to_string()).collect();
+        write!(f, " is in use by {}", processes.join(", "))
+    }
+}
+
+/// Unmounts every filesystem on `drive` and its partitions. Those that stay
+/// mounted are returned along with the processes holding them, so the caller
+/// can ask for them to be closed and try again.
+pub fn release_drive_with(
+    runner: &dyn CommandRunner,
+    inspector: &DiskInspector,
+    drive: &str,
+) -> Result<(), Vec<BusyMount>> {
+    let mut busy = Vec::new();
+    for (device, mountpoint) in inspector.mounts_on(drive) {
+        if let Err(err) = run_tool(runner, Invocation::new("umount").arg(&mountpoint)) {
+            busy.push(BusyMount {
+                processes: inspector.processes_using(&mountpoint),
+                device,
+                mountpoint,
+                reason: err.to_string(),
+            });
+        }
+    }
+    if busy.is_empty() {
+        Ok(())
+    } else {
+        Err(busy)
+    }
+}
+
+/// Shorthand for [`release_drive_with`] on the live system.
+pub fn release_drive(drive: &str) -> Result<(), Vec<BusyMount>> {
+    release_drive_with(&SystemRunner, &DiskInspector::new(), drive)
+}
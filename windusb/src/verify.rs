@@ -0,0 +1,114 @@
+//! Reads the written drive back and compares it with the ISO.
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::checksum::sha256;
+use crate::drives::format_size;
+use crate::isofs::IsoImage;
+
+/// How a file on the drive differs from its source in the ISO.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    Missing,
+    Size {
+        expected: u64,
+        actual: u64,
+    },
+    Content,
+    /// The file could not be read back from the drive.
+    Unreadable(String),
+}
+
+/// A file on the drive that does not match the ISO.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMismatch {
+    /// Path relative to the drive root.
+    pub path: String,
+    pub mismatch: Mismatch,
+}
+
+impl fmt::Display for FileMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.mismatch {
+            Mismatch::Missing => write!(f, "{} is missing", self.path),
+            Mismatch::Size { expected, actual } => write!(
+                f,
+                "{} is {} instead of {}",
+                self.path,
+                format_size(*actual),
+                format_size(*expected)
+            ),
+            Mismatch::Content => write!(f, "{} has different contents", self.path),
+            Mismatch::Unreadable(err) => write!(f, "{} cannot be read ({})", self.path, err),
+        }
+    }
+}
+
+/// Bytes [`compare_tree`] will hash on each side for the files `include` accepts.
+pub fn bytes_to_compare(iso: &IsoImage, include: impl Fn(&str) -> bool) -> io::Result<u64> {
+    Ok(iso
+        .walk()?
+        .iter()
+        .filter(|(path, file)| !file.is_dir && include(path))
+        .map(|(_, file)| file.size)
+        .sum())
+}
+
+/// Hashes every file `include` accepts in the ISO and its copy below `root`.
+/// `progress` gets the file being compared and the bytes read back so far.
+/// Errors reading the ISO abort; errors reading the drive are mismatches.
+pub fn compare_tree(
+    iso: &IsoImage,
+    root: &Path,
+    include: impl Fn(&str) -> bool,
+    mut progress: impl FnMut(&str, u64),
+) -> io::Result<Vec<FileMismatch>> {
+    let mut mismatches = Vec::new();
+    let mut hashed = 0;
+    for (path, file) in iso.walk()? {
+        if file.is_dir || !include(&path) {
+            continue;
+        }
+        let mismatch = |mismatch| FileMismatch {
+            path: path.clone(),
+            mismatch,
+        };
+        let copy = root.join(&path);
+        let actual = match copy.metadata() {
+            Ok(meta) => meta.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                mismatches.push(mismatch(Mismatch::Missing));
+                hashed += file.size;
+                continue;
+            }
+            Err(err) => {
+                mismatches.push(mismatch(Mismatch::Unreadable(err.to_string())));
+                hashed += file.size;
+                continue;
+            }
+        };
+        if actual != file.size {
+            mismatches.push(mismatch(Mismatch::Size {
+                expected: file.size,
+                actual,
+            }));
+            hashed += file.size;
+            continue;
+        }
+
+        let start = hashed;
+        let expected = sha256(iso.reader(&file), |_| {})
+            .map_err(|err| io::Error::new(err.kind(), format!("{}: {}", path, err)))?;
+        let written = File::open(&copy).and_then(|f| sha256(f, |n| progress(&path, start + n)));
+        hashed += file.size;
+        match written {
+            Ok(hash) if hash == expected => {}
+            Ok(_) => mismatches.push(mismatch(Mismatch::Content)),
+            Err(err) => mismatches.push(mismatch(Mismatch::Unreadable(err.to_string()))),
+        }
+    }
+    Ok(mismatches)
+}
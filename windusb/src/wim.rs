@@ -0,0 +1,266 @@
+//! Reads the header and XML metadata of `install.wim` / `install.esd`.
+
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+const MAGIC: &[u8; 8] = b"MSWIM\0\0\0";
+const HEADER_SIZE: usize = 208;
+
+/// Resource header flag: the resource is compressed.
+const RESOURCE_COMPRESSED: u8 = 0x04;
+
+/// The XML of real images is a few hundred KiB; anything far bigger is corrupt.
+const MAX_XML_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Location of a resource inside the WIM file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resource {
+    pub offset: u64,
+    /// Bytes the resource takes in the file.
+    pub stored_size: u64,
+    /// Bytes once decompressed.
+    pub size: u64,
+    pub flags: u8,
+}
+
+impl Resource {
+    fn parse(b: &[u8]) -> Self {
+        let mut stored = [0; 8];
+        stored[..7].copy_from_slice(&b[..7]);
+        Resource {
+            stored_size: u64::from_le_bytes(stored),
+            flags: b[7],
+            offset: u64::from_le_bytes(b[8..16].try_into().unwrap()),
+            size: u64::from_le_bytes(b[16..24].try_into().unwrap()),
+        }
+    }
+}
+
+/// Fixed header at the start of every WIM (and ESD) file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WimHeader {
+    pub flags: u32,
+    /// 1-based number of this piece of a split (`.swm`) set.
+    pub part_number: u16,
+    pub total_parts: u16,
+    pub image_count: u32,
+    pub xml: Resource,
+    /// Index of the bootable image, 0 if none.
+    pub boot_index: u32,
+}
+
+/// Reads and checks the WIM header.
+pub fn read_header(reader: &mut impl Read) -> io::Result<WimHeader> {
+    let mut b = [0; HEADER_SIZE];
+    reader.read_exact(&mut b)?;
+    if &b[..8] != MAGIC {
+        return Err(invalid("not a WIM file"));
+    }
+    Ok(WimHeader {
+        flags: u32::from_le_bytes(b[16..20].try_into().unwrap()),
+        part_number: u16::from_le_bytes([b[40], b[41]]),
+        total_parts: u16::from_le_bytes([b[42], b[43]]),
+        image_count: u32::from_le_bytes(b[44..48].try_into().unwrap()),
+        xml: Resource::parse(&b[72..96]),
+        boot_index: u32::from_le_bytes(b[120..124].try_into().unwrap()),
+    })
+}
+
+/// The XML metadata document, decoded from UTF-16.
+pub fn read_xml(reader: &mut (impl Read + Seek)) -> io::Result<String> {
+    let header = read_header(reader)?;
+    let xml = header.xml;
+    if xml.flags & RESOURCE_COMPRESSED != 0 {
+        return Err(invalid("compressed XML metadata is not supported"));
+    }
+    if xml.size > MAX_XML_SIZE {
+        return Err(invalid("XML metadata is implausibly large"));
+    }
+    reader.seek(SeekFrom::Start(xml.offset))?;
+    let mut raw = vec![0; xml.size as usize];
+    reader.read_exact(&mut raw)?;
+    let units: Vec<u16> = raw
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    let text = String::from_utf16_lossy(&units);
+    Ok(text.trim_start_matches('\u{feff}').to_string())
+}
+
+/// Every edition (image) described by the WIM's XML metadata.
+pub fn read_editions(reader: &mut (impl Read + Seek)) -> io::Result<Vec<Edition>> {
+    parse_editions(&read_xml(reader)?)
+}
+
+/// CPU architecture an image is built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Architecture {
+    X86,
+    X64,
+    Arm,
+    Arm64,
+    Ia64,
+}
+
+impl Architecture {
+    /// Maps the `PROCESSOR_ARCHITECTURE_*` value found in `<ARCH>`.
+    fn from_code(code: u32) -> Option<Self> {
+        match code {
+            0 => Some(Architecture::X86),
+            5 => Some(Architecture::Arm),
+            6 => Some(Architecture::Ia64),
+            9 => Some(Architecture::X64),
+            12 => Some(Architecture::Arm64),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Architecture::X86 => "x86",
+            Architecture::X64 => "x64",
+            Architecture::Arm => "ARM",
+            Architecture::Arm64 => "ARM64",
+            Architecture::Ia64 => "IA-64",
+        })
+    }
+}
+
+/// Windows version of an image, e.g. 10.0.22631.2428.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WindowsVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub build: u32,
+    pub sp_build: u32,
+}
+
+impl fmt::Display for WindowsVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.major, self.minor, self.build, self.sp_build
+        )
+    }
+}
+
+/// One installable image inside the WIM.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edition {
+    /// 1-based image index, as used by `wimlib-imagex` and DISM.
+    pub index: u32,
+    /// Display name, e.g. "Windows 11 Pro".
+    pub name: String,
+    pub description: Option<String>,
+    /// Edition identifier, e.g. "Professional".
+    pub edition_id: Option<String>,
+    /// "Client" or "Server" (and variants such as "Server Core").
+    pub installation_type: Option<String>,
+    pub version: Option<WindowsVersion>,
+    pub languages: Vec<String>,
+    pub architecture: Option<Architecture>,
+    /// Size of the installed files in bytes.
+    pub size: u64,
+}
+
+impl Edition {
+    /// One-line summary such as "build 22631 · en-US · x64 · 16.4G".
+    pub fn summary(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(version) = self.version {
+            parts.push(format!("build {}", version.build));
+        }
+        if !self.languages.is_empty() {
+            parts.push(self.languages.join(", "));
+        }
+        if let Some(arch) = self.architecture {
+            parts.push(arch.to_string());
+        }
+        if self.size > 0 {
+            parts.push(crate::drives::format_size(self.size));
+        }
+        parts.join(" · ")
+    }
+}
+
+/// Parses the `<WIM>` XML document into editions, ordered by index.
+pub fn parse_editions(xml: &str) -> io::Result<Vec<Edition>> {
+    let doc = roxmltree::Document::parse(xml).map_err(|err| invalid(err.to_string()))?;
+    let mut editions: Vec<Edition> = doc
+        .root_element()
+        .children()
+        .filter(|n| n.has_tag_name("IMAGE"))
+        .filter_map(|image| {
+            let index = image.attribute("INDEX")?.trim().parse().ok()?;
+            let windows = child(image, "WINDOWS");
+            let text = |node: Option<roxmltree::Node>, name: &str| {
+                node.and_then(|n| child(n, name))
+                    .and_then(|n| n.text())
+                    .map(|t| t.trim().to_string())
+                    .filter(|t| !t.is_empty())
+            };
+            let number = |node: Option<roxmltree::Node>, name: &str| {
+                text(node, name).and_then(|t| parse_number(&t))
+            };
+            let version = windows.and_then(|w| child(w, "VERSION")).map(|v| {
+                let v = Some(v);
+                WindowsVersion {
+                    major: number(v, "MAJOR").unwrap_or(0) as u32,
+                    minor: number(v, "MINOR").unwrap_or(0) as u32,
+                    build: number(v, "BUILD").unwrap_or(0) as u32,
+                    sp_build: number(v, "SPBUILD").unwrap_or(0) as u32,
+                }
+            });
+            let languages = windows
+                .and_then(|w| child(w, "LANGUAGES"))
+                .map(|langs| {
+                    langs
+                        .children()
+                        .filter(|n| n.has_tag_name("LANGUAGE"))
+                        .filter_map(|n| n.text())
+                        .map(|t| t.trim().to_string())
+                        .collect()
+                })
+                .unwrap_or_default();
+            Some(Edition {
+                index,
+                name: text(Some(image), "DISPLAYNAME")
+                    .or_else(|| text(Some(image), "NAME"))
+                    .unwrap_or_else(|| format!("Image {}", index)),
+                description: text(Some(image), "DISPLAYDESCRIPTION")
+                    .or_else(|| text(Some(image), "DESCRIPTION")),
+                edition_id: text(windows, "EDITIONID").or_else(|| text(Some(image), "FLAGS")),
+                installation_type: text(windows, "INSTALLATIONTYPE"),
+                version,
+                languages,
+                architecture: number(windows, "ARCH")
+                    .and_then(|code| Architecture::from_code(code as u32)),
+                size: number(Some(image), "TOTALBYTES").unwrap_or(0),
+            })
+        })
+        .collect();
+    editions.sort_by_key(|e| e.index);
+    Ok(editions)
+}
+
+fn child<'a, 'input>(
+    node: roxmltree::Node<'a, 'input>,
+    name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+    node.children().find(|n| n.has_tag_name(name))
+}
+
+/// Numbers in WIM XML are decimal, except for a few written as `0x...`.
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
@@ -0,0 +1,78 @@
+//! Tracks how much data has reached one block device, and how much is still
+//! waiting in the page cache for it, without counting other disks' I/O.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `/sys/block/<dev>/stat` counts in 512-byte units whatever the sector size.
+const STAT_SECTOR_SIZE: u64 = 512;
+
+/// Index of "sectors written" among the fields of `/sys/block/<dev>/stat`.
+const SECTORS_WRITTEN_FIELD: usize = 6;
+
+/// Writeback counters of one whole disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceWriteback {
+    /// `/sys/block/<dev>/stat`.
+    stat: PathBuf,
+    /// `<debugfs>/bdi/<major:minor>/stats`, if the disk's `dev` file was readable.
+    bdi_stats: Option<PathBuf>,
+}
+
+impl DeviceWriteback {
+    /// Counters for `device`, e.g. `/dev/sdb`; `None` if it is not a disk in sysfs.
+    pub fn for_device(device: &str) -> Option<Self> {
+        DeviceWriteback::with_roots(device, Path::new("/sys"), Path::new("/sys/kernel/debug"))
+    }
+
+    /// Like [`for_device`](Self::for_device) against other sysfs and debugfs mounts.
+    pub fn with_roots(device: &str, sys: &Path, debugfs: &Path) -> Option<Self> {
+        let node = fs::canonicalize(device).unwrap_or_else(|_| PathBuf::from(device));
+        let name = node.file_name()?.to_string_lossy().into_owned();
+        let block = sys.join("block").join(&name);
+        let stat = block.join("stat");
+        if !stat.exists() {
+            return None;
+        }
+        let bdi_stats = fs::read_to_string(block.join("dev"))
+            .ok()
+            .map(|dev| debugfs.join("bdi").join(dev.trim()).join("stats"));
+        Some(DeviceWriteback { stat, bdi_stats })
+    }
+
+    /// Bytes the disk has written since it appeared.
+    pub fn bytes_written(&self) -> Option<u64> {
+        parse_sectors_written(&fs::read_to_string(&self.stat).ok()?)
+            .map(|sectors| sectors * STAT_SECTOR_SIZE)
+    }
+
+    /// Dirty and under-writeback bytes cached for the disk. Needs debugfs,
+    /// which is usually only mounted and readable for root.
+    pub fn pending_bytes(&self) -> Option<u64> {
+        parse_bdi_pending(&fs::read_to_string(self.bdi_stats.as_ref()?).ok()?)
+    }
+}
+
+/// Sectors written, from the contents of `/sys/block/<dev>/stat`.
+pub fn parse_sectors_written(stat: &str) -> Option<u64> {
+    stat.split_whitespace()
+        .nth(SECTORS_WRITTEN_FIELD)?
+        .parse()
+        .ok()
+}
+
+/// `BdiReclaimable` plus `BdiWriteback` bytes from a debugfs bdi `stats` file.
+pub fn parse_bdi_pending(stats: &str) -> Option<u64> {
+    let mut found = false;
+    let mut kb = 0;
+    for line in stats.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        if matches!(key.trim(), "BdiReclaimable" | "BdiWriteback") {
+            kb += value.split_whitespace().next()?.parse::<u64>().ok()?;
+            found = true;
+        }
+    }
+    found.then_some(kb * 1024)
+}
@@ -0,0 +1,134 @@
+mod support;
+
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use support::{flash, mount_points, ok, FakeDrive, FakeRunner};
+use windusb::{
+    CommandRunner, DiskInspector, FlashError, FlashEvent, Invocation, Output, SystemRunner,
+};
+
+/// Handler that cancels the job when the tool it answers runs.
+fn cancelling(
+    cancel: &Arc<AtomicBool>,
+) -> impl Fn(&Invocation) -> std::io::Result<Output> + Send + Sync + 'static {
+    let cancel = cancel.clone();
+    move |_| {
+        cancel.store(true, Ordering::Relaxed);
+        Ok(ok(""))
+    }
+}
+
+#[test]
+fn cancelled_before_start_runs_nothing() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+    let cancel = Arc::new(AtomicBool::new(true));
+
+    let (result, events) = flash(drive.flasher().cancel(cancel), &runner);
+
+    assert_eq!(result, Err(FlashError::Cancelled));
+    assert_eq!(
+        events.last(),
+        Some(&FlashEvent::Failed {
+            code: FlashError::CANCELLED,
+            message: "Cancelled".into(),
+        })
+    );
+    assert!(runner.calls().is_empty());
+}
+
+#[test]
+fn cancelling_mid_write_releases_mounts_and_erases_the_drive() {
+    let drive = FakeDrive::new();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let runner =
+        Arc::new(FakeRunner::new().on("wimlib-imagex", Some("split"), cancelling(&cancel)));
+
+    let (result, _) = flash(drive.flasher().cancel(cancel), &runner);
+
+    assert_eq!(result, Err(FlashError::Cancelled));
+    let commands = runner.commands();
+    assert!(!runner.ran("sync"), "{:#?}", commands);
+    let split = commands
+        .iter()
+        .position(|c| c.starts_with("wimlib-imagex split"))
+        .unwrap();
+    let mounts = mount_points(&runner);
+    assert_eq!(
+        commands[split + 1..],
+        [
+            format!("umount {}", mounts[0]),
+            format!("umount {}", mounts[1]),
+            format!("wipefs -af {}", drive.name()),
+        ]
+    );
+}
+
+#[test]
+fn unsafe_drive_is_not_erased_on_cancel() {
+    let drive = FakeDrive::new();
+    let system = tempfile::tempdir().unwrap();
+    fs::create_dir_all(system.path().join("sys/block/sdz")).unwrap();
+    fs::write(system.path().join("sys/block/sdz/removable"), "0\n").unwrap();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let runner =
+        Arc::new(FakeRunner::new().on("blockdev", Some("--getsize64"), cancelling(&cancel)));
+
+    let (result, _) = flash(
+        drive
+            .flasher()
+            .inspector(DiskInspector::with_roots(
+                system.path().join("proc"),
+                system.path().join("sys"),
+            ))
+            .cancel(cancel),
+        &runner,
+    );
+
+    assert_eq!(result, Err(FlashError::Cancelled));
+    assert!(!runner.ran("wipefs"));
+}
+
+#[test]
+fn cancelling_before_the_wipe_leaves_the_drive_untouched() {
+    let drive = FakeDrive::new();
+    let cancel = Arc::new(AtomicBool::new(false));
+    let runner =
+        Arc::new(FakeRunner::new().on("blockdev", Some("--flushbufs"), cancelling(&cancel)));
+
+    let (result, _) = flash(drive.flasher().cancel(cancel), &runner);
+
+    assert_eq!(result, Err(FlashError::Cancelled));
+    assert!(runner.ran("blockdev"));
+    assert!(!runner.ran("wipefs"), "{:#?}", runner.commands());
+}
+
+#[test]
+fn system_runner_terminates_the_running_tool() {
+    let cancel = Arc::new(AtomicBool::new(false));
+    let cancel_t = cancel.clone();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        cancel_t.store(true, Ordering::Relaxed);
+    });
+    let started = Instant::now();
+
+    let out = SystemRunner
+        .run_cancellable(
+            &Invocation::new("sh").args(["-c", "echo started; exec sleep 10"]),
+            &mut |_| {},
+            &cancel,
+        )
+        .unwrap();
+
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert_eq!(out.status, None);
+    assert_eq!(out.stdout, "started\n");
+    assert!(SystemRunner
+        .run_cancellable(&Invocation::new("true"), &mut |_| {}, &cancel)
+        .is_err());
+}
@@ -0,0 +1,190 @@
+mod support;
+
+use std::path::Path;
+use std::sync::Arc;
+
+use support::{flash, ok, FakeDrive, FakeRunner};
+use windusb::capacity::{
+    check_iso_fits_with, check_space, exported_size, fat32_cluster_size, required_space,
+};
+use windusb::iso::parse_listing;
+use windusb::wim::parse_editions;
+use windusb::{DriveLayout, FlashError, FlashEvent, IsoEntry};
+
+const GB: u64 = 1_000_000_000;
+
+/// Trimmed `7z l` output for a Windows 11 ISO.
+const LISTING: &str = "\
+7-Zip 23.01 (x64) : Copyright (c) 1999-2023 Igor Pavlov : 2023-06-20
+
+Scanning the drive for archives:
+1 file, 6812706816 bytes (6498 MiB)
+
+Listing archive: Win11_23H2_English_x64.iso
+
+--
+Path = Win11_23H2_English_x64.iso
+Type = Udf
+
+   Date      Time    Attr         Size   Compressed  Name
+------------------- ----- ------------ ------------  ------------------------
+2023-10-20 04:12:40 D....                            boot
+2023-10-20 04:12:40 D....                            sources
+2023-10-20 04:12:40 .....       454656       454656  bootmgr
+2023-10-20 04:12:40 .....        16384        16384  boot/bcd
+2023-10-20 04:12:40 .....       128000       128000  sources/en-us/setup wizard.mui
+2023-10-20 04:12:40 .....   6300000000   6300000000  sources/install.wim
+------------------- ----- ------------ ------------  ------------------------
+2023-10-20 04:12:40         6300599040   6300599040  4 files, 2 folders
+";
+
+fn entry(path: &str, size: u64) -> IsoEntry {
+    IsoEntry {
+        path: path.to_string(),
+        size,
+        is_dir: false,
+    }
+}
+
+#[test]
+fn parses_7z_listing_columns() {
+    let entries = parse_listing(LISTING);
+
+    assert_eq!(entries.len(), 6);
+    assert!(entries[0].is_dir);
+    assert_eq!(entries[0].path, "boot");
+    assert_eq!(entries[2], entry("bootmgr", 454_656));
+    assert_eq!(entries[4], entry("sources/en-us/setup wizard.mui", 128_000));
+    assert_eq!(entries[5].size, 6_300_000_000);
+}
+
+#[test]
+fn listing_without_table_yields_nothing() {
+    assert!(parse_listing("sources/install.wim\n").is_empty());
+}
+
+#[test]
+fn small_files_are_rounded_up_to_clusters() {
+    let entries = [entry("a", 1), entry("b", 4097)];
+    // Root directory cluster, one cluster for `a`, two for `b`.
+    assert_eq!(required_space(&entries, 4096), 4 * 4096);
+}
+
+#[test]
+fn install_image_pays_per_piece_overhead() {
+    let plain = required_space(&[entry("sources/boot.wim", 6 * GB)], 4096);
+    let split = required_space(&[entry("sources/install.wim", 6 * GB)], 4096);
+    assert!(split > plain, "{} <= {}", split, plain);
+}
+
+#[test]
+fn cluster_size_grows_with_the_volume() {
+    assert_eq!(fat32_cluster_size(7 * GB), 4096);
+    assert_eq!(fat32_cluster_size(15 * GB), 8192);
+    assert_eq!(fat32_cluster_size(64 * GB), 32768);
+}
+
+#[test]
+fn windows_11_does_not_fit_on_a_nominal_4gb_stick() {
+    let entries = parse_listing(LISTING);
+
+    let small = check_space(&entries, 4 * GB, DriveLayout::Fat32);
+    assert!(!small.fits());
+
+    let large = check_space(&entries, 16 * GB, DriveLayout::Fat32);
+    assert!(large.fits());
+    assert!(!large.is_tight());
+
+    let tight = check_space(&entries, small.required + 100_000_000, DriveLayout::Fat32);
+    assert!(tight.fits() && tight.is_tight(), "{:?}", tight);
+}
+
+#[test]
+fn ntfs_skips_the_split_overhead_but_reserves_its_own_room() {
+    let entries = parse_listing(LISTING);
+    let drive = 6_350_000_000;
+
+    let fat32 = check_space(&entries, drive, DriveLayout::Fat32);
+    let ntfs = check_space(&entries, drive, DriveLayout::Ntfs);
+
+    assert!(ntfs.required < fat32.required);
+    // Metadata and the UEFI:NTFS partition take more than the FATs do.
+    assert!(ntfs.available < fat32.available);
+    assert!(fat32.fits());
+    assert!(!ntfs.fits(), "{:?}", ntfs);
+}
+
+#[test]
+fn checks_iso_against_drive_size() {
+    let runner = FakeRunner::new()
+        .on("7z", Some("l"), |_| Ok(ok(LISTING)))
+        .on("blockdev", Some("--getsize64"), |_| Ok(ok("8004304896\n")));
+
+    let space = check_iso_fits_with(
+        &runner,
+        Path::new("/isos/win11.iso"),
+        "/dev/sdb",
+        &[],
+        DriveLayout::Fat32,
+    )
+    .unwrap();
+
+    assert!(space.fits());
+    assert!(space.available < 8_004_304_896);
+}
+
+#[test]
+fn unknown_drive_size_skips_the_check() {
+    let runner = FakeRunner::new().on("7z", Some("l"), |_| Ok(ok(LISTING)));
+    assert_eq!(
+        check_iso_fits_with(
+            &runner,
+            Path::new("/isos/win11.iso"),
+            "/dev/sdb",
+            &[],
+            DriveLayout::Fat32
+        ),
+        None
+    );
+}
+
+#[test]
+fn flasher_refuses_a_drive_that_is_too_small_before_wiping() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(
+        FakeRunner::new()
+            .on("7z", Some("l"), |_| Ok(ok(LISTING)))
+            .on("blockdev", Some("--getsize64"), |_| Ok(ok("4000000000\n"))),
+    );
+
+    let (result, events) = flash(drive.flasher(), &runner);
+
+    assert!(matches!(result, Err(FlashError::InsufficientSpace(s)) if !s.fits()));
+    assert!(matches!(
+        events.last(),
+        Some(FlashEvent::Failed {
+            code: "insufficient_space",
+            ..
+        })
+    ));
+    assert!(!runner.ran("wipefs"));
+    assert!(!runner.ran("mkdir"));
+}
+
+#[test]
+fn selected_editions_are_weighed_against_the_largest() {
+    let editions = parse_editions(
+        r#"<WIM>
+            <IMAGE INDEX="1"><NAME>Windows 11 Home</NAME><TOTALBYTES>15000000000</TOTALBYTES></IMAGE>
+            <IMAGE INDEX="2"><NAME>Windows 11 Education</NAME><TOTALBYTES>16000000000</TOTALBYTES></IMAGE>
+            <IMAGE INDEX="3"><NAME>Windows 11 Pro</NAME><TOTALBYTES>16000000000</TOTALBYTES></IMAGE>
+        </WIM>"#,
+    )
+    .unwrap();
+
+    assert_eq!(exported_size(6 * GB, &editions, &[1]), 5_625_000_000);
+    assert_eq!(exported_size(6 * GB, &editions, &[3]), 6 * GB);
+    assert_eq!(exported_size(6 * GB, &editions, &[1, 3]), 6 * GB);
+    assert_eq!(exported_size(6 * GB, &editions, &[9]), 6 * GB);
+    assert_eq!(exported_size(6 * GB, &[], &[1]), 6 * GB);
+}
@@ -0,0 +1,155 @@
+use std::fs;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc;
+
+use windusb::checksum::hash_file;
+use windusb::{verify_iso, Catalogue, ChecksumEvent, IsoHashes, KnownImage, Verdict};
+
+const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+const ABC_SHA1: &str = "a9993e364706816aba3e25717850c26c9cd0d89d";
+
+fn catalogue() -> Catalogue {
+    Catalogue::parse(&format!(
+        r#"[
+            {{"file": "Win11_English_x64.iso", "edition": "Windows 11 23H2", "language": "English",
+              "build": "22631", "sha256": "{}"}},
+            {{"edition": "Windows 10 22H2", "language": "German", "build": "19045",
+              "sha1": "{}"}}
+        ]"#,
+        ABC_SHA256.to_uppercase(),
+        ABC_SHA1
+    ))
+    .unwrap()
+}
+
+fn hashes(sha256: &str, sha1: &str) -> IsoHashes {
+    IsoHashes {
+        sha256: sha256.to_string(),
+        sha1: sha1.to_string(),
+    }
+}
+
+#[test]
+fn hashes_files_with_both_algorithms() {
+    let dir = tempfile::tempdir().unwrap();
+    let iso = dir.path().join("win.iso");
+    fs::write(&iso, b"abc").unwrap();
+    let mut reports = Vec::new();
+
+    let result = hash_file(&iso, &AtomicBool::new(false), |done, total| {
+        reports.push((done, total))
+    })
+    .unwrap();
+
+    assert_eq!(result, hashes(ABC_SHA256, ABC_SHA1));
+    assert_eq!(reports.first(), Some(&(0, 3)));
+    assert_eq!(reports.last(), Some(&(3, 3)));
+}
+
+#[test]
+fn cancelled_hashing_is_interrupted() {
+    let dir = tempfile::tempdir().unwrap();
+    let iso = dir.path().join("win.iso");
+    fs::write(&iso, b"abc").unwrap();
+
+    let err = hash_file(&iso, &AtomicBool::new(true), |_, _| {}).unwrap_err();
+
+    assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+}
+
+#[test]
+fn matching_hash_is_verified_regardless_of_case_or_name() {
+    let verdict = catalogue().check("renamed.iso", &hashes(ABC_SHA256, "0"));
+
+    let Verdict::Verified(image) = verdict else {
+        panic!("not verified: {:?}", verdict);
+    };
+    assert_eq!(image.summary(), "Windows 11 23H2 · English · build 22631");
+}
+
+#[test]
+fn sha1_alone_is_enough() {
+    let verdict = catalogue().check("de.iso", &hashes("0", ABC_SHA1));
+
+    assert!(matches!(verdict, Verdict::Verified(ref i) if i.language == "German"));
+}
+
+#[test]
+fn known_file_name_with_other_hash_is_a_mismatch() {
+    let verdict = catalogue().check("win11_english_x64.iso", &hashes("0", "0"));
+
+    assert!(matches!(verdict, Verdict::Mismatch(ref i) if i.build == "22631"));
+    assert!(verdict.to_string().starts_with("checksum mismatch"));
+}
+
+#[test]
+fn anything_else_is_unknown() {
+    assert_eq!(
+        catalogue().check("custom.iso", &hashes("0", "0")),
+        Verdict::Unknown
+    );
+}
+
+#[test]
+fn user_entries_extend_the_bundled_catalogue() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("iso-hashes.json");
+    fs::write(
+        &path,
+        r#"[{"edition": "Windows 11 LTSC", "language": "English", "build": "26100", "sha256": "ff"}]"#,
+    )
+    .unwrap();
+
+    let mut catalogue = Catalogue::bundled();
+    let bundled = catalogue.images().len();
+    catalogue.extend(Catalogue::load_file(&path).unwrap());
+
+    assert_eq!(catalogue.images().len(), bundled + 1);
+    assert!(matches!(
+        catalogue.check("x.iso", &hashes("FF", "0")),
+        Verdict::Verified(KnownImage { ref build, .. }) if build == "26100"
+    ));
+    assert!(Catalogue::parse("{").is_err());
+}
+
+#[test]
+fn verifies_in_the_background() {
+    let dir = tempfile::tempdir().unwrap();
+    let iso = dir.path().join("Win11_English_x64.iso");
+    fs::write(&iso, b"not the real thing").unwrap();
+    let (tx, rx) = mpsc::channel();
+
+    verify_iso(iso, catalogue(), tx);
+    let events: Vec<ChecksumEvent> = rx.iter().collect();
+
+    assert!(matches!(
+        events.first(),
+        Some(ChecksumEvent::Hashing {
+            hashed: 0,
+            total: 18
+        })
+    ));
+    assert!(matches!(
+        events.last(),
+        Some(ChecksumEvent::Done {
+            verdict: Verdict::Mismatch(_),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn bundled_catalogue_lists_official_images() {
+    let catalogue = Catalogue::bundled();
+
+    assert!(!catalogue.images().is_empty());
+    for image in catalogue.images() {
+        let sha256 = image.sha256.as_deref().unwrap_or_default();
+        assert!(
+            sha256.len() == 64 && sha256.bytes().all(|b| b.is_ascii_hexdigit()),
+            "{:?}",
+            image
+        );
+        assert!(image.file.is_some(), "{:?}", image);
+    }
+}
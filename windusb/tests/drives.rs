@@ -0,0 +1,110 @@
+mod support;
+
+use support::{ok, FakeRunner};
+use windusb::drives::{format_size, parse_lsblk, partition_path};
+use windusb::list_usb_drives_with;
+
+const LSBLK: &str = r#"{
+   "blockdevices": [
+      {
+         "name": "/dev/nvme0n1", "type": "disk", "size": 512110190592,
+         "vendor": null, "model": "Samsung SSD 980 PRO 512GB", "serial": "S5GXNF0R",
+         "rm": false, "rota": false, "tran": "nvme",
+         "mountpoint": null, "label": null, "fstype": null,
+         "children": [
+            {
+               "name": "/dev/nvme0n1p1", "type": "part", "size": 536870912,
+               "vendor": null, "model": null, "serial": null,
+               "rm": false, "rota": false, "tran": null,
+               "mountpoint": "/boot/efi", "label": null, "fstype": "vfat"
+            }
+         ]
+      },{
+         "name": "/dev/sdb", "type": "disk", "size": 15518924800,
+         "vendor": "SanDisk ", "model": "Ultra Fit usb stick", "serial": "4C530001",
+         "rm": true, "rota": false, "tran": "usb",
+         "mountpoint": null, "label": null, "fstype": null,
+         "children": [
+            {
+               "name": "/dev/sdb1", "type": "part", "size": 15517876224,
+               "vendor": null, "model": null, "serial": null,
+               "rm": true, "rota": false, "tran": null,
+               "mountpoint": "/run/media/user/WIN11", "label": "WIN11", "fstype": "vfat"
+            }
+         ]
+      }
+   ]
+}"#;
+
+/// util-linux before 2.33 quotes every value.
+const LSBLK_LEGACY: &str = r#"{
+   "blockdevices": [
+      {"name": "/dev/sdc", "type": "disk", "size": "8004304896", "vendor": "Kingston", "model": "DataTraveler 3.0",
+       "serial": null, "rm": "1", "rota": "0", "tran": "usb", "mountpoint": null, "label": null, "fstype": null}
+   ]
+}"#;
+
+#[test]
+fn parses_disks_with_partitions() {
+    let devices = parse_lsblk(LSBLK).unwrap();
+    assert_eq!(devices.len(), 2);
+
+    let stick = &devices[1];
+    assert_eq!(stick.name, "/dev/sdb");
+    assert!(stick.is_disk() && stick.is_usb());
+    assert_eq!(stick.size, 15_518_924_800);
+    assert_eq!(stick.vendor.as_deref(), Some("SanDisk"));
+    assert_eq!(stick.model.as_deref(), Some("Ultra Fit usb stick"));
+    assert_eq!(stick.serial.as_deref(), Some("4C530001"));
+    assert!(stick.removable);
+    assert!(!stick.rotational);
+
+    let part = &stick.children[0];
+    assert_eq!(part.kind, "part");
+    assert_eq!(part.mountpoint.as_deref(), Some("/run/media/user/WIN11"));
+    assert_eq!(part.label.as_deref(), Some("WIN11"));
+    assert_eq!(part.fstype.as_deref(), Some("vfat"));
+}
+
+#[test]
+fn accepts_string_values_from_old_lsblk() {
+    let devices = parse_lsblk(LSBLK_LEGACY).unwrap();
+    assert_eq!(devices[0].size, 8_004_304_896);
+    assert!(devices[0].removable);
+    assert!(!devices[0].rotational);
+}
+
+#[test]
+fn lists_only_whole_usb_disks() {
+    let runner = FakeRunner::new().on("lsblk", None, |_| Ok(ok(LSBLK)));
+
+    let drives = list_usb_drives_with(&runner);
+
+    assert_eq!(drives.len(), 1);
+    assert_eq!(drives[0].path, "/dev/sdb");
+    assert_eq!(drives[0].description, "14.5G SanDisk Ultra Fit usb stick");
+    assert_eq!(drives[0].device.children.len(), 1);
+    assert_eq!(runner.calls()[0].args[..3], ["-J", "-b", "-p"]);
+}
+
+#[test]
+fn lsblk_failure_yields_no_drives() {
+    let runner = FakeRunner::new().fail("lsblk", None, 1, "lsblk: unknown column");
+    assert!(list_usb_drives_with(&runner).is_empty());
+}
+
+#[test]
+fn formats_sizes_like_lsblk() {
+    assert_eq!(format_size(512), "512B");
+    assert_eq!(format_size(8 * 1024 * 1024 * 1024), "8G");
+    assert_eq!(format_size(15_518_924_800), "14.5G");
+}
+
+#[test]
+fn partition_nodes_follow_the_kernel_naming_rule() {
+    assert_eq!(partition_path("/dev/sdb", 1), "/dev/sdb1");
+    assert_eq!(partition_path("/dev/nvme0n1", 1), "/dev/nvme0n1p1");
+    assert_eq!(partition_path("/dev/mmcblk0", 2), "/dev/mmcblk0p2");
+    assert_eq!(partition_path("/dev/loop7", 1), "/dev/loop7p1");
+    assert_eq!(partition_path("/dev/nbd0", 1), "/dev/nbd0p1");
+}
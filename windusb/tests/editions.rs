@@ -0,0 +1,205 @@
+mod support;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+
+use support::image::{udf, wim, UdfOptions};
+use support::{flash, ok, FakeDrive, FakeRunner};
+use windusb::{FlashError, FlashEvent, Invocation, Phase};
+
+fn staging_is_empty(drive: &FakeDrive) -> bool {
+    fs::read_dir(drive.staging()).unwrap().next().is_none()
+}
+
+fn wimlib(runner: &FakeRunner, command: &str) -> Vec<Invocation> {
+    runner
+        .calls()
+        .into_iter()
+        .filter(|c| c.program == "wimlib-imagex" && c.args[0] == command)
+        .collect()
+}
+
+/// Makes `wimlib-imagex export` produce a destination file of `size` bytes.
+fn exports_bytes(size: u64) -> FakeRunner {
+    FakeRunner::new().on("wimlib-imagex", Some("export"), move |inv| {
+        let file = fs::File::create(&inv.args[3])?;
+        file.set_len(size)?;
+        Ok(ok(""))
+    })
+}
+
+#[test]
+fn exports_selected_editions_in_order() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(drive.flasher().editions([6, 1]), &runner);
+    assert_eq!(result, Ok(()));
+
+    let exports = wimlib(&runner, "export");
+    assert_eq!(exports.len(), 2);
+    for (export, index) in exports.iter().zip(["6", "1"]) {
+        assert!(export.args[1].ends_with("/sources/install.wim"));
+        assert_eq!(export.args[2], index);
+        assert!(export.args[3].starts_with(&format!(
+            "{}/windusb_export_{}_",
+            drive.staging().display(),
+            std::process::id()
+        )));
+        assert!(export.args[3].ends_with("/install.wim"));
+    }
+    assert_eq!(exports[0].args[3], exports[1].args[3]);
+    assert!(staging_is_empty(&drive));
+}
+
+#[test]
+fn each_run_exports_into_a_fresh_private_directory() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(
+        FakeRunner::new().on("wimlib-imagex", Some("export"), |inv| {
+            let staging = std::path::Path::new(&inv.args[3]);
+            assert!(!staging.exists(), "export would append to {:?}", staging);
+            let mode = fs::metadata(staging.parent().unwrap())?
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o700);
+            fs::write(staging, b"exported")?;
+            Ok(ok(""))
+        }),
+    );
+
+    for _ in 0..2 {
+        let (result, _) = flash(drive.flasher().editions([6]), &runner);
+        assert_eq!(result, Ok(()));
+    }
+
+    let exports = wimlib(&runner, "export");
+    assert_ne!(exports[0].args[3], exports[1].args[3]);
+    assert!(staging_is_empty(&drive));
+}
+
+#[test]
+fn export_of_unknown_size_is_split_from_staging() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(drive.flasher().editions([6]), &runner);
+    assert_eq!(result, Ok(()));
+
+    let splits = wimlib(&runner, "split");
+    assert_eq!(splits.len(), 1);
+    assert_eq!(splits[0].args[1], wimlib(&runner, "export")[0].args[3]);
+    assert!(splits[0].args[2].ends_with("/sources/install.swm"));
+}
+
+#[test]
+fn small_export_is_copied_without_splitting() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(exports_bytes(3 << 30));
+
+    let (result, events) = flash(drive.flasher().editions([6]), &runner);
+    assert_eq!(result, Ok(()));
+
+    assert!(wimlib(&runner, "split").is_empty());
+    let copy = runner
+        .calls()
+        .into_iter()
+        .find(|c| c.program == "cp")
+        .unwrap();
+    assert_eq!(copy.args[0], wimlib(&runner, "export")[0].args[3]);
+    assert!(copy.args[1].ends_with("/sources/install.wim"));
+    assert!(staging_is_empty(&drive));
+    assert!(!events
+        .iter()
+        .any(|e| matches!(e, FlashEvent::Progress(p) if p.message.starts_with("Splitting"))));
+}
+
+#[test]
+fn export_above_fat32_limit_is_split() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(exports_bytes(5 << 30));
+
+    let (result, _) = flash(drive.flasher().editions([1, 6]), &runner);
+    assert_eq!(result, Ok(()));
+
+    assert_eq!(wimlib(&runner, "split").len(), 1);
+    assert!(!runner.ran("cp"));
+    assert!(staging_is_empty(&drive));
+}
+
+#[test]
+fn esd_editions_stay_solid() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().on("7z", Some("l"), |_| {
+        Ok(ok(
+            "2024-01-01 00:00:00 ....A  3000000000  sources/install.esd\n",
+        ))
+    }));
+
+    let (result, _) = flash(drive.flasher().editions([2]), &runner);
+    assert_eq!(result, Ok(()));
+
+    let export = &wimlib(&runner, "export")[0];
+    assert!(export.args[1].ends_with("/sources/install.esd"));
+    assert!(export.args[3].ends_with(".esd"));
+    assert!(export.args.contains(&"--solid".to_string()));
+}
+
+#[test]
+fn failed_export_stops_before_writing_the_image() {
+    let drive = FakeDrive::new();
+    let runner =
+        Arc::new(FakeRunner::new().fail("wimlib-imagex", Some("export"), 1, "unknown image"));
+
+    let (result, events) = flash(drive.flasher().editions([6]), &runner);
+
+    assert!(matches!(result, Err(FlashError::Export(ref e)) if e.stderr == "unknown image"));
+    assert!(matches!(
+        events.last(),
+        Some(FlashEvent::Failed {
+            code: "export_failed",
+            ..
+        })
+    ));
+    assert!(wimlib(&runner, "split").is_empty());
+    assert!(!runner.ran("cp"));
+}
+
+#[test]
+fn reports_export_progress() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().on("wimlib-imagex", Some("export"), |_| {
+        std::thread::sleep(std::time::Duration::from_millis(700));
+        Ok(ok(""))
+    }));
+
+    let (result, events) = flash(drive.flasher().editions([6]), &runner);
+    assert_eq!(result, Ok(()));
+
+    let exporting = events
+        .iter()
+        .filter_map(|e| match e {
+            FlashEvent::Progress(p) if p.phase == Phase::ExportingImage => Some(p),
+            _ => None,
+        })
+        .next()
+        .expect("no export progress");
+    assert!(exporting.message.starts_with("Exporting selected editions"));
+}
+
+#[test]
+fn rejects_editions_missing_from_the_image_before_wiping() {
+    let xml = r#"<WIM><IMAGE INDEX="1"><NAME>Windows 11 Home</NAME></IMAGE><IMAGE INDEX="2"><NAME>Windows 11 Pro</NAME></IMAGE></WIM>"#;
+    let drive = FakeDrive::new().with_iso(&udf(
+        &[("sources/install.wim", &wim(xml))],
+        UdfOptions::default(),
+    ));
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(drive.flasher().editions([2, 6]), &runner);
+
+    assert_eq!(result, Err(FlashError::UnknownEdition(6)));
+    assert!(!runner.ran("wipefs"));
+    assert!(!runner.ran("mkdir"));
+}
@@ -0,0 +1,595 @@
+mod support;
+
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+use support::{flash, ok, step, FakeDrive, FakeRunner};
+use windusb::{DiskInspector, DriveLayout, FlashError, FlashEvent, Hazard, PartitionScheme, Phase};
+
+#[test]
+fn runs_full_sequence_in_order() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, events) = flash(drive.flasher(), &runner);
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(events.last(), Some(&FlashEvent::Finished));
+
+    let commands = runner.commands();
+    let dev = drive.name();
+    let expected = [
+        "7z l /isos/win11.iso".to_string(),
+        format!("blockdev --flushbufs {}", dev),
+        format!("wipefs -af {}", dev),
+        format!("sgdisk -Z {}", dev),
+        format!("sgdisk -n=1:0:0 -t=1:0700 {}", dev),
+        format!("partprobe {}", dev),
+        format!("mkfs.fat -F32 -I {}1", dev),
+        format!("mount {}1 /tmp/windusb_usb_", dev),
+        "mount -o loop,ro /isos/win11.iso /tmp/windusb_iso_".to_string(),
+        "7z x /isos/win11.iso -o/tmp/windusb_usb_".to_string(),
+        "wimlib-imagex split /tmp/windusb_iso_".to_string(),
+        "sync".to_string(),
+        "umount -l /tmp/windusb_usb_".to_string(),
+        "umount -l /tmp/windusb_iso_".to_string(),
+    ];
+    let positions: Vec<usize> = expected.iter().map(|p| step(&commands, p)).collect();
+    assert!(
+        positions.windows(2).all(|w| w[0] < w[1]),
+        "steps ran out of order: {:#?}",
+        commands
+    );
+    assert!(!runner.ran("ms-sys") && !runner.ran("parted"));
+}
+
+#[test]
+fn excludes_install_image_from_extraction_and_splits_it() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(drive.flasher(), &runner);
+    assert_eq!(result, Ok(()));
+
+    let calls = runner.calls();
+    let extract = calls
+        .iter()
+        .find(|c| c.program == "7z" && c.args[0] == "x")
+        .unwrap();
+    assert!(extract.args.contains(&"-xr!install.wim".to_string()));
+    assert!(extract.args.contains(&"-y".to_string()));
+
+    let split = calls.iter().find(|c| c.program == "wimlib-imagex").unwrap();
+    assert_eq!(split.args[0], "split");
+    assert!(split.args[1].ends_with("/sources/install.wim"));
+    assert!(split.args[2].ends_with("/sources/install.swm"));
+    assert_eq!(split.args[3], "3400");
+}
+
+#[test]
+fn handles_esd_images() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().on("7z", Some("l"), |_| {
+        Ok(ok(
+            "2024-01-01 00:00:00 ....A  3000000000  sources/install.esd\n",
+        ))
+    }));
+
+    let (result, _) = flash(drive.flasher(), &runner);
+    assert_eq!(result, Ok(()));
+
+    let calls = runner.calls();
+    let split = calls.iter().find(|c| c.program == "wimlib-imagex").unwrap();
+    assert!(split.args[1].ends_with("/sources/install.esd"));
+    assert!(split.args[2].ends_with("/sources/install.esd"));
+}
+
+#[test]
+fn progress_moves_through_phases_in_order() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (_, events) = flash(drive.flasher(), &runner);
+
+    let mut phases: Vec<Phase> = Vec::new();
+    for event in &events {
+        if let FlashEvent::Progress(p) = event {
+            assert!((0.0..=1.0).contains(&p.fraction));
+            if phases.last() != Some(&p.phase) {
+                phases.push(p.phase);
+            }
+        }
+    }
+    assert_eq!(phases.first(), Some(&Phase::Formatting));
+    assert!(phases
+        .windows(2)
+        .all(|w| w[0] != Phase::Syncing || w[1] == Phase::Syncing));
+}
+
+#[test]
+fn rejects_iso_without_install_image_before_touching_the_drive() {
+    let drive = FakeDrive::new();
+    let runner =
+        Arc::new(FakeRunner::new().on("7z", Some("l"), |_| Ok(ok("boot/bcd\nsetup.exe\n"))));
+
+    let (result, events) = flash(drive.flasher(), &runner);
+
+    assert_eq!(result, Err(FlashError::InvalidIso));
+    assert_eq!(
+        events.last(),
+        Some(&FlashEvent::Failed {
+            code: "invalid_iso",
+            message: "Invalid ISO: install.wim/esd not found".into(),
+        })
+    );
+    assert_eq!(runner.commands(), vec!["7z l /isos/win11.iso".to_string()]);
+}
+
+#[test]
+fn refuses_unsafe_drive_before_unmounting_or_wiping() {
+    let drive = FakeDrive::new();
+    let system = tempfile::tempdir().unwrap();
+    fs::create_dir_all(system.path().join("sys/block/sdz")).unwrap();
+    fs::write(system.path().join("sys/block/sdz/removable"), "0\n").unwrap();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, events) = flash(
+        drive.flasher().inspector(DiskInspector::with_roots(
+            system.path().join("proc"),
+            system.path().join("sys"),
+        )),
+        &runner,
+    );
+
+    assert_eq!(
+        result,
+        Err(FlashError::UnsafeDrive(vec![Hazard::NotRemovable]))
+    );
+    assert!(matches!(
+        events.last(),
+        Some(FlashEvent::Failed {
+            code: "unsafe_drive",
+            ..
+        })
+    ));
+    assert!(!runner.ran("umount"));
+    assert!(!runner.ran("wipefs"));
+}
+
+#[test]
+fn partition_failure_stops_before_formatting_and_reports_stderr() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().fail(
+        "sgdisk",
+        Some("-n=1:0:0"),
+        4,
+        "Could not create partition 1 from 34 to 2047\n",
+    ));
+
+    let (result, events) = flash(drive.flasher(), &runner);
+
+    let err = result.unwrap_err();
+    assert!(matches!(err, FlashError::CreatePartition(_)));
+    let tool = err.tool_error().unwrap();
+    assert_eq!(tool.program, "sgdisk");
+    assert_eq!(tool.status, Some(4));
+    assert_eq!(tool.stderr, "Could not create partition 1 from 34 to 2047");
+
+    match events.last() {
+        Some(FlashEvent::Failed { code, message }) => {
+            assert_eq!(*code, "create_partition_failed");
+            assert!(
+                message.contains("Could not create partition 1"),
+                "{}",
+                message
+            );
+        }
+        other => panic!("expected failure event, got {:?}", other),
+    }
+    assert!(!runner.ran("partprobe"));
+    assert!(!runner.ran("mkfs.fat"));
+}
+
+#[test]
+fn each_preparation_step_maps_to_its_own_error() {
+    let cases: [(&str, Option<&str>, &str); 7] = [
+        ("blockdev", None, "flush_buffers_failed"),
+        ("wipefs", None, "wipe_failed"),
+        ("sgdisk", Some("-Z"), "zap_partition_table_failed"),
+        ("partprobe", None, "reload_partition_table_failed"),
+        ("mkfs.fat", None, "format_failed"),
+        ("mount", Some("loop,ro"), "mount_iso_failed"),
+        ("sync", None, "sync_failed"),
+    ];
+    for (program, arg, code) in cases {
+        let drive = FakeDrive::new();
+        let runner = Arc::new(FakeRunner::new().fail(program, arg, 1, "boom"));
+
+        let (result, _) = flash(drive.flasher(), &runner);
+
+        let err = result.unwrap_err();
+        assert_eq!(err.code(), code, "failing {}", program);
+        assert_eq!(err.tool_error().unwrap().stderr, "boom");
+    }
+}
+
+#[test]
+fn tool_that_cannot_start_is_reported_without_status() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().on("mkfs.fat", None, |_| {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No such file or directory",
+        ))
+    }));
+
+    let (result, _) = flash(drive.flasher(), &runner);
+
+    let err = result.unwrap_err();
+    assert!(matches!(err, FlashError::Format(_)));
+    assert_eq!(err.tool_error().unwrap().status, None);
+    assert!(!runner.ran("mount"));
+}
+
+#[test]
+fn usb_mount_failure_stops_the_run() {
+    let drive = FakeDrive::new();
+    let dev_part = format!("{}1", drive.name());
+    let runner = Arc::new(FakeRunner::new().fail("mount", Some(&dev_part), 32, "wrong fs type"));
+
+    let (result, _) = flash(drive.flasher(), &runner);
+
+    assert!(matches!(result, Err(FlashError::MountUsb(_))));
+    assert!(!runner.commands().iter().any(|c| c.starts_with("7z x")));
+}
+
+#[test]
+fn extraction_failure_skips_split() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().fail(
+        "7z",
+        Some("x"),
+        2,
+        "ERROR: Data Error : efi/boot/bootx64.efi",
+    ));
+
+    let (result, _) = flash(drive.flasher(), &runner);
+
+    assert!(matches!(result, Err(FlashError::Extract(_))));
+    assert!(!runner.ran("wimlib-imagex"));
+    assert!(!runner.ran("sync"));
+}
+
+#[test]
+fn split_failure_is_reported() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().fail(
+        "wimlib-imagex",
+        None,
+        47,
+        "[ERROR] Could not write to \"install.swm\": No space left on device",
+    ));
+
+    let (result, events) = flash(drive.flasher(), &runner);
+
+    assert!(matches!(result, Err(FlashError::Split(_))));
+    assert!(!runner.ran("sync"));
+    assert!(matches!(
+        events.last(),
+        Some(FlashEvent::Failed {
+            code: "split_failed",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn split_progress_follows_wimlib_output() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(
+        FakeRunner::new()
+            .on("wimlib-imagex", Some("split"), |_| {
+                Ok(ok("\
+Writing \"install.swm\" (part 1 of 2): 0 MiB of 4000 MiB (0%) written
+Archiving file data: 1000 MiB of 3400 MiB (29%) done\r\
+Archiving file data: 2000 MiB of 3400 MiB (58%) done
+"))
+            })
+            .stream_lines(std::time::Duration::from_millis(400)),
+    );
+
+    let (result, events) = flash(drive.flasher(), &runner);
+    assert_eq!(result, Ok(()));
+
+    let splitting: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            FlashEvent::Progress(p) if p.phase == Phase::SplittingImage => Some(p),
+            _ => None,
+        })
+        .collect();
+    let latest = splitting.last().expect("no split progress");
+    assert_eq!(latest.bytes_total, Some(4000 << 20));
+    assert!(latest.bytes_done >= Some(1000 << 20));
+    assert!(splitting.windows(2).all(|w| w[0].fraction <= w[1].fraction));
+}
+
+#[test]
+fn unmount_failure_after_sync_is_reported() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().fail("umount", None, 32, "target is busy"));
+
+    let (result, _) = flash(drive.flasher(), &runner);
+
+    assert!(matches!(result, Err(FlashError::Unmount(_))));
+}
+
+#[test]
+fn unplugged_drive_is_detected_after_formatting() {
+    let drive = FakeDrive::new();
+    let node = drive.path.clone();
+    let runner = Arc::new(FakeRunner::new().on("wipefs", None, move |_| {
+        fs::remove_file(&node)?;
+        Ok(ok(""))
+    }));
+
+    let (result, _) = flash(drive.flasher(), &runner);
+
+    assert_eq!(
+        result,
+        Err(FlashError::DriveDisconnected(Phase::Formatting))
+    );
+    assert!(!runner.ran("mount"));
+}
+
+#[test]
+fn unplugged_drive_during_extraction_wins_over_tool_error() {
+    let drive = FakeDrive::new();
+    let node = drive.path.clone();
+    let runner = Arc::new(FakeRunner::new().on("7z", Some("x"), move |_| {
+        fs::remove_file(&node)?;
+        Ok(support::failed(2, "ERROR: Can not open output file"))
+    }));
+
+    let (result, _) = flash(drive.flasher(), &runner);
+
+    assert_eq!(
+        result,
+        Err(FlashError::DriveDisconnected(Phase::CopyingBootFiles))
+    );
+}
+
+#[test]
+fn sd_card_partition_is_named_with_a_p_suffix() {
+    let drive = FakeDrive::named("mmcblk0", "p");
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(
+        drive.flasher().inspector(DiskInspector::with_roots(
+            drive.dir().join("proc"),
+            drive.dir().join("sys"),
+        )),
+        &runner,
+    );
+
+    assert_eq!(result, Ok(()));
+    let commands = runner.commands();
+    let settle = step(&commands, "udevadm settle");
+    assert!(step(&commands, "partprobe") < settle);
+    assert!(commands.contains(&format!("mkfs.fat -F32 -I {}p1", drive.name())));
+}
+
+#[test]
+fn mbr_scheme_marks_the_partition_active_and_installs_boot_code() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(drive.flasher().scheme(PartitionScheme::Mbr), &runner);
+
+    assert_eq!(result, Ok(()));
+    let commands = runner.commands();
+    let dev = drive.name();
+    let expected = [
+        format!("sgdisk -Z {}", dev),
+        format!(
+            "parted -s {} mklabel msdos mkpart primary fat32 1MiB 100% set 1 boot on",
+            dev
+        ),
+        format!("partprobe {}", dev),
+        format!("mkfs.fat -F32 -I {}1", dev),
+        format!("ms-sys -8 {}1", dev),
+        format!("ms-sys -7 {}", dev),
+        format!("mount {}1 /tmp/windusb_usb_", dev),
+    ];
+    let positions: Vec<usize> = expected.iter().map(|p| step(&commands, p)).collect();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]), "{:#?}", commands);
+    assert!(!commands.iter().any(|c| c.starts_with("sgdisk -n")));
+}
+
+#[test]
+fn hybrid_scheme_activates_the_mirrored_partition() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(drive.flasher().scheme(PartitionScheme::Hybrid), &runner);
+
+    assert_eq!(result, Ok(()));
+    let commands = runner.commands();
+    let dev = drive.name();
+    let expected = [
+        format!("sgdisk -n=1:0:0 -t=1:0700 {}", dev),
+        format!("sgdisk -h 1 {}", dev),
+        format!("sfdisk --label-nested dos --activate {} 1", dev),
+        format!("mkfs.fat -F32 -I {}1", dev),
+        format!("ms-sys -8 {}1", dev),
+        format!("ms-sys -7 {}", dev),
+    ];
+    let positions: Vec<usize> = expected.iter().map(|p| step(&commands, p)).collect();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]), "{:#?}", commands);
+    assert!(!runner.ran("parted"));
+}
+
+#[test]
+fn hybrid_scheme_failing_to_set_the_boot_flag_is_reported() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().fail("sfdisk", None, 1, "no nested MBR"));
+
+    let (result, _) = flash(drive.flasher().scheme(PartitionScheme::Hybrid), &runner);
+
+    let err = result.unwrap_err();
+    assert_eq!(err.code(), "set_boot_flag_failed");
+    assert_eq!(err.tool_error().unwrap().stderr, "no nested MBR");
+    assert!(!runner.ran("mkfs.fat"));
+}
+
+#[test]
+fn schemes_are_named_for_the_command_line() {
+    for scheme in PartitionScheme::ALL {
+        assert_eq!(PartitionScheme::from_name(scheme.name()), Some(scheme));
+    }
+    assert_eq!(
+        PartitionScheme::from_name("MBR"),
+        Some(PartitionScheme::Mbr)
+    );
+    assert_eq!(PartitionScheme::from_name("apm"), None);
+    assert_eq!(PartitionScheme::default(), PartitionScheme::Gpt);
+}
+
+#[test]
+fn ntfs_layout_copies_the_image_whole_next_to_uefi_ntfs() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(drive.ntfs(), &runner);
+
+    assert_eq!(result, Ok(()));
+    let commands = runner.commands();
+    let dev = drive.name();
+    let expected = [
+        format!("sgdisk -n=1:0:-2M -t=1:0700 -n=2:0:0 -t=2:ef00 {}", dev),
+        format!("partprobe {}", dev),
+        format!("mkfs.ntfs -Q {}1", dev),
+        format!(
+            "dd if={} of={}2 bs=1M conv=fsync",
+            drive.uefi_ntfs().display(),
+            dev
+        ),
+        format!("mount -t ntfs3 {}1 /tmp/windusb_usb_", dev),
+        "7z x /isos/win11.iso -o/tmp/windusb_usb_".to_string(),
+        "cp /tmp/windusb_iso_".to_string(),
+        "sync".to_string(),
+    ];
+    let positions: Vec<usize> = expected.iter().map(|p| step(&commands, p)).collect();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]), "{:#?}", commands);
+    let copy = &commands[positions[6]];
+    assert!(copy.contains("/sources/install.wim /tmp/windusb_usb_"));
+    assert!(copy.ends_with("/sources/install.wim"));
+    assert!(!runner.ran("wimlib-imagex") && !runner.ran("mkfs.fat") && !runner.ran("ms-sys"));
+}
+
+#[test]
+fn ntfs_falls_back_to_ntfs_3g_without_the_ntfs3_driver() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().fail(
+        "mount",
+        Some("ntfs3"),
+        32,
+        "unknown filesystem type 'ntfs3'",
+    ));
+
+    let (result, _) = flash(drive.ntfs().verify(true), &runner);
+
+    let commands = runner.commands();
+    let dev = drive.name();
+    step(&commands, &format!("ntfs-3g {}1 /tmp/windusb_usb_", dev));
+    step(
+        &commands,
+        &format!("ntfs-3g -o ro {}1 /tmp/windusb_usb_", dev),
+    );
+    assert!(!commands.contains(&format!("mount {}1", dev)));
+    // The ISO only exists as a 7z listing, so there is nothing to compare.
+    assert_eq!(result.unwrap_err().code(), "verify_failed");
+}
+
+#[test]
+fn ntfs_layout_on_mbr_uses_the_ntfs_boot_record() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(drive.ntfs().scheme(PartitionScheme::Mbr), &runner);
+
+    assert_eq!(result, Ok(()));
+    let commands = runner.commands();
+    let dev = drive.name();
+    assert!(commands.contains(&format!(
+        "parted -s -- {} mklabel msdos mkpart primary ntfs 1MiB -2MiB \
+         mkpart primary fat16 -2MiB 100% set 1 boot on set 2 esp on",
+        dev
+    )));
+    assert!(step(&commands, "dd if=") < step(&commands, &format!("ms-sys -n {}1", dev)));
+    assert!(commands.contains(&format!("ms-sys -7 {}", dev)));
+}
+
+#[test]
+fn uefi_ntfs_failure_is_reported() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new().fail("dd", None, 1, "No such file or directory"));
+
+    let (result, _) = flash(drive.ntfs(), &runner);
+
+    let err = result.unwrap_err();
+    assert_eq!(err.code(), "uefi_ntfs_failed");
+    assert!(err.to_string().contains("No such file or directory"));
+    assert!(!runner.ran("mount"));
+}
+
+#[test]
+fn missing_uefi_ntfs_image_is_reported_before_wiping() {
+    let drive = FakeDrive::new();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(
+        drive
+            .ntfs()
+            .uefi_ntfs_image(drive.path.with_file_name("missing.img")),
+        &runner,
+    );
+
+    assert_eq!(result.unwrap_err().code(), "uefi_ntfs_missing");
+    assert!(!runner.ran("wipefs") && !runner.ran("sgdisk"));
+}
+
+#[test]
+fn missing_tools_are_reported_before_wiping() {
+    let drive = FakeDrive::new();
+    for (tool, scheme) in [
+        ("mkfs.ntfs", PartitionScheme::Gpt),
+        ("dd", PartitionScheme::Gpt),
+        ("parted", PartitionScheme::Mbr),
+        ("ms-sys", PartitionScheme::Mbr),
+        ("sfdisk", PartitionScheme::Hybrid),
+    ] {
+        let runner = Arc::new(FakeRunner::new().missing(tool));
+
+        let (result, _) = flash(drive.ntfs().scheme(scheme), &runner);
+
+        assert_eq!(
+            result,
+            Err(FlashError::MissingTool(tool.to_string())),
+            "{}",
+            tool
+        );
+        assert!(!runner.ran("wipefs") && !runner.ran("sgdisk"));
+    }
+}
+
+#[test]
+fn layouts_are_named_for_the_command_line() {
+    for layout in DriveLayout::ALL {
+        assert_eq!(DriveLayout::from_name(layout.name()), Some(layout));
+    }
+    assert_eq!(DriveLayout::from_name("NTFS"), Some(DriveLayout::Ntfs));
+    assert_eq!(DriveLayout::from_name("exfat"), None);
+    assert_eq!(DriveLayout::default(), DriveLayout::Fat32);
+}
@@ -0,0 +1,120 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use windusb::hotplug::{wait_for_node, watch_drives_with, UEvent};
+
+fn raw(fields: &[&str]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for field in fields {
+        buf.extend_from_slice(field.as_bytes());
+        buf.push(0);
+    }
+    buf
+}
+
+#[test]
+fn parses_disk_add() {
+    let buf = raw(&[
+        "add@/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb",
+        "ACTION=add",
+        "DEVPATH=/devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb",
+        "SUBSYSTEM=block",
+        "MAJOR=8",
+        "MINOR=16",
+        "DEVNAME=sdb",
+        "DEVTYPE=disk",
+        "SEQNUM=4242",
+    ]);
+
+    let event = UEvent::parse(&buf).unwrap();
+
+    assert_eq!(event.action, "add");
+    assert_eq!(event.subsystem, "block");
+    assert_eq!(event.device_path().as_deref(), Some("/dev/sdb"));
+    assert!(event.is_disk_change());
+}
+
+#[test]
+fn partitions_and_other_subsystems_are_not_disk_changes() {
+    let partition = UEvent::parse(&raw(&[
+        "remove@/devices/virtual/block/loop0/loop0p1",
+        "ACTION=remove",
+        "SUBSYSTEM=block",
+        "DEVNAME=loop0p1",
+        "DEVTYPE=partition",
+    ]))
+    .unwrap();
+    assert!(!partition.is_disk_change());
+
+    let usb = UEvent::parse(&raw(&[
+        "add@/devices/pci0000:00/0000:00:14.0/usb2/2-1",
+        "ACTION=add",
+        "SUBSYSTEM=usb",
+        "DEVNAME=bus/usb/002/003",
+        "DEVTYPE=usb_device",
+    ]))
+    .unwrap();
+    assert!(!usb.is_disk_change());
+    assert_eq!(usb.devname.as_deref(), Some("003"));
+}
+
+#[test]
+fn ignores_udev_rebroadcasts_and_garbage() {
+    let mut udev = b"libudev\0".to_vec();
+    udev.extend_from_slice(&[0xfe, 0xed, 0xca, 0xfe, 0, 0, 0, 0]);
+    assert_eq!(UEvent::parse(&udev), None);
+    assert_eq!(UEvent::parse(b""), None);
+    assert_eq!(UEvent::parse(&raw(&["add@/devices/x"])), None);
+}
+
+#[test]
+fn waits_for_a_device_node_to_appear() {
+    let dir = tempfile::tempdir().unwrap();
+    let node = dir.path().join("mmcblk0p1");
+    let created = node.clone();
+    let creator = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(100));
+        fs::write(created, b"").unwrap();
+    });
+    let started = Instant::now();
+
+    assert!(wait_for_node(&node, Duration::from_secs(10)));
+    assert!(started.elapsed() < Duration::from_secs(5));
+    creator.join().unwrap();
+}
+
+#[test]
+fn gives_up_on_a_node_that_never_appears() {
+    let dir = tempfile::tempdir().unwrap();
+    let started = Instant::now();
+
+    assert!(!wait_for_node(
+        &dir.path().join("sdz1"),
+        Duration::from_millis(300)
+    ));
+    assert!(started.elapsed() >= Duration::from_millis(300));
+}
+
+#[test]
+fn overflowing_the_receive_buffer_counts_as_a_change() {
+    let mut script: VecDeque<io::Result<Option<UEvent>>> = VecDeque::from([
+        Err(io::Error::from_raw_os_error(libc::ENOBUFS)),
+        Ok(None),
+        Ok(UEvent::parse(&raw(&[
+            "ACTION=add",
+            "SUBSYSTEM=block",
+            "DEVTYPE=partition",
+        ]))),
+        Err(io::Error::from_raw_os_error(libc::EBADF)),
+    ]);
+    let (tx, rx) = mpsc::channel();
+
+    watch_drives_with(|_| script.pop_front().unwrap(), &tx);
+
+    assert_eq!(rx.try_iter().count(), 1);
+    assert!(script.is_empty());
+}
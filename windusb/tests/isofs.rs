@@ -0,0 +1,369 @@
+mod support;
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use support::image::{iso9660, udf, UdfOptions};
+use windusb::isofs::{IsoFormat, IsoImage};
+
+/// Bytes that differ from sector to sector, so misplaced reads show up.
+fn pattern(len: usize, seed: u8) -> Vec<u8> {
+    (0..len)
+        .map(|i| (i / 7) as u8 ^ (i % 251) as u8 ^ seed)
+        .collect()
+}
+
+fn open(dir: &Path, image: &[u8]) -> IsoImage {
+    let path = dir.join("test.iso");
+    fs::write(&path, image).unwrap();
+    IsoImage::open(&path).unwrap()
+}
+
+fn read(image: &IsoImage, path: &str) -> Vec<u8> {
+    let file = image.lookup(path).unwrap();
+    let mut data = Vec::new();
+    image.reader(&file).read_to_end(&mut data).unwrap();
+    data
+}
+
+/// Overwrites the first occurrence of `from` in `image` with `to`.
+fn patch(image: &mut [u8], from: &[u8], to: &[u8]) {
+    let at = image
+        .windows(from.len())
+        .position(|w| w == from)
+        .unwrap_or_else(|| panic!("{:?} not in image", from));
+    image[at..at + to.len()].copy_from_slice(to);
+}
+
+fn paths(image: &IsoImage) -> Vec<String> {
+    image
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|e| {
+            if e.is_dir {
+                format!("{}/", e.path)
+            } else {
+                e.path
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn reads_joliet_names_and_contents() {
+    let dir = tempfile::tempdir().unwrap();
+    let wim = pattern(10_000, 1);
+    let image = open(
+        dir.path(),
+        &iso9660(
+            &[
+                ("setup.exe", b"MZ"),
+                ("sources/install.wim", &wim),
+                ("efi/boot/bootx64.efi", b"efi"),
+            ],
+            true,
+            None,
+        ),
+    );
+
+    assert_eq!(image.format(), IsoFormat::Joliet);
+    assert_eq!(
+        paths(&image),
+        [
+            "efi/",
+            "setup.exe",
+            "sources/",
+            "sources/install.wim",
+            "efi/boot/",
+            "efi/boot/bootx64.efi",
+        ]
+    );
+    assert_eq!(read(&image, "sources/install.wim"), wim);
+    assert_eq!(read(&image, "setup.exe"), b"MZ");
+}
+
+#[test]
+fn plain_iso9660_drops_version_suffix() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = open(
+        dir.path(),
+        &iso9660(&[("readme.txt", b"hello")], false, None),
+    );
+
+    assert_eq!(image.format(), IsoFormat::Iso9660);
+    assert_eq!(paths(&image), ["README.TXT"]);
+    assert_eq!(read(&image, "readme.txt"), b"hello");
+}
+
+#[test]
+fn joins_multi_extent_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = pattern(5 * 2048 + 100, 2);
+    let image = open(
+        dir.path(),
+        &iso9660(&[("big.bin", &data), ("z.txt", b"z")], true, Some(2 * 2048)),
+    );
+
+    let file = image.lookup("big.bin").unwrap();
+    assert_eq!(file.size, data.len() as u64);
+    assert_eq!(read(&image, "big.bin"), data);
+    assert_eq!(paths(&image), ["big.bin", "z.txt"]);
+}
+
+#[test]
+fn prefers_udf_over_the_bridge_readme() {
+    let dir = tempfile::tempdir().unwrap();
+    let wim = pattern(30_000, 3);
+    let image = open(
+        dir.path(),
+        &udf(
+            &[("bootmgr", b"boot"), ("sources/install.wim", &wim)],
+            UdfOptions {
+                bridge: true,
+                ..Default::default()
+            },
+        ),
+    );
+
+    assert_eq!(image.format(), IsoFormat::Udf);
+    assert_eq!(
+        paths(&image),
+        ["bootmgr", "sources/", "sources/install.wim"]
+    );
+    assert_eq!(read(&image, "SOURCES/Install.WIM"), wim);
+}
+
+#[test]
+fn reads_fragmented_udf_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = pattern(9 * 2048 + 5, 4);
+    let image = open(
+        dir.path(),
+        &udf(
+            &[("sources/boot.wim", &data)],
+            UdfOptions {
+                max_extent: Some(4 * 2048),
+                ..Default::default()
+            },
+        ),
+    );
+
+    assert_eq!(read(&image, "sources/boot.wim"), data);
+}
+
+#[test]
+fn seeks_across_extents() {
+    let dir = tempfile::tempdir().unwrap();
+    let data = pattern(9 * 2048 + 5, 8);
+    let image = open(
+        dir.path(),
+        &udf(
+            &[("install.wim", &data)],
+            UdfOptions {
+                max_extent: Some(2 * 2048),
+                ..Default::default()
+            },
+        ),
+    );
+    let file = image.lookup("install.wim").unwrap();
+    let mut reader = image.reader(&file);
+    let mut buf = [0; 100];
+
+    reader.seek(SeekFrom::Start(4 * 2048 - 50)).unwrap();
+    reader.read_exact(&mut buf).unwrap();
+    assert_eq!(buf[..], data[4 * 2048 - 50..4 * 2048 + 50]);
+
+    assert_eq!(reader.seek(SeekFrom::End(-5)).unwrap(), 9 * 2048);
+    let mut tail = Vec::new();
+    reader.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, data[9 * 2048..]);
+}
+
+#[test]
+fn reads_udf_metadata_partition_and_embedded_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let wim = pattern(20_000, 5);
+    let names: Vec<String> = (0..60)
+        .map(|i| format!("Ünïcode file {:02}.txt", i))
+        .collect();
+    let mut files: Vec<(&str, &[u8])> = vec![("sources/install.wim", &wim), ("tiny.ini", b"[x]")];
+    let paths_owned: Vec<String> = names.iter().map(|n| format!("docs/{}", n)).collect();
+    files.extend(paths_owned.iter().map(|p| (p.as_str(), &b"doc"[..])));
+    let image = open(
+        dir.path(),
+        &udf(
+            &files,
+            UdfOptions {
+                metadata_partition: true,
+                inline_up_to: 16,
+                max_extent: Some(3 * 2048),
+                ..Default::default()
+            },
+        ),
+    );
+
+    assert_eq!(image.format(), IsoFormat::Udf);
+    assert_eq!(read(&image, "sources/install.wim"), wim);
+    assert_eq!(read(&image, "tiny.ini"), b"[x]");
+    let docs = image.read_dir(&image.lookup("docs").unwrap()).unwrap();
+    assert_eq!(docs.len(), 60);
+    assert_eq!(read(&image, "docs/Ünïcode file 42.txt"), b"doc");
+}
+
+#[test]
+fn extracts_everything_but_excluded_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let boot = pattern(4096 * 3 + 1, 6);
+    let image = open(
+        dir.path(),
+        &udf(
+            &[
+                ("efi/boot/bootx64.efi", b"efi"),
+                ("sources/boot.wim", &boot),
+                ("sources/install.wim", b"skipped"),
+            ],
+            UdfOptions::default(),
+        ),
+    );
+    let dest = dir.path().join("usb");
+    let mut last = 0;
+
+    let written = image
+        .extract(
+            &dest,
+            |path| !path.eq_ignore_ascii_case("sources/install.wim"),
+            |_, bytes| last = bytes,
+        )
+        .unwrap();
+
+    assert_eq!(written, boot.len() as u64 + 3);
+    assert_eq!(last, written);
+    assert_eq!(fs::read(dest.join("sources/boot.wim")).unwrap(), boot);
+    assert_eq!(fs::read(dest.join("efi/boot/bootx64.efi")).unwrap(), b"efi");
+    assert!(!dest.join("sources/install.wim").exists());
+}
+
+#[test]
+fn rejects_files_that_are_not_images() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("random.iso");
+    fs::write(&path, pattern(64 * 2048, 7)).unwrap();
+
+    assert!(IsoImage::open(&path).is_err());
+    assert!(IsoImage::open(dir.path().join("missing.iso")).is_err());
+}
+
+#[test]
+fn missing_paths_are_not_found() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = open(dir.path(), &iso9660(&[("a.txt", b"a")], true, None));
+
+    let err = image.lookup("sources/install.wim").unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+}
+
+#[test]
+fn udf_names_that_leave_the_tree_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut image = udf(
+        &[
+            ("zz", b"parent"),
+            ("a_b", b"nested"),
+            ("sources/install.wim", b"wim"),
+        ],
+        UdfOptions::default(),
+    );
+    // Plain entries named ".." and "a/b", without the parent flag.
+    patch(&mut image, b"\x08zz", b"\x08..");
+    patch(&mut image, b"\x08a_b", b"\x08a/b");
+    let image = open(dir.path(), &image);
+    let dest = dir.path().join("usb");
+
+    assert_eq!(paths(&image), ["sources/", "sources/install.wim"]);
+    image.extract(&dest, |_| true, |_, _| {}).unwrap();
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    assert!(!dest.join("a").exists());
+}
+
+#[test]
+fn joliet_names_with_slashes_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut image = iso9660(&[("a_b", b"nested"), ("ok.txt", b"ok")], true, None);
+    patch(&mut image, b"\0a\0_\0b", b"\0a\0/\0b");
+    let image = open(dir.path(), &image);
+
+    assert_eq!(paths(&image), ["ok.txt"]);
+}
+
+#[test]
+fn directory_that_contains_its_ancestor_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut image = udf(&[("dd/file.txt", b"x")], UdfOptions::default());
+    // Point the "dd" entry back at the root directory's file entry, named in
+    // the file set descriptor at the start of the partition.
+    let fsd = 257 * 2048;
+    let root = image[fsd + 404..fsd + 408].to_vec();
+    let name = image.windows(3).position(|w| w == b"\x08dd").unwrap();
+    image[name - 38 + 24..name - 38 + 28].copy_from_slice(&root);
+    let image = open(dir.path(), &image);
+
+    let err = image.entries().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn allocation_extents_that_loop_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let spare = vec![0xA5; 2048];
+    let looped = vec![0x5A; 2048];
+    let mut image = udf(
+        &[("spare.bin", &spare), ("looped.bin", &looped)],
+        UdfOptions::default(),
+    );
+    let block_of = |image: &[u8], data: &[u8]| {
+        let at = image.windows(data.len()).position(|w| w == data).unwrap();
+        (at / 2048 - 257) as u32
+    };
+    // Turn the spare block into an allocation extent descriptor whose only
+    // entry continues the list in itself, and send looped.bin's list there.
+    let spare_block = block_of(&image, &spare);
+    let looped_block = block_of(&image, &looped);
+    let continued = ((3u32 << 30) | 2048).to_le_bytes();
+    let at = (257 + spare_block as usize) * 2048;
+    let mut aed = vec![0; 2048];
+    aed[..2].copy_from_slice(&258u16.to_le_bytes());
+    aed[20..24].copy_from_slice(&8u32.to_le_bytes());
+    aed[24..28].copy_from_slice(&continued);
+    aed[28..32].copy_from_slice(&spare_block.to_le_bytes());
+    image[at..at + 2048].copy_from_slice(&aed);
+    let mut ad = 2048u32.to_le_bytes().to_vec();
+    ad.extend(looped_block.to_le_bytes());
+    let mut redirected = continued.to_vec();
+    redirected.extend(spare_block.to_le_bytes());
+    patch(&mut image, &ad, &redirected);
+    let path = dir.path().join("test.iso");
+    fs::write(&path, &image).unwrap();
+
+    let err = IsoImage::open(&path)
+        .and_then(|image| image.entries())
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn implausible_directory_sizes_are_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut image = udf(&[("dd/file.txt", b"x")], UdfOptions::default());
+    // Claim an information length of 2^62 for every directory entry.
+    for at in (257 * 2048..image.len()).step_by(2048) {
+        if image[at..at + 2] == 261u16.to_le_bytes() && image[at + 27] == 4 {
+            image[at + 56..at + 64].copy_from_slice(&(1u64 << 62).to_le_bytes());
+        }
+    }
+    let image = open(dir.path(), &image);
+
+    let err = image.read_dir(&image.root()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,299 @@
+//! End-to-end run of the real pipeline against a loop device.
+//!
+//! Opt-in: needs root, loop device support and `wimlib-imagex`,
+//! `xorriso`, `sgdisk`, `wipefs`, `blockdev`, `partprobe` and `mkfs.fat` on
+//! `PATH`. Run with
+//!
+//! ```text
+//! sudo -E cargo test -p windusb --test loop_device -- --ignored
+//! ```
+
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc;
+
+use tempfile::TempDir;
+use windusb::{FlashEvent, Flasher};
+
+const DISK_SIZE: u64 = 256 * 1024 * 1024;
+const SECTOR: u64 = 512;
+
+/// Microsoft basic data partition type GUID in its on-disk byte order.
+const BASIC_DATA_GUID: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+
+fn run(program: &str, args: &[&str]) -> String {
+    let out = Command::new(program)
+        .args(args)
+        .output()
+        .unwrap_or_else(|e| panic!("cannot run {}: {}", program, e));
+    assert!(
+        out.status.success(),
+        "{} {:?} failed: {}",
+        program,
+        args,
+        String::from_utf8_lossy(&out.stderr)
+    );
+    String::from_utf8_lossy(&out.stdout).into_owned()
+}
+
+fn check_prerequisites() {
+    assert_eq!(
+        unsafe { libc::geteuid() },
+        0,
+        "loop device tests must run as root"
+    );
+    for tool in [
+        "losetup",
+        "wimlib-imagex",
+        "xorriso",
+        "sgdisk",
+        "wipefs",
+        "blockdev",
+        "partprobe",
+        "mkfs.fat",
+    ] {
+        assert!(
+            Command::new("sh")
+                .args(["-c", &format!("command -v {}", tool)])
+                .output()
+                .is_ok_and(|o| o.status.success()),
+            "{} is required for loop device tests",
+            tool
+        );
+    }
+}
+
+/// Sparse disk image attached to a loop device, detached on drop.
+struct LoopDisk {
+    image: PathBuf,
+    device: String,
+}
+
+impl LoopDisk {
+    fn attach(dir: &Path) -> Self {
+        let image = dir.join("disk.img");
+        File::create(&image).unwrap().set_len(DISK_SIZE).unwrap();
+        let device = run("losetup", &["-fP", "--show", &image.to_string_lossy()])
+            .trim()
+            .to_string();
+        LoopDisk { image, device }
+    }
+
+    fn partition(&self) -> String {
+        format!("{}p1", self.device)
+    }
+}
+
+impl Drop for LoopDisk {
+    fn drop(&mut self) {
+        let _ = Command::new("losetup").args(["-d", &self.device]).status();
+    }
+}
+
+/// Read-only mount of the flashed partition, unmounted on drop.
+struct Mounted {
+    dir: TempDir,
+}
+
+impl Mounted {
+    fn new(device: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        run(
+            "mount",
+            &["-o", "ro", device, &dir.path().to_string_lossy()],
+        );
+        Mounted { dir }
+    }
+}
+
+impl Drop for Mounted {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(self.dir.path()).status();
+    }
+}
+
+/// Lays out a tiny tree resembling a Windows ISO, with a real WIM captured by
+/// wimlib, and masters it into an ISO 9660 + Joliet image.
+fn build_windows_like_iso(dir: &Path) -> (PathBuf, PathBuf) {
+    let root = dir.join("iso_root");
+    let payload = dir.join("payload");
+    fs::create_dir_all(root.join("efi/boot")).unwrap();
+    fs::create_dir_all(root.join("boot")).unwrap();
+    fs::create_dir_all(root.join("sources")).unwrap();
+    fs::create_dir_all(payload.join("Windows/System32")).unwrap();
+
+    fs::write(root.join("bootmgr"), vec![0x42; 4096]).unwrap();
+    fs::write(root.join("efi/boot/bootx64.efi"), b"MZ fake uefi loader").unwrap();
+    fs::write(root.join("boot/bcd"), vec![0x07; 8192]).unwrap();
+    fs::write(root.join("setup.exe"), b"MZ fake setup").unwrap();
+    fs::write(root.join("sources/boot.wim"), vec![0x11; 65536]).unwrap();
+    fs::write(
+        payload.join("Windows/System32/ntoskrnl.exe"),
+        b"MZ fake kernel",
+    )
+    .unwrap();
+
+    run(
+        "wimlib-imagex",
+        &[
+            "capture",
+            &payload.to_string_lossy(),
+            &root.join("sources/install.wim").to_string_lossy(),
+            "Windows Test",
+        ],
+    );
+
+    let iso = dir.join("windows.iso");
+    run(
+        "xorriso",
+        &[
+            "-as",
+            "mkisofs",
+            "-quiet",
+            "-J",
+            "-R",
+            "-V",
+            "WINTEST",
+            "-o",
+            &iso.to_string_lossy(),
+            &root.to_string_lossy(),
+        ],
+    );
+    (iso, root)
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> Vec<u8> {
+    let mut buf = vec![0; len];
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.read_exact(&mut buf).unwrap();
+    buf
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes(bytes[..4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+/// Checks the protective MBR and a GPT with exactly one basic data partition,
+/// returning that partition's first LBA.
+fn assert_gpt_layout(image: &Path) -> u64 {
+    let mut disk = File::open(image).unwrap();
+
+    let mbr = read_at(&mut disk, 0, 512);
+    assert_eq!(&mbr[510..], &[0x55, 0xAA], "missing MBR signature");
+    assert_eq!(mbr[446 + 4], 0xEE, "MBR is not a GPT protective MBR");
+
+    let header = read_at(&mut disk, SECTOR, 92);
+    assert_eq!(&header[..8], b"EFI PART", "missing GPT header");
+    let entries_lba = le_u64(&header[72..]);
+    let entry_count = le_u32(&header[80..]) as usize;
+    let entry_size = le_u32(&header[84..]) as usize;
+
+    let table = read_at(&mut disk, entries_lba * SECTOR, entry_count * entry_size);
+    let used: Vec<&[u8]> = table
+        .chunks(entry_size)
+        .filter(|e| e[..16].iter().any(|&b| b != 0))
+        .collect();
+    assert_eq!(used.len(), 1, "expected a single partition");
+    assert_eq!(
+        used[0][..16],
+        BASIC_DATA_GUID,
+        "partition is not basic data (0700)"
+    );
+
+    let first_lba = le_u64(&used[0][32..]);
+    let last_lba = le_u64(&used[0][40..]);
+    assert!(
+        (last_lba + 1 - first_lba) * SECTOR > DISK_SIZE * 9 / 10,
+        "partition does not span the disk"
+    );
+    first_lba
+}
+
+fn assert_fat32(image: &Path, first_lba: u64) {
+    let mut disk = File::open(image).unwrap();
+    let boot = read_at(&mut disk, first_lba * SECTOR, 512);
+    assert_eq!(&boot[510..], &[0x55, 0xAA], "missing FAT boot signature");
+    assert_eq!(&boot[82..90], b"FAT32   ", "partition is not FAT32");
+}
+
+fn relative_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path.strip_prefix(root).unwrap().to_path_buf());
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Lowercased path, since FAT32 and Joliet may disagree on case.
+fn folded(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+#[test]
+#[ignore = "needs root, loop devices and the flashing tools; run with --ignored"]
+fn flashes_synthetic_iso_onto_loop_device() {
+    check_prerequisites();
+    let work = tempfile::tempdir().unwrap();
+    let (iso, iso_root) = build_windows_like_iso(work.path());
+    let disk = LoopDisk::attach(work.path());
+
+    let (tx, rx) = mpsc::channel();
+    let result = Flasher::new(&disk.device, &iso).run(tx);
+    let events: Vec<FlashEvent> = rx.iter().collect();
+    assert_eq!(result, Ok(()), "events: {:#?}", events);
+    assert_eq!(events.last(), Some(&FlashEvent::Finished));
+
+    let first_lba = assert_gpt_layout(&disk.image);
+    assert_fat32(&disk.image, first_lba);
+
+    let mounted = Mounted::new(&disk.partition());
+    let usb = mounted.dir.path();
+    let copied: Vec<String> = relative_files(usb).iter().map(|p| folded(p)).collect();
+
+    for file in relative_files(&iso_root) {
+        if folded(&file) == "sources/install.wim" {
+            continue;
+        }
+        assert!(
+            copied.contains(&folded(&file)),
+            "{} missing on the stick; got {:?}",
+            file.display(),
+            copied
+        );
+        let on_stick = relative_files(usb)
+            .into_iter()
+            .find(|p| folded(p) == folded(&file))
+            .unwrap();
+        assert_eq!(
+            fs::read(usb.join(&on_stick)).unwrap(),
+            fs::read(iso_root.join(&file)).unwrap(),
+            "{} differs from the ISO",
+            file.display()
+        );
+    }
+
+    assert!(!copied.contains(&"sources/install.wim".to_string()));
+    assert!(copied.contains(&"sources/install.swm".to_string()));
+    let info = run(
+        "wimlib-imagex",
+        &["info", &usb.join("sources/install.swm").to_string_lossy()],
+    );
+    assert!(info.contains("Windows Test"), "{}", info);
+}
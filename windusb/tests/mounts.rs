@@ -0,0 +1,157 @@
+mod support;
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Arc;
+
+use support::FakeRunner;
+use windusb::mounts::{sweep_exports_in, sweep_stale_in, MountPoint};
+use windusb::Invocation;
+
+/// No process has this pid: it is above the kernel's `pid_max` limit.
+const DEAD_PID: u32 = 999_999_999;
+
+fn mount_point(root: &Path, runner: &Arc<FakeRunner>) -> MountPoint {
+    MountPoint::create_in(root, "usb", runner.clone()).unwrap()
+}
+
+#[test]
+fn creates_a_private_directory_and_removes_it_on_drop() {
+    let root = tempfile::tempdir().unwrap();
+    let runner = Arc::new(FakeRunner::new());
+
+    let mount = mount_point(root.path(), &runner);
+    let path = mount.path().to_path_buf();
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+    assert!(name.starts_with(&format!("windusb_usb_{}_", std::process::id())));
+    assert_eq!(
+        fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+        0o700
+    );
+    assert_ne!(path, mount_point(root.path(), &runner).path());
+    drop(mount);
+    assert!(!path.exists());
+    assert!(runner.calls().is_empty());
+}
+
+#[test]
+fn drop_unmounts_what_is_still_mounted() {
+    let root = tempfile::tempdir().unwrap();
+    let runner = Arc::new(FakeRunner::new().fail("umount", None, 32, "target is busy"));
+
+    let mount = mount_point(root.path(), &runner);
+    let dir = mount.as_arg();
+    mount
+        .mount(runner.as_ref(), Invocation::new("mount").arg("/dev/sdz1"))
+        .unwrap();
+    drop(mount);
+
+    assert_eq!(
+        runner.commands(),
+        [
+            format!("mount /dev/sdz1 {}", dir),
+            format!("umount {}", dir),
+            format!("umount -l {}", dir),
+        ]
+    );
+    assert!(!Path::new(&dir).exists());
+}
+
+#[test]
+fn unmounted_directories_are_only_removed() {
+    let root = tempfile::tempdir().unwrap();
+    let runner = Arc::new(FakeRunner::new());
+
+    let mount = mount_point(root.path(), &runner);
+    mount
+        .mount(runner.as_ref(), Invocation::new("mount").arg("/dev/sdz1"))
+        .unwrap();
+    mount.unmount(runner.as_ref(), true).unwrap();
+    drop(mount);
+
+    assert_eq!(runner.commands().len(), 2);
+}
+
+#[test]
+fn panicking_owner_still_unmounts() {
+    let root = tempfile::tempdir().unwrap();
+    let runner = Arc::new(FakeRunner::new());
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mount = mount_point(root.path(), &runner);
+        mount
+            .mount(runner.as_ref(), Invocation::new("mount").arg("/dev/sdz1"))
+            .unwrap();
+        panic!("extraction blew up");
+    }));
+
+    assert!(result.is_err());
+    assert!(runner.commands()[1].starts_with("umount "));
+    assert_eq!(fs::read_dir(root.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn sweep_removes_mount_points_of_dead_runs_only() {
+    let root = tempfile::tempdir().unwrap();
+    let dir = |name: &str| {
+        let path = root.path().join(name);
+        fs::create_dir(&path).unwrap();
+        path
+    };
+    let crashed = dir(&format!("windusb_iso_{}_a1B2c3", DEAD_PID));
+    let old_style = dir("windusb_usb_1804289383");
+    let ours = dir(&format!("windusb_usb_{}_x9Y8z7", std::process::id()));
+    let unrelated = dir("windusb_export");
+    let mounts = root.path().join("mounts");
+    fs::write(
+        &mounts,
+        format!(
+            "/dev/loop7 {} udf ro,relatime 0 0\n/dev/sdz1 {} vfat rw 0 0\n",
+            crashed.display(),
+            ours.display()
+        ),
+    )
+    .unwrap();
+    let runner = FakeRunner::new();
+
+    let mut removed = sweep_stale_in(&runner, root.path(), &mounts);
+
+    removed.sort();
+    let mut expected = vec![crashed.clone(), old_style];
+    expected.sort();
+    assert_eq!(removed, expected);
+    assert_eq!(
+        runner.commands(),
+        [format!("umount -l {}", crashed.display())]
+    );
+    assert!(ours.exists() && unrelated.exists());
+}
+
+#[test]
+fn sweep_removes_exports_of_dead_runs() {
+    let root = tempfile::tempdir().unwrap();
+    let crashed = root
+        .path()
+        .join(format!("windusb_export_{}_a1B2c3", DEAD_PID));
+    fs::create_dir(&crashed).unwrap();
+    fs::write(crashed.join("install.wim"), b"editions").unwrap();
+    let old_style = root.path().join("windusb_export_1804289383.wim");
+    fs::write(&old_style, b"editions").unwrap();
+    let ours = root
+        .path()
+        .join(format!("windusb_export_{}_x9Y8z7", std::process::id()));
+    fs::create_dir(&ours).unwrap();
+    let unrelated = root.path().join("install.wim");
+    fs::write(&unrelated, b"").unwrap();
+
+    let mut removed = sweep_exports_in(root.path());
+
+    removed.sort();
+    let mut expected = vec![crashed, old_style];
+    expected.sort();
+    assert_eq!(removed, expected);
+    assert!(ours.exists() && unrelated.exists());
+}
@@ -0,0 +1,227 @@
+mod support;
+
+use std::fs;
+
+use support::image::{udf, wim, UdfOptions};
+use support::{ok, FakeRunner};
+use windusb::platform::detect_platform_with;
+use windusb::{
+    Architecture, Edition, Generation, IsoEntry, PartitionScheme, PlatformInfo, PlatformWarning,
+    WindowsVersion,
+};
+
+fn files(paths: &[&str]) -> Vec<IsoEntry> {
+    paths
+        .iter()
+        .map(|path| IsoEntry {
+            path: path.to_string(),
+            size: 1,
+            is_dir: false,
+        })
+        .collect()
+}
+
+fn edition(arch: Architecture, version: (u32, u32, u32), kind: &str) -> Edition {
+    Edition {
+        index: 1,
+        name: "Windows".to_string(),
+        description: None,
+        edition_id: None,
+        installation_type: Some(kind.to_string()),
+        version: Some(WindowsVersion {
+            major: version.0,
+            minor: version.1,
+            build: version.2,
+            sp_build: 0,
+        }),
+        languages: Vec::new(),
+        architecture: Some(arch),
+        size: 0,
+    }
+}
+
+#[test]
+fn maps_versions_to_releases() {
+    let release = |major, minor, build, server| {
+        Generation::from_version(
+            WindowsVersion {
+                major,
+                minor,
+                build,
+                sp_build: 0,
+            },
+            server,
+        )
+    };
+
+    assert_eq!(release(6, 1, 7601, false), Some(Generation::Windows7));
+    assert_eq!(release(6, 3, 9600, false), Some(Generation::Windows8_1));
+    assert_eq!(release(10, 0, 19045, false), Some(Generation::Windows10));
+    assert_eq!(release(10, 0, 22631, false), Some(Generation::Windows11));
+    assert_eq!(
+        release(10, 0, 20348, true),
+        Some(Generation::Server("2022"))
+    );
+    assert_eq!(
+        release(6, 1, 7601, true),
+        Some(Generation::Server("2008 R2"))
+    );
+    assert_eq!(release(5, 1, 2600, false), None);
+}
+
+#[test]
+fn windows_11_x64_has_no_warnings() {
+    let info = PlatformInfo::from_contents(
+        &files(&["bootmgr", "EFI/BOOT/BOOTX64.EFI", "sources/install.wim"]),
+        &[edition(Architecture::X64, (10, 0, 22631), "Client")],
+    );
+
+    assert_eq!(info.generation, Some(Generation::Windows11));
+    assert_eq!(info.architectures, [Architecture::X64]);
+    assert!(info.bios_bootable);
+    assert_eq!(info.summary(), "Windows 11 · x64");
+    assert_eq!(info.warnings(PartitionScheme::Gpt), []);
+}
+
+#[test]
+fn windows_7_without_uefi_loader_is_flagged() {
+    let info = PlatformInfo::from_contents(
+        &files(&["bootmgr", "efi/microsoft/boot/bcd", "sources/install.wim"]),
+        &[edition(Architecture::X64, (6, 1, 7601), "Client")],
+    );
+
+    assert_eq!(
+        info.warnings(PartitionScheme::Gpt),
+        [
+            PlatformWarning::NoUefiLoader,
+            PlatformWarning::Windows7OnUefi
+        ]
+    );
+    // `bootmgr` starts it from the MBR instead.
+    for scheme in [PartitionScheme::Mbr, PartitionScheme::Hybrid] {
+        assert_eq!(info.warnings(scheme), []);
+    }
+}
+
+#[test]
+fn windows_7_is_only_flagged_when_written_for_uefi_alone() {
+    let info = PlatformInfo::from_contents(
+        &files(&["bootmgr", "efi/boot/bootx64.efi", "sources/install.wim"]),
+        &[edition(Architecture::X64, (6, 1, 7601), "Client")],
+    );
+
+    assert_eq!(
+        info.warnings(PartitionScheme::Gpt),
+        [PlatformWarning::Windows7OnUefi]
+    );
+    for scheme in [PartitionScheme::Mbr, PartitionScheme::Hybrid] {
+        assert_eq!(info.warnings(scheme), []);
+    }
+}
+
+#[test]
+fn iso_without_any_boot_loader_is_flagged_on_every_scheme() {
+    let info = PlatformInfo::from_contents(
+        &files(&["sources/install.wim"]),
+        &[edition(Architecture::X64, (10, 0, 19045), "Client")],
+    );
+
+    for scheme in PartitionScheme::ALL {
+        assert_eq!(info.warnings(scheme), [PlatformWarning::NoUefiLoader]);
+    }
+}
+
+#[test]
+fn thirty_two_bit_isos_warn_about_uefi() {
+    let info = PlatformInfo::from_contents(
+        &files(&["efi/boot/bootia32.efi"]),
+        &[edition(Architecture::X86, (10, 0, 19041), "Client")],
+    );
+
+    assert_eq!(
+        info.warnings(PartitionScheme::Gpt),
+        [PlatformWarning::Only32BitUefi]
+    );
+}
+
+#[test]
+fn arm64_isos_warn_about_devices() {
+    let info = PlatformInfo::from_contents(
+        &files(&["efi/boot/bootaa64.efi"]),
+        &[edition(Architecture::Arm64, (10, 0, 22631), "Client")],
+    );
+
+    assert_eq!(info.summary(), "Windows 11 · ARM64");
+    assert_eq!(
+        info.warnings(PartitionScheme::Gpt),
+        [PlatformWarning::ArmOnly(Architecture::Arm64)]
+    );
+}
+
+#[test]
+fn loader_for_the_wrong_architecture_is_flagged() {
+    let info = PlatformInfo::from_contents(
+        &files(&["efi/boot/bootx64.efi"]),
+        &[edition(Architecture::Arm64, (10, 0, 22631), "Client")],
+    );
+
+    assert!(info
+        .warnings(PartitionScheme::Gpt)
+        .contains(&PlatformWarning::LoaderMismatch {
+            loaders: vec![Architecture::X64],
+            images: vec![Architecture::Arm64],
+        }));
+}
+
+#[test]
+fn server_images_are_recognised() {
+    let info = PlatformInfo::from_contents(
+        &files(&["efi/boot/bootx64.efi"]),
+        &[edition(Architecture::X64, (10, 0, 20348), "Server Core")],
+    );
+
+    assert_eq!(info.summary(), "Windows Server 2022 · x64");
+}
+
+#[test]
+fn falls_back_to_loaders_without_metadata() {
+    let runner = FakeRunner::new().on("7z", Some("l"), |_| {
+        Ok(ok("\
+   Date      Time    Attr         Size   Compressed  Name
+------------------- ----- ------------ ------------  ------------------------
+2023-10-20 04:12:40 ....A       408912               efi/boot/bootaa64.efi
+2023-10-20 04:12:40 ....A   4000000000               sources/install.esd
+------------------- ----- ------------ ------------  ------------------------
+"))
+    });
+
+    let info = detect_platform_with(&runner, "/isos/win11.iso".as_ref()).unwrap();
+
+    assert_eq!(info.generation, None);
+    assert_eq!(info.architectures, [Architecture::Arm64]);
+    assert!(!info.bios_bootable);
+}
+
+#[test]
+fn reads_metadata_from_the_iso() {
+    let dir = tempfile::tempdir().unwrap();
+    let iso = dir.path().join("win10.iso");
+    let xml = r#"<WIM><IMAGE INDEX="1"><WINDOWS><ARCH>9</ARCH><INSTALLATIONTYPE>Client</INSTALLATIONTYPE><VERSION><MAJOR>10</MAJOR><MINOR>0</MINOR><BUILD>19045</BUILD></VERSION></WINDOWS><NAME>Windows 10 Pro</NAME></IMAGE></WIM>"#;
+    fs::write(
+        &iso,
+        udf(
+            &[
+                ("bootmgr", b"boot"),
+                ("efi/boot/bootx64.efi", b"efi"),
+                ("sources/install.wim", &wim(xml)),
+            ],
+            UdfOptions::default(),
+        ),
+    )
+    .unwrap();
+
+    let info = detect_platform_with(&FakeRunner::new(), &iso).unwrap();
+
+    assert_eq!(info.summary(), "Windows 10 · x64");
+    assert!(info.warnings(PartitionScheme::Gpt).is_empty());
+}
@@ -0,0 +1,106 @@
+use windusb::progress::{parse_7z_progress, parse_wimlib_progress, WimlibProgress};
+use windusb::runner::output_lines;
+use windusb::{CommandRunner, Invocation, SystemRunner};
+
+const MIB: u64 = 1024 * 1024;
+
+#[test]
+fn splits_redrawn_progress_into_lines() {
+    let lines: Vec<&str> =
+        output_lines("  0%\u{8}\u{8}\u{8}\u{8} 12% 3 - boot\\bcd\r 40%\nEverything is Ok\n")
+            .collect();
+
+    assert_eq!(
+        lines,
+        ["0%", "12% 3 - boot\\bcd", "40%", "Everything is Ok"]
+    );
+}
+
+#[test]
+fn parses_7z_progress() {
+    let progress = parse_7z_progress(" 42% 17 - sources\\boot.wim").unwrap();
+    assert_eq!(progress.fraction, 0.42);
+    assert_eq!(progress.file.as_deref(), Some("sources/boot.wim"));
+
+    assert_eq!(parse_7z_progress("100%").unwrap().file, None);
+    assert!(parse_7z_progress("Extracting archive: win11.iso").is_none());
+}
+
+#[test]
+fn parses_wimlib_progress() {
+    let archiving =
+        parse_wimlib_progress("Archiving file data: 1203 MiB of 4764 MiB (25%) done").unwrap();
+    assert_eq!(archiving.bytes_done, Some(1203 * MIB));
+    assert_eq!(archiving.bytes_total, Some(4764 * MIB));
+    assert_eq!(archiving.fraction, 0.25);
+    assert_eq!(archiving.file, None);
+
+    let part = parse_wimlib_progress(
+        "Writing \"/tmp/usb/sources/install2.swm\" (part 2 of 2): 3400 MiB of 4764 MiB (71%) written",
+    )
+    .unwrap();
+    assert_eq!(part.bytes_done, Some(3400 * MIB));
+    assert_eq!(part.file.as_deref(), Some("/tmp/usb/sources/install2.swm"));
+
+    assert!(parse_wimlib_progress("Splitting WIM: 2 parts").is_none());
+}
+
+#[test]
+fn split_progress_adds_part_counters_to_the_part_start() {
+    let mut wimlib = WimlibProgress::default();
+
+    assert!(wimlib.update("Writing \"a.swm\" (part 1 of 2): 0 MiB of 4000 MiB (0%) written"));
+    assert!(wimlib.update("Archiving file data: 1000 MiB of 3400 MiB (29%) done"));
+    assert_eq!(wimlib.done(), 1000 * MIB);
+    assert!(wimlib.update("Writing \"a2.swm\" (part 2 of 2): 3400 MiB of 4000 MiB (85%) written"));
+    assert!(wimlib.update("Archiving file data: 300 MiB of 600 MiB (50%) done"));
+    assert_eq!(wimlib.done(), 3700 * MIB);
+    assert_eq!(wimlib.total(), Some(4000 * MIB));
+    assert!(!wimlib.update("Finished splitting"));
+}
+
+#[test]
+fn export_progress_is_taken_as_is() {
+    let mut wimlib = WimlibProgress::default();
+
+    wimlib.update("Archiving file data: 10 GiB of 12 GiB (83%) done");
+
+    assert_eq!(wimlib.done(), 10 << 30);
+    assert_eq!(wimlib.total(), Some(12 << 30));
+}
+
+#[test]
+fn system_runner_streams_stdout_lines() {
+    let mut lines = Vec::new();
+
+    let out = SystemRunner
+        .run_with_output(
+            &Invocation::new("sh").args([
+                "-c",
+                "printf ' 10%%\\b\\b\\b\\b 20%%\\rdone\\n'; echo oops >&2; exit 3",
+            ]),
+            &mut |line| lines.push(line.to_string()),
+        )
+        .unwrap();
+
+    assert_eq!(lines, ["10%", "20%", "done"]);
+    assert_eq!(out.status, Some(3));
+    assert_eq!(out.stderr, "oops\n");
+}
+
+#[test]
+fn system_runner_joins_lines_split_across_writes() {
+    let mut lines = Vec::new();
+
+    SystemRunner
+        .run_with_output(
+            &Invocation::new("sh").args([
+                "-c",
+                "printf 'a\\n'; sleep 0.1; printf 'b'; sleep 0.1; printf 'c\\n'",
+            ]),
+            &mut |line| lines.push(line.to_string()),
+        )
+        .unwrap();
+
+    assert_eq!(lines, ["a", "bc"]);
+}
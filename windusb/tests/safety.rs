@@ -0,0 +1,279 @@
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+
+use tempfile::TempDir;
+use windusb::{BusyProcess, DiskInspector, Hazard};
+
+/// Minimal `/proc` and `/sys` trees for one disk.
+struct FakeSystem {
+    root: TempDir,
+}
+
+impl FakeSystem {
+    fn new() -> Self {
+        let system = FakeSystem {
+            root: tempfile::tempdir().unwrap(),
+        };
+        system.write("proc/mounts", "proc /proc proc rw 0 0\n");
+        system.write(
+            "proc/swaps",
+            "Filename\t\t\t\tType\t\tSize\t\tUsed\t\tPriority\n",
+        );
+        system
+    }
+
+    fn path(&self, rel: &str) -> PathBuf {
+        self.root.path().join(rel)
+    }
+
+    fn write(&self, rel: &str, contents: &str) {
+        let path = self.path(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn disk(&self, name: &str, removable: bool, partitions: &[&str]) -> &Self {
+        self.write(
+            &format!("sys/block/{}/removable", name),
+            if removable { "1\n" } else { "0\n" },
+        );
+        fs::create_dir_all(self.path(&format!("sys/class/block/{}/holders", name))).unwrap();
+        for part in partitions {
+            self.write(&format!("sys/block/{}/{}/partition", name, part), "1\n");
+            fs::create_dir_all(self.path(&format!("sys/class/block/{}/holders", part))).unwrap();
+        }
+        self
+    }
+
+    fn holder(&self, device: &str, holder: &str, dm: Option<(&str, &str)>) -> &Self {
+        fs::create_dir_all(self.path(&format!("sys/class/block/{}/holders/{}", device, holder)))
+            .unwrap();
+        fs::create_dir_all(self.path(&format!("sys/class/block/{}/holders", holder))).unwrap();
+        if let Some((name, uuid)) = dm {
+            self.write(&format!("sys/class/block/{}/dm/name", holder), name);
+            self.write(&format!("sys/class/block/{}/dm/uuid", holder), uuid);
+        }
+        self
+    }
+
+    fn mount(&self, source: &str, mountpoint: &str) -> &Self {
+        let path = self.path("proc/mounts");
+        let mut mounts = fs::read_to_string(&path).unwrap();
+        mounts.push_str(&format!("{} {} ext4 rw 0 0\n", source, mountpoint));
+        fs::write(path, mounts).unwrap();
+        self
+    }
+
+    fn mountinfo(&self, source: &str, mountpoint: &str) -> &Self {
+        let path = self.path("proc/self/mountinfo");
+        let mut mounts = fs::read_to_string(&path).unwrap_or_default();
+        mounts.push_str(&format!(
+            "{} 1 8:17 / {} rw,nosuid shared:1 - vfat {} rw\n",
+            100 + mounts.lines().count(),
+            mountpoint,
+            source
+        ));
+        self.write("proc/self/mountinfo", &mounts);
+        self
+    }
+
+    /// A process named `comm` with its working directory and open files at `paths`.
+    fn process(&self, pid: u32, comm: &str, cwd: &str, files: &[&str]) -> &Self {
+        self.write(&format!("proc/{}/comm", pid), &format!("{}\n", comm));
+        symlink(cwd, self.path(&format!("proc/{}/cwd", pid))).unwrap();
+        symlink("/", self.path(&format!("proc/{}/root", pid))).unwrap();
+        fs::create_dir_all(self.path(&format!("proc/{}/fd", pid))).unwrap();
+        for (fd, file) in files.iter().enumerate() {
+            symlink(file, self.path(&format!("proc/{}/fd/{}", pid, fd))).unwrap();
+        }
+        self
+    }
+
+    fn swap(&self, source: &str) -> &Self {
+        let path = self.path("proc/swaps");
+        let mut swaps = fs::read_to_string(&path).unwrap();
+        swaps.push_str(&format!("{}\tpartition\t8388604\t0\t-2\n", source));
+        fs::write(path, swaps).unwrap();
+        self
+    }
+
+    fn inspector(&self) -> DiskInspector {
+        DiskInspector::with_roots(self.path("proc"), self.path("sys"))
+    }
+}
+
+fn hazards(system: &FakeSystem, drive: &str) -> Vec<Hazard> {
+    system.inspector().hazards(drive)
+}
+
+#[test]
+fn plain_usb_stick_is_safe() {
+    let system = FakeSystem::new();
+    system
+        .disk("sdb", true, &["sdb1"])
+        .mount("/dev/sdb1", "/run/media/user/USB\\040STICK");
+
+    assert_eq!(hazards(&system, "/dev/sdb"), vec![]);
+}
+
+#[test]
+fn disk_hosting_root_is_refused() {
+    let system = FakeSystem::new();
+    system
+        .disk("sdb", true, &["sdb1", "sdb2"])
+        .mount("/dev/sdb2", "/")
+        .mount("/dev/sdb1", "/boot/efi");
+
+    assert_eq!(
+        hazards(&system, "/dev/sdb"),
+        vec![
+            Hazard::SystemMount {
+                device: "/dev/sdb2".into(),
+                mountpoint: "/".into()
+            },
+            Hazard::SystemMount {
+                device: "/dev/sdb1".into(),
+                mountpoint: "/boot/efi".into()
+            },
+        ]
+    );
+}
+
+#[test]
+fn fixed_disk_is_refused() {
+    let system = FakeSystem::new();
+    system.disk("nvme0n1", false, &["nvme0n1p1"]);
+
+    assert_eq!(hazards(&system, "/dev/nvme0n1"), vec![Hazard::NotRemovable]);
+}
+
+#[test]
+fn active_swap_partition_is_refused() {
+    let system = FakeSystem::new();
+    system
+        .disk("sdc", true, &["sdc1", "sdc2"])
+        .swap("/dev/sdc2");
+
+    assert_eq!(
+        hazards(&system, "/dev/sdc"),
+        vec![Hazard::ActiveSwap {
+            device: "/dev/sdc2".into()
+        }]
+    );
+}
+
+#[test]
+fn lvm_on_luks_is_traced_to_the_mount() {
+    let system = FakeSystem::new();
+    system
+        .disk("sdb", true, &["sdb1"])
+        .holder(
+            "sdb1",
+            "dm-0",
+            Some(("luks-1234", "CRYPT-LUKS2-1234-luks-1234")),
+        )
+        .holder("dm-0", "dm-1", Some(("vg-home", "LVM-abcdef")))
+        .mount("/dev/mapper/vg-home", "/home");
+
+    let found = hazards(&system, "/dev/sdb");
+
+    assert!(found.contains(&Hazard::LuksMember {
+        device: "/dev/sdb1".into(),
+        holder: "/dev/mapper/luks-1234".into()
+    }));
+    assert!(found.contains(&Hazard::LvmMember {
+        device: "/dev/sdb1".into(),
+        holder: "/dev/mapper/vg-home".into()
+    }));
+    assert!(found.contains(&Hazard::SystemMount {
+        device: "/dev/sdb1".into(),
+        mountpoint: "/home".into()
+    }));
+}
+
+#[test]
+fn raid_member_is_refused() {
+    let system = FakeSystem::new();
+    system
+        .disk("sdd", true, &["sdd1"])
+        .holder("sdd1", "md127", None);
+
+    assert_eq!(
+        hazards(&system, "/dev/sdd"),
+        vec![Hazard::RaidMember {
+            device: "/dev/sdd1".into(),
+            holder: "/dev/md127".into()
+        }]
+    );
+}
+
+#[test]
+fn unknown_device_has_no_hazards() {
+    let system = FakeSystem::new();
+    assert!(hazards(&system, "/dev/sdq").is_empty());
+}
+
+#[test]
+fn mounts_on_lists_partitions_deepest_first() {
+    let system = FakeSystem::new();
+    system
+        .disk("sdb", true, &["sdb1", "sdb2"])
+        .mountinfo("/dev/sda2", "/")
+        .mountinfo("/dev/sdb1", "/run/media/user/USB\\040STICK")
+        .mountinfo("/dev/sdb2", "/run/media/user/USB\\040STICK/inner")
+        .mountinfo("tmpfs", "/tmp");
+
+    assert_eq!(
+        system.inspector().mounts_on("/dev/sdb"),
+        vec![
+            (
+                "/dev/sdb2".to_string(),
+                "/run/media/user/USB STICK/inner".to_string()
+            ),
+            (
+                "/dev/sdb1".to_string(),
+                "/run/media/user/USB STICK".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn processes_using_a_mountpoint_are_found_by_open_files_and_cwd() {
+    let system = FakeSystem::new();
+    system
+        .process(4242, "nautilus", "/home/user", &["/media/stick/setup.exe"])
+        .process(17, "bash", "/media/stick/sources", &[])
+        .process(99, "vim", "/home/user", &["/media/stick2/notes.txt"]);
+
+    assert_eq!(
+        system.inspector().processes_using("/media/stick"),
+        vec![
+            BusyProcess {
+                pid: 17,
+                command: "bash".into()
+            },
+            BusyProcess {
+                pid: 4242,
+                command: "nautilus".into()
+            },
+        ]
+    );
+}
+
+#[test]
+fn partition_node_comes_from_sysfs() {
+    let system = FakeSystem::new();
+    system.disk("mmcblk0", true, &["mmcblk0p1", "mmcblk0p2"]);
+    system.write("sys/block/mmcblk0/mmcblk0p2/partition", "2\n");
+
+    let inspector = system.inspector();
+
+    assert_eq!(
+        inspector.partition_node("/dev/mmcblk0", 2).as_deref(),
+        Some("/dev/mmcblk0p2")
+    );
+    assert_eq!(inspector.partition_node("/dev/mmcblk0", 3), None);
+    assert_eq!(inspector.partition_node("/dev/sdq", 1), None);
+}
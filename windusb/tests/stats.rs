@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+
+use windusb::stats::format_duration;
+use windusb::{FlashStats, Phase, Progress};
+
+const MB: u64 = 1_000_000;
+
+fn secs(n: u64) -> Duration {
+    Duration::from_secs(n)
+}
+
+fn progress(phase: Phase) -> Progress {
+    Progress::new(phase, "", 0.5)
+}
+
+#[test]
+fn speed_and_eta_follow_the_drive_counter() {
+    let t0 = Instant::now();
+    let mut stats = FlashStats::started_at(t0, Some(1000 * MB));
+    stats.observe_at(t0, &progress(Phase::CopyingBootFiles));
+
+    stats.record_at(t0, 5000 * MB);
+    stats.record_at(t0 + secs(4), 5000 * MB + 40 * MB);
+
+    assert_eq!(stats.bytes_written(), Some(40 * MB));
+    assert_eq!(stats.bytes_per_sec(), Some(10.0 * MB as f64));
+    assert_eq!(stats.eta(), Some(secs(96)));
+    assert_eq!(stats.elapsed_at(t0 + secs(4)), secs(4));
+}
+
+#[test]
+fn falls_back_to_the_phase_eta_while_the_drive_is_idle() {
+    let t0 = Instant::now();
+    let mut stats = FlashStats::started_at(t0, Some(1000 * MB));
+    stats.record_at(t0, 0);
+    stats.record_at(t0 + secs(10), 0);
+
+    stats.observe_at(t0 + secs(10), &progress(Phase::ExportingImage).rate(None));
+    assert_eq!(stats.eta(), None);
+
+    let exporting = progress(Phase::ExportingImage)
+        .bytes(100, Some(400))
+        .rate(Some(10.0));
+    stats.observe_at(t0 + secs(11), &exporting);
+    assert_eq!(stats.eta(), Some(secs(30)));
+}
+
+#[test]
+fn breaks_time_and_writes_down_by_phase() {
+    let t0 = Instant::now();
+    let mut stats = FlashStats::started_at(t0, None);
+    stats.record_at(t0, 0);
+    stats.observe_at(t0, &progress(Phase::Formatting));
+    stats.record_at(t0 + secs(3), 2 * MB);
+    stats.observe_at(t0 + secs(3), &progress(Phase::CopyingBootFiles));
+    stats.observe_at(t0 + secs(5), &progress(Phase::CopyingBootFiles));
+    stats.record_at(t0 + secs(63), 600 * MB);
+    stats.observe_at(t0 + secs(63), &progress(Phase::SplittingImage));
+    stats.record_at(t0 + secs(70), 700 * MB);
+
+    let phases = stats.phases_at(t0 + secs(70));
+
+    let summary: Vec<_> = phases
+        .iter()
+        .map(|p| (p.phase, p.elapsed, p.bytes_written, p.finished))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (Phase::Formatting, secs(3), Some(2 * MB), true),
+            (Phase::CopyingBootFiles, secs(60), Some(598 * MB), true),
+            (Phase::SplittingImage, secs(7), Some(100 * MB), false),
+        ]
+    );
+}
+
+#[test]
+fn clock_stops_when_finished() {
+    let t0 = Instant::now();
+    let mut stats = FlashStats::started_at(t0, Some(10 * MB));
+    stats.observe_at(t0, &progress(Phase::Syncing));
+    stats.record_at(t0, 0);
+    stats.record_at(t0 + secs(1), MB);
+
+    stats.finish_at(t0 + secs(2));
+    stats.record_at(t0 + secs(5), 10 * MB);
+
+    assert_eq!(stats.elapsed_at(t0 + secs(30)), secs(2));
+    assert_eq!(stats.bytes_written(), Some(MB));
+    assert_eq!(stats.bytes_per_sec(), None);
+    assert_eq!(stats.eta(), None);
+    assert!(stats.phases_at(t0 + secs(30))[0].finished);
+}
+
+#[test]
+fn formats_durations() {
+    assert_eq!(format_duration(secs(7)), "0:07");
+    assert_eq!(format_duration(secs(754)), "12:34");
+    assert_eq!(format_duration(secs(3723)), "1:02:03");
+}
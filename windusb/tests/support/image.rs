@@ -0,0 +1,612 @@
+//! Builds small ISO 9660 and UDF images in memory, so the native reader can be
+//! tested without mastering tools.
+
+use std::collections::BTreeMap;
+
+const SECTOR: usize = 2048;
+
+/// Files to put in an image; directories are implied by the paths.
+pub type Files<'a> = &'a [(&'a str, &'a [u8])];
+
+#[derive(Default)]
+struct Node {
+    dirs: BTreeMap<String, Node>,
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Node {
+    fn from_files(files: Files) -> Node {
+        let mut root = Node::default();
+        for (path, data) in files {
+            let mut parts: Vec<&str> = path.split('/').collect();
+            let name = parts.pop().unwrap();
+            let mut dir = &mut root;
+            for part in parts {
+                dir = dir.dirs.entry(part.to_string()).or_default();
+            }
+            dir.files.insert(name.to_string(), data.to_vec());
+        }
+        root
+    }
+}
+
+fn put16(buf: &mut [u8], at: usize, v: u16) {
+    buf[at..at + 2].copy_from_slice(&v.to_le_bytes());
+}
+
+fn put32(buf: &mut [u8], at: usize, v: u32) {
+    buf[at..at + 4].copy_from_slice(&v.to_le_bytes());
+}
+
+fn put64(buf: &mut [u8], at: usize, v: u64) {
+    buf[at..at + 8].copy_from_slice(&v.to_le_bytes());
+}
+
+fn both32(buf: &mut [u8], at: usize, v: u32) {
+    buf[at..at + 4].copy_from_slice(&v.to_le_bytes());
+    buf[at + 4..at + 8].copy_from_slice(&v.to_be_bytes());
+}
+
+fn sectors(len: usize) -> usize {
+    len.div_ceil(SECTOR).max(1)
+}
+
+fn write_at(image: &mut Vec<u8>, offset: usize, data: &[u8]) {
+    if image.len() < offset + data.len() {
+        image.resize(offset + data.len(), 0);
+    }
+    image[offset..offset + data.len()].copy_from_slice(data);
+}
+
+fn volume_descriptor(kind: u8) -> Vec<u8> {
+    let mut desc = vec![0; SECTOR];
+    desc[0] = kind;
+    desc[1..6].copy_from_slice(b"CD001");
+    desc[6] = 1;
+    desc
+}
+
+// ---------------------------------------------------------------- ISO 9660
+
+/// One directory tree (primary or Joliet) laid out on disc.
+struct IsoTree {
+    joliet: bool,
+    /// Directory path -> (first sector, byte size).
+    dirs: BTreeMap<String, (usize, usize)>,
+}
+
+fn iso_name(name: &str, joliet: bool, is_dir: bool) -> Vec<u8> {
+    let name = if is_dir {
+        name.to_string()
+    } else {
+        format!("{};1", name)
+    };
+    if joliet {
+        name.encode_utf16().flat_map(|u| u.to_be_bytes()).collect()
+    } else {
+        name.to_uppercase().into_bytes()
+    }
+}
+
+fn iso_record(name: &[u8], lba: usize, size: usize, flags: u8) -> Vec<u8> {
+    let len = 33 + name.len() + (1 - name.len() % 2);
+    let mut rec = vec![0; len];
+    rec[0] = len as u8;
+    both32(&mut rec, 2, lba as u32);
+    both32(&mut rec, 10, size as u32);
+    rec[25] = flags;
+    rec[28] = 1;
+    rec[31] = 1;
+    rec[32] = name.len() as u8;
+    rec[33..33 + name.len()].copy_from_slice(name);
+    rec
+}
+
+/// Packs records into sectors without letting one span a boundary.
+fn pack(records: &[Vec<u8>]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for rec in records {
+        let used = data.len() % SECTOR;
+        if used + rec.len() > SECTOR {
+            data.resize(data.len() + SECTOR - used, 0);
+        }
+        data.extend_from_slice(rec);
+    }
+    data.resize(sectors(data.len()) * SECTOR, 0);
+    data
+}
+
+/// An ISO 9660 image, with a Joliet tree when `joliet` is set. Files larger
+/// than `max_extent` bytes are recorded as multi-extent files.
+pub fn iso9660(files: Files, joliet: bool, max_extent: Option<usize>) -> Vec<u8> {
+    let root = Node::from_files(files);
+    let max_extent = max_extent.unwrap_or(u32::MAX as usize & !(SECTOR - 1));
+
+    // Primary descriptor, optional Joliet descriptor and terminator.
+    let mut trees = vec![IsoTree {
+        joliet: false,
+        dirs: BTreeMap::new(),
+    }];
+    if joliet {
+        trees.push(IsoTree {
+            joliet: true,
+            dirs: BTreeMap::new(),
+        });
+    }
+    let mut next = 16 + trees.len() + 1;
+
+    // Directory sizes only depend on names, so lay directories out first.
+    for tree in &mut trees {
+        let mut pending = vec![(String::new(), &root)];
+        while let Some((path, node)) = pending.pop() {
+            let mut records = vec![iso_record(&[0], 0, 0, 2), iso_record(&[1], 0, 0, 2)];
+            for name in node.dirs.keys() {
+                records.push(iso_record(&iso_name(name, tree.joliet, true), 0, 0, 2));
+            }
+            for (name, data) in &node.files {
+                let chunks = data.len().div_ceil(max_extent).max(1);
+                for _ in 0..chunks {
+                    records.push(iso_record(&iso_name(name, tree.joliet, false), 0, 0, 0));
+                }
+            }
+            let size = pack(&records).len();
+            tree.dirs.insert(path.clone(), (next, size));
+            next += size / SECTOR;
+            for (name, child) in &node.dirs {
+                let child_path = format!("{}/{}", path, name);
+                pending.push((child_path, child));
+            }
+        }
+    }
+
+    let mut file_lba = BTreeMap::new();
+    let mut pending = vec![(String::new(), &root)];
+    while let Some((path, node)) = pending.pop() {
+        for (name, data) in &node.files {
+            file_lba.insert(format!("{}/{}", path, name), next);
+            next += sectors(data.len());
+        }
+        for (name, child) in &node.dirs {
+            pending.push((format!("{}/{}", path, name), child));
+        }
+    }
+
+    let mut image = vec![0; next * SECTOR];
+    let mut descriptors = Vec::new();
+    for tree in &trees {
+        let mut desc = volume_descriptor(if tree.joliet { 2 } else { 1 });
+        if tree.joliet {
+            desc[88..91].copy_from_slice(&[0x25, 0x2F, 0x45]);
+        }
+        let (root_lba, root_size) = tree.dirs[""];
+        let root_rec = iso_record(&[0], root_lba, root_size, 2);
+        desc[156..156 + 34].copy_from_slice(&root_rec);
+        descriptors.push(desc);
+
+        let mut pending = vec![(String::new(), String::new(), &root)];
+        while let Some((path, parent, node)) = pending.pop() {
+            let (lba, size) = tree.dirs[&path];
+            let (parent_lba, parent_size) = tree.dirs[&parent];
+            let mut records = vec![
+                iso_record(&[0], lba, size, 2),
+                iso_record(&[1], parent_lba, parent_size, 2),
+            ];
+            for name in node.dirs.keys() {
+                let child_path = format!("{}/{}", path, name);
+                let (child_lba, child_size) = tree.dirs[&child_path];
+                records.push(iso_record(
+                    &iso_name(name, tree.joliet, true),
+                    child_lba,
+                    child_size,
+                    2,
+                ));
+            }
+            for (name, data) in &node.files {
+                let start = file_lba[&format!("{}/{}", path, name)];
+                let chunks = data.len().div_ceil(max_extent).max(1);
+                for chunk in 0..chunks {
+                    let len = (data.len() - chunk * max_extent).min(max_extent);
+                    let flags = if chunk + 1 < chunks { 0x80 } else { 0 };
+                    records.push(iso_record(
+                        &iso_name(name, tree.joliet, false),
+                        start + chunk * max_extent / SECTOR,
+                        len,
+                        flags,
+                    ));
+                }
+            }
+            write_at(&mut image, lba * SECTOR, &pack(&records));
+            for (name, child) in &node.dirs {
+                pending.push((format!("{}/{}", path, name), path.clone(), child));
+            }
+        }
+    }
+    descriptors.push(volume_descriptor(255));
+    for (i, desc) in descriptors.iter().enumerate() {
+        write_at(&mut image, (16 + i) * SECTOR, desc);
+    }
+
+    let mut pending = vec![(String::new(), &root)];
+    while let Some((path, node)) = pending.pop() {
+        for (name, data) in &node.files {
+            let lba = file_lba[&format!("{}/{}", path, name)];
+            write_at(&mut image, lba * SECTOR, data);
+        }
+        for (name, child) in &node.dirs {
+            pending.push((format!("{}/{}", path, name), child));
+        }
+    }
+    image
+}
+
+// --------------------------------------------------------------------- UDF
+
+/// Knobs for [`udf`].
+#[derive(Clone, Copy, Default)]
+pub struct UdfOptions {
+    /// UDF 2.50 layout: extended file entries and a metadata partition whose
+    /// file is split into two extents.
+    pub metadata_partition: bool,
+    /// Embed files up to this size in their file entry.
+    pub inline_up_to: usize,
+    /// Split file data into allocation descriptors of at most this many bytes.
+    pub max_extent: Option<usize>,
+    /// Precede the UDF structures with an ISO 9660 tree holding only a README,
+    /// like Windows installation media.
+    pub bridge: bool,
+}
+
+const PARTITION_START: usize = 257;
+
+fn tag(buf: &mut [u8], id: u16, location: u32) {
+    put16(buf, 0, id);
+    put16(buf, 2, 2);
+    put32(buf, 12, location);
+}
+
+fn dstring(name: &str) -> Vec<u8> {
+    if name.chars().all(|c| (c as u32) < 256) {
+        let mut out = vec![8];
+        out.extend(name.chars().map(|c| c as u8));
+        out
+    } else {
+        let mut out = vec![16];
+        out.extend(name.encode_utf16().flat_map(|u| u.to_be_bytes()));
+        out
+    }
+}
+
+/// Where a block lives: logical block number and partition reference.
+#[derive(Clone, Copy)]
+struct Addr {
+    block: usize,
+    partition: u16,
+}
+
+fn long_ad(buf: &mut [u8], at: usize, len: usize, addr: Addr) {
+    put32(buf, at, len as u32);
+    put32(buf, at + 4, addr.block as u32);
+    put16(buf, at + 8, addr.partition);
+}
+
+struct UdfEntry {
+    path: String,
+    is_dir: bool,
+    data: Vec<u8>,
+    fe: Addr,
+    /// Logical blocks holding the data, if not embedded.
+    data_at: Option<Addr>,
+}
+
+/// A UDF image (ECMA-167 file entries with short or long allocation descriptors).
+pub fn udf(files: Files, opts: UdfOptions) -> Vec<u8> {
+    let root = Node::from_files(files);
+    let meta_partition: u16 = if opts.metadata_partition { 1 } else { 0 };
+
+    // Metadata space: FSD, file entries and directory contents.
+    let mut meta_next = 1; // block 0 holds the file set descriptor
+    let mut entries: Vec<UdfEntry> = Vec::new();
+    let mut children: BTreeMap<String, Vec<(String, bool)>> = BTreeMap::new();
+    let mut pending = vec![(String::new(), &root)];
+    while let Some((path, node)) = pending.pop() {
+        entries.push(UdfEntry {
+            path: path.clone(),
+            is_dir: true,
+            data: Vec::new(),
+            fe: Addr {
+                block: meta_next,
+                partition: meta_partition,
+            },
+            data_at: None,
+        });
+        meta_next += 1;
+        let mut list = Vec::new();
+        for (name, data) in &node.files {
+            entries.push(UdfEntry {
+                path: format!("{}/{}", path, name),
+                is_dir: false,
+                data: data.clone(),
+                fe: Addr {
+                    block: meta_next,
+                    partition: meta_partition,
+                },
+                data_at: None,
+            });
+            meta_next += 1;
+            list.push((name.clone(), false));
+        }
+        for (name, child) in &node.dirs {
+            pending.push((format!("{}/{}", path, name), child));
+            list.push((name.clone(), true));
+        }
+        children.insert(path, list);
+    }
+    let fe_of = |entries: &[UdfEntry], path: &str| -> Addr {
+        entries.iter().find(|e| e.path == path).unwrap().fe
+    };
+
+    // Directory contents: a parent FID followed by one FID per child.
+    for i in 0..entries.len() {
+        if !entries[i].is_dir {
+            continue;
+        }
+        let path = entries[i].path.clone();
+        let parent = match path.rfind('/') {
+            Some(at) => path[..at].to_string(),
+            None => String::new(),
+        };
+        let mut data = fid(&[], 0x0A, fe_of(&entries, &parent));
+        for (name, is_dir) in &children[&path] {
+            let child = fe_of(&entries, &format!("{}/{}", path, name));
+            data.extend(fid(&dstring(name), if *is_dir { 0x02 } else { 0 }, child));
+        }
+        entries[i].data = data;
+    }
+    for entry in entries.iter_mut().filter(|e| e.is_dir) {
+        entry.data_at = Some(Addr {
+            block: meta_next,
+            partition: meta_partition,
+        });
+        meta_next += sectors(entry.data.len());
+    }
+
+    // Physical partition: [metadata file entry] [metadata blocks] [file data].
+    let meta_split = (meta_next / 2).max(1);
+    let meta_gap = 3;
+    let meta_to_physical = |block: usize| -> usize {
+        if !opts.metadata_partition {
+            block
+        } else if block < meta_split {
+            1 + block
+        } else {
+            1 + meta_split + meta_gap + (block - meta_split)
+        }
+    };
+    let mut data_next = if opts.metadata_partition {
+        1 + meta_next + meta_gap
+    } else {
+        meta_next
+    };
+    for entry in entries.iter_mut().filter(|e| !e.is_dir) {
+        if entry.data.len() > opts.inline_up_to {
+            entry.data_at = Some(Addr {
+                block: data_next,
+                partition: 0,
+            });
+            data_next += sectors(entry.data.len());
+        }
+    }
+
+    let partition_len = data_next;
+    let mut image = vec![0; (PARTITION_START + partition_len) * SECTOR];
+    let block_offset = |addr: Addr| -> usize {
+        let physical = if addr.partition == 1 {
+            meta_to_physical(addr.block)
+        } else {
+            addr.block
+        };
+        (PARTITION_START + physical) * SECTOR
+    };
+
+    // Volume recognition sequence, optionally behind an ISO 9660 bridge.
+    let mut vrs = 16;
+    if opts.bridge {
+        let readme = b"This disc contains a UDF file system.";
+        let mut pvd = volume_descriptor(1);
+        pvd[156..190].copy_from_slice(&iso_record(&[0], 21, SECTOR, 2));
+        write_at(&mut image, 16 * SECTOR, &pvd);
+        write_at(&mut image, 17 * SECTOR, &volume_descriptor(255));
+        let dir = pack(&[
+            iso_record(&[0], 21, SECTOR, 2),
+            iso_record(&[1], 21, SECTOR, 2),
+            iso_record(b"README.TXT;1", 22, readme.len(), 0),
+        ]);
+        write_at(&mut image, 21 * SECTOR, &dir);
+        write_at(&mut image, 22 * SECTOR, readme);
+        vrs = 18;
+    }
+    let nsr: &[u8; 5] = if opts.metadata_partition {
+        b"NSR03"
+    } else {
+        b"NSR02"
+    };
+    for (i, id) in [b"BEA01", nsr, b"TEA01"].iter().enumerate() {
+        let mut desc = vec![0; SECTOR];
+        desc[1..6].copy_from_slice(*id);
+        desc[6] = 1;
+        write_at(&mut image, (vrs + i) * SECTOR, &desc);
+    }
+
+    // Anchor and main volume descriptor sequence at sectors 32..35.
+    let mut anchor = vec![0; SECTOR];
+    tag(&mut anchor, 2, 256);
+    put32(&mut anchor, 16, 3 * SECTOR as u32);
+    put32(&mut anchor, 20, 32);
+    write_at(&mut image, 256 * SECTOR, &anchor);
+
+    let mut pd = vec![0; SECTOR];
+    tag(&mut pd, 5, 32);
+    put16(&mut pd, 22, 0);
+    put32(&mut pd, 188, PARTITION_START as u32);
+    put32(&mut pd, 192, partition_len as u32);
+    write_at(&mut image, 32 * SECTOR, &pd);
+
+    let mut lvd = vec![0; SECTOR];
+    tag(&mut lvd, 6, 33);
+    put32(&mut lvd, 212, SECTOR as u32);
+    long_ad(
+        &mut lvd,
+        248,
+        SECTOR,
+        Addr {
+            block: 0,
+            partition: meta_partition,
+        },
+    );
+    lvd[440..446].copy_from_slice(&[1, 6, 1, 0, 0, 0]);
+    if opts.metadata_partition {
+        let map = &mut lvd[446..446 + 64];
+        map[0] = 2;
+        map[1] = 64;
+        map[5..5 + 23].copy_from_slice(b"*UDF Metadata Partition");
+        put16(map, 36, 1);
+        put16(map, 38, 0);
+        put32(map, 40, 0);
+        put32(map, 48, u32::MAX);
+        put32(&mut lvd, 264, 6 + 64);
+        put32(&mut lvd, 268, 2);
+    } else {
+        put32(&mut lvd, 264, 6);
+        put32(&mut lvd, 268, 1);
+    }
+    write_at(&mut image, 33 * SECTOR, &lvd);
+
+    let mut terminator = vec![0; SECTOR];
+    tag(&mut terminator, 8, 34);
+    write_at(&mut image, 34 * SECTOR, &terminator);
+
+    // Metadata file: an extended file entry at physical block 0 covering the
+    // two runs of metadata blocks.
+    if opts.metadata_partition {
+        let mut fe = vec![0; SECTOR];
+        tag(&mut fe, 266, 0);
+        fe[27] = 250;
+        put64(&mut fe, 56, (meta_next * SECTOR) as u64);
+        put32(&mut fe, 212, 16);
+        put32(&mut fe, 216, (meta_split * SECTOR) as u32);
+        put32(&mut fe, 220, 1);
+        put32(&mut fe, 224, ((meta_next - meta_split) * SECTOR) as u32);
+        put32(&mut fe, 228, (1 + meta_split + meta_gap) as u32);
+        write_at(&mut image, PARTITION_START * SECTOR, &fe);
+    }
+
+    let mut fsd = vec![0; SECTOR];
+    tag(&mut fsd, 256, 0);
+    long_ad(&mut fsd, 400, SECTOR, fe_of(&entries, ""));
+    write_at(
+        &mut image,
+        block_offset(Addr {
+            block: 0,
+            partition: meta_partition,
+        }),
+        &fsd,
+    );
+
+    for entry in &entries {
+        let fe = file_entry(entry, opts);
+        write_at(&mut image, block_offset(entry.fe), &fe);
+        if let Some(at) = entry.data_at {
+            write_at(&mut image, block_offset(at), &entry.data);
+        }
+    }
+    image
+}
+
+fn fid(name: &[u8], characteristics: u8, icb: Addr) -> Vec<u8> {
+    let len = (38 + name.len()).div_ceil(4) * 4;
+    let mut fid = vec![0; len];
+    tag(&mut fid, 257, icb.block as u32);
+    put16(&mut fid, 16, 1);
+    fid[18] = characteristics;
+    fid[19] = name.len() as u8;
+    long_ad(&mut fid, 20, SECTOR, icb);
+    fid[38..38 + name.len()].copy_from_slice(name);
+    fid
+}
+
+fn file_entry(entry: &UdfEntry, opts: UdfOptions) -> Vec<u8> {
+    let mut fe = vec![0; SECTOR];
+    let (id, ea_at) = if opts.metadata_partition {
+        (266, 208)
+    } else {
+        (261, 168)
+    };
+    let ads_at = ea_at + 8;
+    tag(&mut fe, id, entry.fe.block as u32);
+    put16(&mut fe, 16 + 4, 4);
+    fe[16 + 11] = if entry.is_dir { 4 } else { 5 };
+    put64(&mut fe, 56, entry.data.len() as u64);
+
+    let Some(at) = entry.data_at else {
+        // Embedded data.
+        put16(&mut fe, 16 + 18, 3);
+        put32(&mut fe, ea_at + 4, entry.data.len() as u32);
+        fe[ads_at..ads_at + entry.data.len()].copy_from_slice(&entry.data);
+        return fe;
+    };
+
+    // Long descriptors whenever data may sit in another partition than the entry.
+    let long = opts.metadata_partition;
+    let ad_size = if long { 16 } else { 8 };
+    put16(&mut fe, 16 + 18, if long { 1 } else { 0 });
+    let max_extent = opts.max_extent.unwrap_or(1 << 29);
+    let mut offset = 0;
+    let mut pos = ads_at;
+    loop {
+        let len = (entry.data.len() - offset).min(max_extent);
+        let block = at.block + offset / SECTOR;
+        if long {
+            long_ad(
+                &mut fe,
+                pos,
+                len,
+                Addr {
+                    block,
+                    partition: at.partition,
+                },
+            );
+        } else {
+            put32(&mut fe, pos, len as u32);
+            put32(&mut fe, pos + 4, block as u32);
+        }
+        pos += ad_size;
+        offset += len;
+        if offset >= entry.data.len() {
+            break;
+        }
+    }
+    put32(&mut fe, ea_at + 4, (pos - ads_at) as u32);
+    fe
+}
+
+// --------------------------------------------------------------------- WIM
+
+/// A WIM file holding only a header and the UTF-16 XML metadata `xml`.
+pub fn wim(xml: &str) -> Vec<u8> {
+    let mut data = vec![0; 208];
+    data[..8].copy_from_slice(b"MSWIM\0\0\0");
+    put32(&mut data, 8, 208);
+    put32(&mut data, 12, 0x10d00);
+    put16(&mut data, 40, 1);
+    put16(&mut data, 42, 1);
+    put32(&mut data, 44, xml.matches("<IMAGE ").count() as u32);
+
+    let mut encoded = vec![0xFF, 0xFE];
+    encoded.extend(xml.encode_utf16().flat_map(|u| u.to_le_bytes()));
+    let len = encoded.len() as u64;
+    put64(&mut data, 72, len);
+    put64(&mut data, 80, 208);
+    put64(&mut data, 88, len);
+    data.extend(encoded);
+    data
+}
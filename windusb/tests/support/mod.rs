@@ -0,0 +1,268 @@
+#![allow(dead_code)]
+
+pub mod image;
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tempfile::TempDir;
+use windusb::runner::output_lines;
+use windusb::{CommandRunner, DriveLayout, FlashError, FlashEvent, Flasher, Invocation, Output};
+
+type Handler = Box<dyn Fn(&Invocation) -> io::Result<Output> + Send + Sync>;
+
+struct Rule {
+    program: String,
+    arg: Option<String>,
+    handler: Handler,
+}
+
+impl Rule {
+    fn matches(&self, invocation: &Invocation) -> bool {
+        invocation.program == self.program
+            && self
+                .arg
+                .as_ref()
+                .is_none_or(|arg| invocation.args.iter().any(|a| a == arg))
+    }
+}
+
+/// Records every invocation and answers from scripted rules. Anything without
+/// a rule succeeds with empty output, except `7z l`, which lists an
+/// `install.wim` so the ISO is accepted.
+pub struct FakeRunner {
+    rules: Vec<Rule>,
+    calls: Mutex<Vec<Invocation>>,
+    line_delay: Option<Duration>,
+    missing: Vec<String>,
+}
+
+impl FakeRunner {
+    pub fn new() -> Self {
+        FakeRunner {
+            rules: Vec::new(),
+            calls: Mutex::new(Vec::new()),
+            line_delay: None,
+            missing: Vec::new(),
+        }
+        .on("7z", Some("l"), |_| {
+            Ok(ok(
+                "2024-01-01 00:00:00 ....A  4000000000  sources/install.wim\n",
+            ))
+        })
+    }
+
+    /// Answers invocations of `program` that carry `arg` (any, if `None`) with `handler`.
+    /// Later rules take precedence.
+    pub fn on<F>(mut self, program: &str, arg: Option<&str>, handler: F) -> Self
+    where
+        F: Fn(&Invocation) -> io::Result<Output> + Send + Sync + 'static,
+    {
+        self.rules.insert(
+            0,
+            Rule {
+                program: program.to_string(),
+                arg: arg.map(str::to_string),
+                handler: Box::new(handler),
+            },
+        );
+        self
+    }
+
+    /// Makes `program` (optionally only with `arg`) exit with `status` and `stderr`.
+    pub fn fail(self, program: &str, arg: Option<&str>, status: i32, stderr: &str) -> Self {
+        let stderr = stderr.to_string();
+        self.on(program, arg, move |_| Ok(failed(status, &stderr)))
+    }
+
+    /// Reports `program` as not installed.
+    pub fn missing(mut self, program: &str) -> Self {
+        self.missing.push(program.to_string());
+        self
+    }
+
+    /// Streams each scripted stdout line `delay` apart, as a slow tool would print them.
+    pub fn stream_lines(mut self, delay: Duration) -> Self {
+        self.line_delay = Some(delay);
+        self
+    }
+
+    pub fn calls(&self) -> Vec<Invocation> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Invocations as `program args...` lines, without the `du` progress polling.
+    pub fn commands(&self) -> Vec<String> {
+        self.calls()
+            .iter()
+            .filter(|c| c.program != "du")
+            .map(|c| c.to_string())
+            .collect()
+    }
+
+    pub fn ran(&self, program: &str) -> bool {
+        self.calls().iter().any(|c| c.program == program)
+    }
+}
+
+impl CommandRunner for FakeRunner {
+    fn run(&self, invocation: &Invocation) -> io::Result<Output> {
+        self.calls.lock().unwrap().push(invocation.clone());
+        match self.rules.iter().find(|r| r.matches(invocation)) {
+            Some(rule) => (rule.handler)(invocation),
+            None => Ok(ok("")),
+        }
+    }
+
+    fn run_with_output(
+        &self,
+        invocation: &Invocation,
+        on_line: &mut dyn FnMut(&str),
+    ) -> io::Result<Output> {
+        let out = self.run(invocation)?;
+        for line in output_lines(&out.stdout) {
+            on_line(line);
+            if let Some(delay) = self.line_delay {
+                thread::sleep(delay);
+            }
+        }
+        Ok(out)
+    }
+
+    fn has_tool(&self, program: &str) -> bool {
+        !self.missing.iter().any(|p| p == program)
+    }
+}
+
+pub fn ok(stdout: &str) -> Output {
+    Output {
+        status: Some(0),
+        stdout: stdout.to_string(),
+        stderr: String::new(),
+    }
+}
+
+pub fn failed(status: i32, stderr: &str) -> Output {
+    Output {
+        status: Some(status),
+        stdout: String::new(),
+        stderr: stderr.to_string(),
+    }
+}
+
+/// Regular files standing in for the target block device and its
+/// partitions, so that `device_exists` holds until a test removes them,
+/// next to a staging directory, a UEFI:NTFS image and optionally an ISO.
+pub struct FakeDrive {
+    dir: TempDir,
+    pub path: PathBuf,
+    iso: PathBuf,
+    staging: PathBuf,
+}
+
+impl FakeDrive {
+    /// `sdz` with two partitions. The ISO is `/isos/win11.iso`, which only
+    /// exists as the runner's `7z l` listing.
+    pub fn new() -> Self {
+        FakeDrive::named("sdz", "")
+    }
+
+    /// A drive whose partitions are named `<name><separator><n>`, like
+    /// `mmcblk0p1`.
+    pub fn named(name: &str, separator: &str) -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        fs::write(&path, b"").unwrap();
+        for number in 1..=2 {
+            fs::write(
+                dir.path().join(format!("{}{}{}", name, separator, number)),
+                b"",
+            )
+            .unwrap();
+        }
+        fs::write(dir.path().join("uefi-ntfs.img"), b"").unwrap();
+        let staging = dir.path().join("staging");
+        fs::create_dir(&staging).unwrap();
+        FakeDrive {
+            path,
+            iso: PathBuf::from("/isos/win11.iso"),
+            staging,
+            dir,
+        }
+    }
+
+    /// Writes `contents` as the ISO to flash, so it can be read natively.
+    pub fn with_iso(mut self, contents: &[u8]) -> Self {
+        self.iso = self.dir.path().join("win11.iso");
+        fs::write(&self.iso, contents).unwrap();
+        self
+    }
+
+    pub fn name(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn iso(&self) -> &Path {
+        &self.iso
+    }
+
+    pub fn staging(&self) -> &Path {
+        &self.staging
+    }
+
+    pub fn uefi_ntfs(&self) -> PathBuf {
+        self.dir.path().join("uefi-ntfs.img")
+    }
+
+    /// Flasher for this drive and ISO, staging exports next to the drive.
+    pub fn flasher(&self) -> Flasher {
+        Flasher::new(self.name(), &self.iso)
+            .staging_dir(&self.staging)
+            .uefi_ntfs_image(self.uefi_ntfs())
+    }
+
+    /// Like [`FakeDrive::flasher`], writing the NTFS layout.
+    pub fn ntfs(&self) -> Flasher {
+        self.flasher().layout(DriveLayout::Ntfs)
+    }
+}
+
+/// Runs `flasher` through `runner` and collects its events. Nothing really
+/// gets mounted, so whatever was extracted into the mount points is removed.
+pub fn flash(
+    flasher: Flasher,
+    runner: &Arc<FakeRunner>,
+) -> (Result<(), FlashError>, Vec<FlashEvent>) {
+    let (tx, rx) = mpsc::channel();
+    let result = flasher.runner(runner.clone()).run(tx);
+    for dir in mount_points(runner) {
+        let _ = fs::remove_dir_all(dir);
+    }
+    (result, rx.iter().collect())
+}
+
+/// Targets of the `mount` calls, in order; the USB mount point comes first.
+pub fn mount_points(runner: &FakeRunner) -> Vec<String> {
+    runner
+        .calls()
+        .into_iter()
+        .filter(|c| c.program == "mount")
+        .filter_map(|c| c.args.last().cloned())
+        .collect()
+}
+
+/// Index of the first command starting with `prefix`.
+pub fn step(commands: &[String], prefix: &str) -> usize {
+    commands
+        .iter()
+        .position(|c| c.starts_with(prefix))
+        .unwrap_or_else(|| panic!("`{}` never ran; got {:#?}", prefix, commands))
+}
@@ -0,0 +1,106 @@
+mod support;
+
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::sync::Arc;
+
+use support::{flash, FakeDrive, FakeRunner};
+use tempfile::TempDir;
+use windusb::unmount::release_drive_with;
+use windusb::{BusyMount, BusyProcess, DiskInspector, FlashError, FlashEvent};
+
+/// `/proc` and `/sys` for a removable `sdz` with two mounted partitions and
+/// a file manager browsing the first one.
+fn system() -> TempDir {
+    let root = tempfile::tempdir().unwrap();
+    let write = |rel: &str, contents: &str| {
+        let path = root.path().join(rel);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    };
+    write("sys/block/sdz/removable", "1\n");
+    write("sys/block/sdz/sdz1/partition", "1\n");
+    write("sys/block/sdz/sdz2/partition", "2\n");
+    write(
+        "proc/self/mountinfo",
+        "22 1 8:1 / / rw shared:1 - ext4 /dev/sda1 rw\n\
+         90 22 8:241 / /media/user/STICK rw shared:2 - vfat /dev/sdz1 rw\n\
+         91 22 8:242 / /media/user/DATA rw shared:3 - exfat /dev/sdz2 rw\n",
+    );
+    write("proc/4242/comm", "nautilus\n");
+    fs::create_dir_all(root.path().join("proc/4242/fd")).unwrap();
+    symlink("/home/user", root.path().join("proc/4242/cwd")).unwrap();
+    symlink(
+        "/media/user/STICK/setup.exe",
+        root.path().join("proc/4242/fd/3"),
+    )
+    .unwrap();
+    root
+}
+
+fn inspector(root: &Path) -> DiskInspector {
+    DiskInspector::with_roots(root.join("proc"), root.join("sys"))
+}
+
+fn busy_stick() -> BusyMount {
+    BusyMount {
+        device: "/dev/sdz1".into(),
+        mountpoint: "/media/user/STICK".into(),
+        processes: vec![BusyProcess {
+            pid: 4242,
+            command: "nautilus".into(),
+        }],
+        reason: "umount exited with status 32: target is busy".into(),
+    }
+}
+
+#[test]
+fn every_partition_gets_a_plain_unmount() {
+    let root = system();
+    let runner = FakeRunner::new();
+
+    let result = release_drive_with(&runner, &inspector(root.path()), "/dev/sdz");
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        runner.commands(),
+        ["umount /media/user/STICK", "umount /media/user/DATA"]
+    );
+}
+
+#[test]
+fn busy_partition_is_reported_with_its_processes() {
+    let root = system();
+    let runner = FakeRunner::new().fail("umount", Some("/media/user/STICK"), 32, "target is busy");
+
+    let result = release_drive_with(&runner, &inspector(root.path()), "/dev/sdz");
+
+    assert_eq!(result, Err(vec![busy_stick()]));
+    assert!(runner.ran("umount"));
+    assert!(!runner.commands().iter().any(|c| c.contains("-l")));
+    assert_eq!(
+        busy_stick().to_string(),
+        "/dev/sdz1 at /media/user/STICK is in use by nautilus (pid 4242)"
+    );
+}
+
+#[test]
+fn flasher_stops_before_wiping_a_busy_drive() {
+    let root = system();
+    let drive = FakeDrive::new();
+    let runner =
+        Arc::new(FakeRunner::new().fail("umount", Some("/media/user/STICK"), 32, "target is busy"));
+
+    let (result, events) = flash(drive.flasher().inspector(inspector(root.path())), &runner);
+
+    assert_eq!(result, Err(FlashError::DriveBusy(vec![busy_stick()])));
+    assert!(matches!(
+        events.last(),
+        Some(FlashEvent::Failed {
+            code: "drive_busy",
+            ..
+        })
+    ));
+    assert!(!runner.ran("wipefs"));
+}
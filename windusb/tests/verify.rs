@@ -0,0 +1,159 @@
+mod support;
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use support::image::{udf, wim, UdfOptions};
+use support::{flash, mount_points, ok, step, FakeDrive, FakeRunner};
+use windusb::{FlashError, FlashEvent, Phase};
+
+const XML: &str = r#"<WIM><IMAGE INDEX="1"><NAME>Windows 11 Pro</NAME></IMAGE></WIM>"#;
+
+/// A drive and an ISO on disk. Nothing gets mounted on the (real) mount
+/// points, so the boot files are extracted into them and read back.
+fn windows_drive() -> FakeDrive {
+    FakeDrive::new().with_iso(&udf(
+        &[
+            ("bootmgr", b"bootmgr"),
+            ("efi/boot/bootx64.efi", b"efi loader"),
+            ("sources/install.wim", &wim(XML)),
+        ],
+        UdfOptions::default(),
+    ))
+}
+
+#[test]
+fn reads_the_drive_back_after_syncing() {
+    let drive = windows_drive();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, events) = flash(drive.flasher().verify(true), &runner);
+    assert_eq!(result, Ok(()));
+
+    let commands = runner.commands();
+    let usb_mt = &mount_points(&runner)[0];
+    let part = format!("{}1", drive.name());
+    let sync = step(&commands, "sync");
+    let flush = step(&commands[sync..], &format!("blockdev --flushbufs {}", part)) + sync;
+    let remount = step(&commands, &format!("mount -o ro {} {}", part, usb_mt));
+    let verify = step(&commands, "wimlib-imagex verify");
+    assert!(flush < remount && remount < verify);
+    assert_eq!(
+        commands[verify],
+        format!(
+            "wimlib-imagex verify {0}/sources/install.swm --ref={0}/sources/install*.swm",
+            usb_mt
+        )
+    );
+    assert_eq!(commands.last().unwrap(), &format!("umount {}", usb_mt));
+
+    let verified: Vec<_> = events
+        .iter()
+        .filter_map(|e| match e {
+            FlashEvent::Progress(p) if p.phase == Phase::Verifying => Some(p),
+            _ => None,
+        })
+        .collect();
+    assert!(verified
+        .iter()
+        .any(|p| p.bytes_done == Some(17) && p.bytes_total == Some(17)));
+    assert_eq!(events.last(), Some(&FlashEvent::Finished));
+}
+
+#[test]
+fn reports_files_that_differ_from_the_iso() {
+    let drive = windows_drive();
+    let runner = Arc::new(FakeRunner::new().on("mount", Some("ro"), |inv| {
+        let root = Path::new(inv.args.last().unwrap());
+        fs::write(root.join("bootmgr"), b"BOOTMGR")?;
+        fs::remove_file(root.join("efi/boot/bootx64.efi"))?;
+        Ok(ok(""))
+    }));
+
+    let (result, events) = flash(drive.flasher().verify(true), &runner);
+
+    let Err(FlashError::VerifyMismatch(files)) = result else {
+        panic!("unexpected result: {:?}", result);
+    };
+    assert_eq!(files.len(), 2);
+    assert!(files.contains(&"bootmgr has different contents".to_string()));
+    assert!(files.contains(&"efi/boot/bootx64.efi is missing".to_string()));
+    assert!(!runner
+        .commands()
+        .iter()
+        .any(|c| c.starts_with("wimlib-imagex verify")));
+    assert!(matches!(
+        events.last(),
+        Some(FlashEvent::Failed {
+            code: "verify_mismatch",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn damaged_install_image_fails_but_still_unmounts() {
+    let drive = windows_drive();
+    let runner = Arc::new(FakeRunner::new().fail(
+        "wimlib-imagex",
+        Some("verify"),
+        1,
+        "Integrity check failed",
+    ));
+
+    let (result, _) = flash(drive.flasher().verify(true), &runner);
+
+    assert!(
+        matches!(result, Err(FlashError::VerifyImage(ref e)) if e.stderr == "Integrity check failed")
+    );
+    assert!(runner
+        .commands()
+        .last()
+        .unwrap()
+        .starts_with("umount /tmp/windusb_usb_"));
+}
+
+#[test]
+fn busy_drive_is_not_unmounted_lazily() {
+    let drive = windows_drive();
+    let runner = Arc::new(FakeRunner::new().fail("umount", None, 32, "target is busy"));
+
+    let (result, _) = flash(drive.flasher().verify(true), &runner);
+
+    assert!(matches!(result, Err(FlashError::Unmount(ref e)) if e.stderr == "target is busy"));
+    let commands = runner.commands();
+    let usb_mt = &mount_points(&runner)[0];
+    let umount = step(&commands, &format!("umount {}", usb_mt));
+    assert!(!commands[..umount]
+        .iter()
+        .any(|c| c.starts_with("umount -l")));
+    assert!(!commands.iter().any(|c| c.starts_with("mount -o ro")));
+}
+
+#[test]
+fn skipped_unless_requested() {
+    let drive = windows_drive();
+    let runner = Arc::new(FakeRunner::new());
+
+    let (result, _) = flash(drive.flasher(), &runner);
+
+    assert_eq!(result, Ok(()));
+    let commands = runner.commands();
+    assert!(!commands.iter().any(|c| c.starts_with("mount -o ro")));
+    assert!(!commands
+        .iter()
+        .any(|c| c.starts_with("wimlib-imagex verify")));
+}
+
+#[test]
+fn mismatch_message_lists_the_first_files() {
+    let files: Vec<String> = (1..=7).map(|n| format!("file{} is missing", n)).collect();
+
+    let message = FlashError::VerifyMismatch(files).to_string();
+
+    assert!(message.starts_with("7 file(s) on the drive do not match the ISO: file1 is missing;"));
+    assert!(message.contains("file5 is missing"));
+    assert!(!message.contains("file6"));
+    assert!(message.ends_with("and 2 more"));
+}
@@ -0,0 +1,95 @@
+mod support;
+
+use std::fs;
+use std::io::Cursor;
+
+use support::image::{udf, wim, UdfOptions};
+use windusb::wim::{parse_editions, read_editions, read_header};
+use windusb::{Architecture, WindowsVersion};
+
+/// Trimmed XML metadata of a Windows 11 23H2 multi-edition `install.wim`.
+const XML: &str = r#"<WIM><TOTALBYTES>6231945872</TOTALBYTES><IMAGE INDEX="1"><DIRCOUNT>24512</DIRCOUNT><FILECOUNT>109347</FILECOUNT><TOTALBYTES>17548468331</TOTALBYTES><WINDOWS><ARCH>9</ARCH><PRODUCTNAME>Microsoft® Windows® Operating System</PRODUCTNAME><EDITIONID>Core</EDITIONID><INSTALLATIONTYPE>Client</INSTALLATIONTYPE><PRODUCTTYPE>WinNT</PRODUCTTYPE><LANGUAGES><LANGUAGE>en-US</LANGUAGE><DEFAULT>en-US</DEFAULT></LANGUAGES><VERSION><MAJOR>10</MAJOR><MINOR>0</MINOR><BUILD>22631</BUILD><SPBUILD>2428</SPBUILD><SPLEVEL>0</SPLEVEL></VERSION><SYSTEMROOT>WINDOWS</SYSTEMROOT></WINDOWS><NAME>Windows 11 Home</NAME><DESCRIPTION>Windows 11 Home</DESCRIPTION><FLAGS>Core</FLAGS><DISPLAYNAME>Windows 11 Home</DISPLAYNAME><DISPLAYDESCRIPTION>Windows 11 Home</DISPLAYDESCRIPTION></IMAGE>
+<IMAGE INDEX="6"><TOTALBYTES>17601320121</TOTALBYTES><WINDOWS><ARCH>9</ARCH><EDITIONID>Professional</EDITIONID><LANGUAGES><LANGUAGE>en-US</LANGUAGE></LANGUAGES><VERSION><MAJOR>10</MAJOR><MINOR>0</MINOR><BUILD>22631</BUILD><SPBUILD>2428</SPBUILD></VERSION></WINDOWS><NAME>Windows 11 Pro</NAME><DISPLAYNAME>Windows 11 Pro</DISPLAYNAME></IMAGE>
+<IMAGE INDEX="2"><TOTALBYTES>0x10000</TOTALBYTES><WINDOWS><ARCH>12</ARCH></WINDOWS><NAME>Windows 11 Home N</NAME></IMAGE></WIM>"#;
+
+#[test]
+fn parses_edition_metadata() {
+    let editions = parse_editions(XML).unwrap();
+
+    assert_eq!(
+        editions.iter().map(|e| e.index).collect::<Vec<_>>(),
+        [1, 2, 6]
+    );
+    let home = &editions[0];
+    assert_eq!(home.name, "Windows 11 Home");
+    assert_eq!(home.edition_id.as_deref(), Some("Core"));
+    assert_eq!(home.languages, ["en-US"]);
+    assert_eq!(home.architecture, Some(Architecture::X64));
+    assert_eq!(home.size, 17_548_468_331);
+    assert_eq!(
+        home.version,
+        Some(WindowsVersion {
+            major: 10,
+            minor: 0,
+            build: 22631,
+            sp_build: 2428
+        })
+    );
+    assert_eq!(home.summary(), "build 22631 · en-US · x64 · 16.3G");
+}
+
+#[test]
+fn tolerates_sparse_image_entries() {
+    let editions = parse_editions(XML).unwrap();
+    let home_n = &editions[1];
+
+    assert_eq!(home_n.name, "Windows 11 Home N");
+    assert_eq!(home_n.version, None);
+    assert!(home_n.languages.is_empty());
+    assert_eq!(home_n.architecture, Some(Architecture::Arm64));
+    assert_eq!(home_n.size, 0x10000);
+}
+
+#[test]
+fn reads_xml_resource_from_wim_header() {
+    let data = wim(XML);
+    let header = read_header(&mut Cursor::new(&data)).unwrap();
+
+    assert_eq!(header.image_count, 3);
+    assert_eq!((header.part_number, header.total_parts), (1, 1));
+    assert_eq!(read_editions(&mut Cursor::new(&data)).unwrap().len(), 3);
+}
+
+#[test]
+fn rejects_files_without_wim_magic() {
+    let mut data = wim(XML);
+    data[0] = b'X';
+
+    assert!(read_editions(&mut Cursor::new(&data)).is_err());
+}
+
+#[test]
+fn lists_editions_straight_from_the_iso() {
+    let dir = tempfile::tempdir().unwrap();
+    let iso = dir.path().join("win11.iso");
+    let install = wim(XML);
+    fs::write(
+        &iso,
+        udf(
+            &[("setup.exe", b"MZ"), ("sources/install.wim", &install)],
+            UdfOptions::default(),
+        ),
+    )
+    .unwrap();
+
+    let editions = windusb::list_editions(&iso).unwrap();
+    let names: Vec<&str> = editions.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(
+        names,
+        ["Windows 11 Home", "Windows 11 Home N", "Windows 11 Pro"]
+    );
+    assert_eq!(
+        windusb::list_editions(&dir.path().join("missing.iso")),
+        None
+    );
+}
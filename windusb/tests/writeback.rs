@@ -0,0 +1,86 @@
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use windusb::writeback::{parse_bdi_pending, parse_sectors_written};
+use windusb::DeviceWriteback;
+
+const STAT: &str =
+    "    1204        0   96340     512     3312      120  6291456    90211        0    41200    90723\n";
+
+const BDI_STATS: &str = "\
+BdiWriteback:            8192 kB
+BdiReclaimable:        516096 kB
+BdiDirtyThresh:        720896 kB
+DirtyThresh:          2883584 kB
+BackgroundThresh:     1441792 kB
+BdiDirtied:           3145728 kB
+BdiWritten:           2621440 kB
+BdiWriteBandwidth:      30720 kBps
+b_dirty:                    2
+";
+
+/// Lays out `/dev/sdz`, its sysfs entry and, optionally, its debugfs bdi stats.
+fn fake_system(root: &Path, with_debugfs: bool) {
+    fs::create_dir_all(root.join("dev/disk/by-id")).unwrap();
+    fs::write(root.join("dev/sdz"), b"").unwrap();
+    symlink("../../sdz", root.join("dev/disk/by-id/usb-Stick")).unwrap();
+    fs::create_dir_all(root.join("sys/block/sdz")).unwrap();
+    fs::write(root.join("sys/block/sdz/stat"), STAT).unwrap();
+    fs::write(root.join("sys/block/sdz/dev"), "8:208\n").unwrap();
+    if with_debugfs {
+        fs::create_dir_all(root.join("debug/bdi/8:208")).unwrap();
+        fs::write(root.join("debug/bdi/8:208/stats"), BDI_STATS).unwrap();
+    }
+}
+
+fn writeback(root: &Path, device: &str) -> Option<DeviceWriteback> {
+    DeviceWriteback::with_roots(
+        &root.join(device).to_string_lossy(),
+        &root.join("sys"),
+        &root.join("debug"),
+    )
+}
+
+#[test]
+fn parses_sectors_written() {
+    assert_eq!(parse_sectors_written(STAT), Some(6291456));
+    assert_eq!(parse_sectors_written("1 2 3"), None);
+}
+
+#[test]
+fn pending_is_reclaimable_plus_writeback() {
+    assert_eq!(parse_bdi_pending(BDI_STATS), Some((516096 + 8192) * 1024));
+    assert_eq!(parse_bdi_pending("b_dirty: 2\n"), None);
+}
+
+#[test]
+fn reads_counters_of_the_device_behind_a_symlink() {
+    let dir = tempfile::tempdir().unwrap();
+    fake_system(dir.path(), true);
+
+    let device = writeback(dir.path(), "dev/disk/by-id/usb-Stick").unwrap();
+
+    assert_eq!(device.bytes_written(), Some(6291456 * 512));
+    assert_eq!(device.pending_bytes(), Some((516096 + 8192) * 1024));
+}
+
+#[test]
+fn pending_is_unknown_without_debugfs() {
+    let dir = tempfile::tempdir().unwrap();
+    fake_system(dir.path(), false);
+
+    let device = writeback(dir.path(), "dev/sdz").unwrap();
+
+    assert!(device.bytes_written().is_some());
+    assert_eq!(device.pending_bytes(), None);
+}
+
+#[test]
+fn non_disks_are_not_tracked() {
+    let dir = tempfile::tempdir().unwrap();
+    fake_system(dir.path(), true);
+    fs::write(dir.path().join("dev/image.img"), b"").unwrap();
+
+    assert_eq!(writeback(dir.path(), "dev/image.img"), None);
+}